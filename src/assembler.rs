@@ -1,134 +1,501 @@
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
+
 use parsable::{Parsable, format_error_stack};
 
 use crate::{
-    assembler::{labels::{Label, LabelLookup}, parse::{LabelSegment, SourceFile, StatementLineContent, StatementSegment, instruction::{DataStatement, Statement}}},
-    instruction::{Address, Data16, InstructionOrData},
+    assembler::{
+        labels::{Label, LabelLookup},
+        listing::{Listing, ListingLine},
+        macros::{Expansion, InputFile, LineAssembler},
+        parse::{
+            DefinitionKind, IfLine, LabelSegment, SourceLine, StatementLine, StatementLineContent,
+            StatementSegment, SymbolDefinition,
+            expression::{Environment, ExpressionError},
+            instruction::{DataStatement, Statement},
+        },
+    },
+    coding,
+    instruction::{Address, Cpu, Data16, InstructionOrData},
 };
 
 mod labels;
+pub mod listing;
+mod macros;
 mod parse;
 
 pub type AssemblySource<'a> = &'a [u8];
 
-pub fn parse_assembly(
-    source: AssemblySource,
-) -> Result<(Vec<InstructionOrData>, u16), String> {
-    let mut stream = parsable::ScopedStream::new(source);
-    let outcome = parsable::WithEnd::<SourceFile>::parse(&mut stream);
-    let source_file = match outcome.expect("parsing should give a result") {
-        Ok(parsed) => parsed.node,
-        Err(stack) => return Err(format_error_stack(source, stack)),
-    };
-    
-    let origin_address: Address = if let Some(origin_line) = &source_file.origin_line {
-        origin_line.address.node.clone().try_into()
-            .map_err(|_| format!("{}: Expected address", origin_line.address.index))?
-    } else {
-        0x0000_0000
-    };
-
-    let mut labels = LabelLookup::new();
-    let mut add_label = |source_pos: usize, label: Label, address: u16| {
-        // this is kind of inefficient but i couldn't find a better way to do it
-        labels.insert(label.clone(), address).map_err(|_|
-            format!("{}: Duplicate label {}", source_pos, String::from_utf8_lossy(&label.span)))
-    };
-    let mut add_label_segment_opt = |label_segment: Option<&LabelSegment>, address: u16| {
-        if let Some(label_segment) = label_segment {
-            add_label(label_segment.0.index, label_segment.0.node.clone(), address)
-        } else {
-            Ok(())
-        }
-    };
-
-    let mut current_address = origin_address;
-
-    add_label_segment_opt(
-        source_file.origin_line.as_ref().map(|origin_line| origin_line.label.as_ref()).flatten(),
-        current_address,
-    )?;
+/// A position in the assembly source, or in a file it included.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct SourcePosition {
+    /// The file the position is in. `None` for the source passed to [`assemble`] or
+    /// [`parse_assembly`], which wasn't read from a file.
+    pub file: Option<PathBuf>,
+    /// Offset in bytes from the start of the file.
+    pub offset: usize,
+    /// Line number, starting at 1.
+    pub line: usize,
+    /// Column in bytes, starting at 1.
+    pub column: usize,
+}
 
-    fn get_label(content: &StatementLineContent) -> Option<&LabelSegment> {
-        match &content {
-            StatementLineContent::Labeled(label_segment, ..) => Some(label_segment),
-            _ => None,
+impl SourcePosition {
+    fn new(file: Option<PathBuf>, source: AssemblySource, offset: usize) -> Self {
+        let before = &source[..offset.min(source.len())];
+        let line_start = before
+            .iter()
+            .rposition(|&byte| byte == b'\n')
+            .map_or(0, |index| index + 1);
+        Self {
+            file,
+            offset,
+            line: before.iter().filter(|&&byte| byte == b'\n').count() + 1,
+            column: offset - line_start + 1,
         }
     }
+}
 
-    fn get_code(content: &StatementLineContent) -> Option<&StatementSegment> {
-        match &content {
-            StatementLineContent::Labeled(_, code_segment, ..) => code_segment.as_ref(),
-            StatementLineContent::NoLabel(code_segment, ..) => Some(code_segment),
-            _ => None,
+impl Display for SourcePosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file.display())?;
         }
+        write!(f, "{}:{}", self.line, self.column)
     }
+}
 
-    fn get_code_owned(content: StatementLineContent) -> Option<StatementSegment> {
-        match content {
-            StatementLineContent::Labeled(_, code_segment, ..) => code_segment,
-            StatementLineContent::NoLabel(code_segment, ..) => Some(code_segment),
-            _ => None,
+/// An error found while assembling a program.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct AssemblyError {
+    /// Where the error is, `None` for syntax errors, whose message describes where parsing failed.
+    /// For errors in lines expanded from a macro, this is where the macro was called.
+    pub position: Option<SourcePosition>,
+    /// For errors in lines expanded from a macro, the line in the macro definition.
+    pub macro_position: Option<SourcePosition>,
+    pub message: String,
+}
+
+impl AssemblyError {
+    fn at(file: &InputFile, offset: usize, message: String) -> Self {
+        Self {
+            position: Some(file.position(offset)),
+            macro_position: None,
+            message,
         }
     }
-    
-    for code_line in &source_file.lines.nodes {
-        add_label_segment_opt(get_label(&code_line.content), current_address)?;
-        if let Some(code) = get_code(&code_line.content) {
-            let statement = &code.statement;
-            match &statement.node {
-                Statement::DataStatement(data_statement) => {
-                    let length = data_statement.byte_length().ok_or(
-                        format!("{}: Invalid number", statement.index))?;
-                    current_address = current_address.checked_add(length)
-                        .ok_or(format!("{}: Memory size overflowed", statement.index))?;
-                },
-                Statement::Instruction(instruction) => {
-                    current_address = current_address.checked_add(instruction.instruction_length())
-                        .ok_or(format!("{}: Memory size overflowed", statement.index))?;
-                },
-            }
+}
+
+impl Display for AssemblyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.position {
+            Some(position) => write!(f, "{}: {}", position, self.message)?,
+            None => write!(f, "{}", self.message)?,
+        }
+        if let Some(position) = &self.macro_position {
+            write!(f, " (in macro at {})", position)?;
         }
+        Ok(())
+    }
+}
+
+impl std::error::Error for AssemblyError {}
+
+/// A program assembled to machine code.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Program {
+    /// The address the program is assembled to be loaded at, set with `ORG`.
+    pub origin: Address,
+    pub bytes: Vec<u8>,
+    pub listing: Listing,
+}
+
+/// Assembles `source` for `cpu` into machine code. Files included with `INCLUDE` are relative to
+/// the current directory.
+pub fn assemble(source: AssemblySource, cpu: Cpu) -> Result<Program, AssemblyError> {
+    let (items, origin, listing) = assemble_source(source, None, cpu)?;
+    Ok(encode(items, origin, listing))
+}
+
+/// Assembles the file at `path` for `cpu` into machine code. Files included with `INCLUDE` are
+/// relative to the file including them.
+pub fn assemble_file(path: &Path, cpu: Cpu) -> Result<Program, AssemblyError> {
+    let source = fs::read(path).map_err(|err| AssemblyError {
+        position: None,
+        macro_position: None,
+        message: format!("Couldn't read {}: {}", path.display(), err),
+    })?;
+    let (items, origin, listing) = assemble_source(&source, Some(path), cpu)?;
+    Ok(encode(items, origin, listing))
+}
+
+fn encode(items: Vec<InstructionOrData>, origin: Address, listing: Listing) -> Program {
+    let mut bytes = Vec::new();
+    coding::encode_program(&mut bytes, &items).expect("writing to Vec can't error");
+    Program { origin, bytes, listing }
+}
+
+/// Assembles `source` for `cpu`, returning the instructions and data in the order they appear,
+/// and the origin address. Instructions which `cpu` doesn't have are reported as errors.
+pub fn parse_assembly(
+    source: AssemblySource,
+    cpu: Cpu,
+) -> Result<(Vec<InstructionOrData>, u16), AssemblyError> {
+    let (items, origin, _) = assemble_source(source, None, cpu)?;
+    Ok((items, origin))
+}
+
+fn assemble_source(
+    source: AssemblySource,
+    path: Option<&Path>,
+    cpu: Cpu,
+) -> Result<(Vec<InstructionOrData>, u16, Listing), AssemblyError> {
+    let mut first_pass = FirstPass::new();
+    let expansion = macros::expand(source, path, &mut |path| fs::read(path), &mut first_pass)?;
+    if !first_pass.ended {
+        return Err(AssemblyError {
+            position: None,
+            macro_position: None,
+            message: String::from("Missing END"),
+        });
     }
+    let FirstPass { mut labels, origin, lines: parsed_lines, forward_equates, .. } = first_pass;
+    let origin_address = origin.unwrap_or(0x0000);
+    define_forward_equates(&mut labels, &expansion, &parsed_lines, forward_equates)?;
+    // `SET` symbols are defined again as the lines are reached, so using one before its first
+    // `SET` is an error here too.
+    labels.remove_set_symbols();
 
     let mut instructions = Vec::new();
-    for code_line in source_file.lines.nodes {
-        if let Some(code) = get_code_owned(code_line.content) {
+    let mut current_address = origin_address;
+    // The address and range of `instructions` assembled from each line of the expansion, and
+    // whether the line is a `DS`, whose bytes aren't listed.
+    let mut assembled_lines = vec![None; expansion.lines.len()];
+    for ParsedLine { line, column, content, .. } in parsed_lines {
+        let error = |offset: usize, message: &str| expansion.error(line, column + offset, message.to_string());
+        // Operands see the value a `SET` symbol has at that point in the program, so they're
+        // redefined again as this pass reaches them.
+        if let Some(definition) = get_definition(&content)
+            && let DefinitionKind::Set(_) = definition.kind
+        {
+            define(&mut labels, definition, current_address, &error)?;
+        }
+        if let Some(code) = get_code_owned(content) {
             let statement = code.statement;
+            let environment = Environment { symbols: &labels, address: current_address };
+            let expression_error = |e: ExpressionError| error(statement.index, &e.to_string());
+            let address = current_address;
+            let first_item = instructions.len();
+            let is_storage = matches!(statement.node, Statement::DataStatement(DataStatement::DefineStorage(..)));
+            // The first pass already checked that this doesn't overflow.
+            current_address += match &statement.node {
+                Statement::DataStatement(data_statement) => data_statement.byte_length(&environment)
+                    .map_err(expression_error)?,
+                Statement::Instruction(instruction) => instruction.instruction_length(),
+            };
             match statement.node {
                 Statement::DataStatement(data_statement) => match data_statement {
                     DataStatement::DefineByte(_, _, literal) => {
-                        instructions.push(InstructionOrData::Slice(literal.get()
-                            .ok_or(format!("{}: Invalid number", statement.index))?));
+                        instructions.push(InstructionOrData::Slice(literal.get(&environment)
+                            .map_err(expression_error)?));
                     },
                     DataStatement::DefineWord(_, _, data) => {
-                        let data = data.get(&labels)
-                            .ok_or(format!("{}: Unknown label", statement.index))?;
+                        let data = data.evaluate(&environment)
+                            .map_err(expression_error)?;
                             
                         let data = Data16::from(data);
                         instructions.push(InstructionOrData::Byte(data.low));
                         instructions.push(InstructionOrData::Byte(data.high));
                     },
-                    DataStatement::DefineStorage(_, _, literal_number) => {
-                        let length: u16 = literal_number.try_into()
-                            .map_err(|_| format!("{}: Invalid number", statement.index))?;
+                    DataStatement::DefineStorage(_, _, length) => {
+                        let length = length.evaluate(&environment)
+                            .map_err(expression_error)?;
                         instructions.push(InstructionOrData::Slice(
                             vec![0; length as usize].into_boxed_slice()));
                     },
                 },
                 Statement::Instruction(instruction) => {
-                    let instruction = instruction.into_inner(&labels)
-                        .ok_or(format!("{}: Unknown label", statement.index))?;
+                    let instruction = instruction.into_inner(&environment)
+                        .map_err(expression_error)?;
+                    if !cpu.supports(instruction) {
+                        return Err(error(statement.index, &format!("{} isn't available on the {}", instruction, cpu)));
+                    }
                     instructions.push(InstructionOrData::Instruction(instruction));
                 },
             }
+            assembled_lines[line] = Some((address, first_item..instructions.len(), is_storage));
         }
     }
-    Ok((instructions, origin_address))
+
+    let lines = expansion.lines.iter().zip(assembled_lines).map(|(line, assembled)| {
+        let mut bytes = Vec::new();
+        if let Some((_, items, false)) = &assembled {
+            coding::encode_program(&mut bytes, &instructions[items.clone()])
+                .expect("writing to Vec can't error");
+        }
+        ListingLine {
+            file: line.origin.location.file,
+            line: line.origin.line,
+            expanded: line.origin.macro_location.is_some(),
+            address: assembled.map(|(address, ..)| address),
+            bytes,
+            text: String::from_utf8_lossy(&line.text).into_owned(),
+        }
+    });
+    let files = expansion.files.iter().map(|file| file.path.clone()).collect();
+    let listing = Listing { files, lines: lines.collect() };
+    Ok((instructions, origin_address, listing))
+}
+
+// Defines the `EQU` symbols whose values refer to labels or symbols further down, which the
+// first pass left undefined. Every round goes over the whole program again, so that `SET` symbols
+// have the values they have at the `EQU`, and `EQU`s referring to each other are defined in as
+// many rounds as it takes.
+fn define_forward_equates(
+    labels: &mut LabelLookup,
+    expansion: &Expansion,
+    parsed_lines: &[ParsedLine],
+    mut forward_equates: Vec<usize>,
+) -> Result<(), AssemblyError> {
+    while !forward_equates.is_empty() {
+        labels.remove_set_symbols();
+        let mut remaining = Vec::new();
+        let mut unknown = None;
+        let mut pending = forward_equates.iter().peekable();
+        for (index, parsed) in parsed_lines.iter().enumerate() {
+            let Some(definition) = get_definition(&parsed.content) else {
+                continue;
+            };
+            let error = |offset: usize, message: &str| {
+                expansion.error(parsed.line, parsed.column + offset, message.to_string())
+            };
+            if let DefinitionKind::Set(_) = definition.kind {
+                define(labels, definition, parsed.address, &error)?;
+            } else if pending.next_if_eq(&&index).is_some() {
+                let environment = Environment { symbols: labels, address: parsed.address };
+                match definition.value.node.evaluate(&environment) {
+                    Err(e @ ExpressionError::UnknownLabel) => {
+                        unknown.get_or_insert_with(|| error(definition.value.index, &e.to_string()));
+                        remaining.push(index);
+                    },
+                    _ => define(labels, definition, parsed.address, &error)?,
+                }
+            }
+        }
+        if let Some(error) = unknown && remaining.len() == forward_equates.len() {
+            return Err(error);
+        }
+        forward_equates = remaining;
+    }
+    Ok(())
+}
+
+// A line with code or a definition, which the second pass assembles.
+struct ParsedLine {
+    // Index of the line in the expansion.
+    line: usize,
+    // Column of `content` in the line, which offsets in `content` are relative to.
+    column: usize,
+    // Address of the line, which `$` refers to.
+    address: Address,
+    content: StatementLineContent,
+}
+
+// The first pass, which runs as the source is expanded. It defines the labels and symbols with
+// the addresses of the lines, and collects the lines for the second pass.
+struct FirstPass {
+    labels: LabelLookup,
+    // Set by `ORG`, which has to come before the lines with code or definitions.
+    origin: Option<Address>,
+    started: bool,
+    ended: bool,
+    current_address: Address,
+    lines: Vec<ParsedLine>,
+    // Indices in `lines` of the `EQU`s which refer to labels or symbols further down, and are
+    // defined once the first pass is done.
+    forward_equates: Vec<usize>,
+}
+
+impl FirstPass {
+    fn new() -> Self {
+        Self {
+            labels: LabelLookup::new(),
+            origin: None,
+            started: false,
+            ended: false,
+            current_address: 0,
+            lines: Vec::new(),
+            forward_equates: Vec::new(),
+        }
+    }
+}
+
+impl LineAssembler for FirstPass {
+    fn assemble_line(&mut self, expansion: &Expansion, line: usize) -> Result<(), AssemblyError> {
+        let Some((column, source_line)) = parse_line::<SourceLine>(expansion, line)? else {
+            return Ok(());
+        };
+        let error = |offset: usize, message: &str| expansion.error(line, column + offset, message.to_string());
+        let content = match source_line {
+            SourceLine::Statement(StatementLine { content: StatementLineContent::OnlyComment(_), .. }) => {
+                return Ok(());
+            },
+            _ if self.ended => return Err(error(0, "Only comments may follow END")),
+            SourceLine::End(end) => {
+                add_label_segment_opt(&mut self.labels, end.label.as_ref(), self.current_address, &error)?;
+                self.ended = true;
+                return Ok(());
+            },
+            SourceLine::Origin(origin_line) => {
+                if self.started || self.origin.is_some() {
+                    return Err(error(0, "ORG must come before the rest of the program"));
+                }
+                // Nothing can be defined before `ORG`, so there are no symbols to refer to.
+                let environment = Environment { symbols: &self.labels, address: 0 };
+                let address = origin_line.address.node.evaluate(&environment)
+                    .map_err(|e| error(origin_line.address.index, &e.to_string()))?;
+                self.origin = Some(address);
+                self.current_address = address;
+                add_label_segment_opt(&mut self.labels, origin_line.label.as_ref(), address, &error)?;
+                return Ok(());
+            },
+            SourceLine::Statement(statement_line) => statement_line.content,
+        };
+        self.started = true;
+        let address = self.current_address;
+
+        if let Some(definition) = get_definition(&content) {
+            let environment = Environment { symbols: &self.labels, address: self.current_address };
+            if let DefinitionKind::Equ(_) = definition.kind
+                && let Err(ExpressionError::UnknownLabel) = definition.value.node.evaluate(&environment)
+            {
+                self.forward_equates.push(self.lines.len());
+            } else {
+                define(&mut self.labels, definition, self.current_address, &error)?;
+            }
+        }
+        add_label_segment_opt(&mut self.labels, get_label(&content), self.current_address, &error)?;
+        if let Some(code) = get_code(&content) {
+            let statement = &code.statement;
+            let length = match &statement.node {
+                Statement::DataStatement(data_statement) => {
+                    let environment = Environment { symbols: &self.labels, address: self.current_address };
+                    data_statement.byte_length(&environment)
+                        .map_err(|e| error(statement.index, &e.to_string()))?
+                },
+                Statement::Instruction(instruction) => instruction.instruction_length(),
+            };
+            self.current_address = self.current_address.checked_add(length)
+                .ok_or(error(statement.index, "Memory size overflowed"))?;
+        }
+        self.lines.push(ParsedLine { line, column, address, content });
+        Ok(())
+    }
+
+    fn evaluate_condition(&mut self, expansion: &Expansion, line: usize) -> Result<bool, AssemblyError> {
+        let (column, if_line) = parse_line::<IfLine>(expansion, line)?.expect("IF lines aren't blank");
+        let error = |offset: usize, message: &str| expansion.error(line, column + offset, message.to_string());
+        if self.ended {
+            return Err(error(0, "Only comments may follow END"));
+        }
+        let condition = &if_line.condition;
+        let environment = Environment { symbols: &self.labels, address: self.current_address };
+        let value = condition.node.evaluate(&environment)
+            .map_err(|e| error(condition.index, &e.to_string()))?;
+        Ok(value != 0)
+    }
+}
+
+// Parses the code of line `line` of the expansion, without its indentation. Returns the column
+// the parsed code starts at, or `None` for blank lines.
+fn parse_line<T>(expansion: &Expansion, line: usize) -> Result<Option<(usize, T)>, AssemblyError>
+where
+    T: for<'a> Parsable<'a>,
+{
+    let code = &expansion.lines[line].code;
+    let column = macros::indentation(code);
+    let content = &code[column..];
+    if content.trim_ascii().is_empty() {
+        return Ok(None);
+    }
+    match parsable::WithEnd::<T>::parse_or_error(&mut parsable::ScopedStream::new(content)) {
+        Ok(parsed) => Ok(Some((column, parsed.node))),
+        Err(stack) => Err(expansion.error(line, column, format_error_stack(content, stack))),
+    }
+}
+
+fn duplicate(label: &Label) -> String {
+    format!("Duplicate label {}", String::from_utf8_lossy(&label.span))
+}
+
+fn add_label_segment_opt(
+    labels: &mut LabelLookup,
+    label_segment: Option<&LabelSegment>,
+    address: Address,
+    error: &impl Fn(usize, &str) -> AssemblyError,
+) -> Result<(), AssemblyError> {
+    if let Some(label_segment) = label_segment {
+        let label = &label_segment.0.node;
+        // this is kind of inefficient but i couldn't find a better way to do it
+        labels.insert(label.clone(), address)
+            .map_err(|_| error(label_segment.0.index, &duplicate(label)))?;
+    }
+    Ok(())
+}
+
+// The value is evaluated with the symbols defined so far.
+fn define(
+    labels: &mut LabelLookup,
+    definition: &SymbolDefinition,
+    address: Address,
+    error: &impl Fn(usize, &str) -> AssemblyError,
+) -> Result<(), AssemblyError> {
+    let environment = Environment { symbols: labels, address };
+    let value = definition.value.node.evaluate(&environment)
+        .map_err(|e| error(definition.value.index, &e.to_string()))?;
+    let name = definition.name.node.clone();
+    match definition.kind {
+        DefinitionKind::Equ(_) => labels.insert(name.clone(), value),
+        DefinitionKind::Set(_) => labels.set(name.clone(), value),
+    }.map_err(|_| error(definition.name.index, &duplicate(&name)))
+}
+
+fn get_definition(content: &StatementLineContent) -> Option<&SymbolDefinition> {
+    match &content {
+        StatementLineContent::Definition(definition, ..) => Some(definition),
+        _ => None,
+    }
+}
+
+fn get_label(content: &StatementLineContent) -> Option<&LabelSegment> {
+    match &content {
+        StatementLineContent::Labeled(label_segment, ..) => Some(label_segment),
+        _ => None,
+    }
+}
+
+fn get_code(content: &StatementLineContent) -> Option<&StatementSegment> {
+    match &content {
+        StatementLineContent::Labeled(_, code_segment, ..) => code_segment.as_ref(),
+        StatementLineContent::NoLabel(code_segment, ..) => Some(code_segment),
+        _ => None,
+    }
+}
+
+fn get_code_owned(content: StatementLineContent) -> Option<StatementSegment> {
+    match content {
+        StatementLineContent::Labeled(_, code_segment, ..) => code_segment,
+        StatementLineContent::NoLabel(code_segment, ..) => Some(code_segment),
+        _ => None,
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::instruction::{Instruction, Register};
+    use crate::instruction::{Instruction, Register, RegisterPair};
 
     use super::*;
 
@@ -149,7 +516,7 @@ mod tests {
                 END
         ";
 
-        let (instructions, start) = parse_assembly(source).expect("Failed to parse program");
+        let (instructions, start) = parse_assembly(source, Cpu::Intel8080).expect("Failed to parse program");
         assert_eq!(instructions, vec![
             InstructionOrData::Instruction(Instruction::Mov(Register::A, Register::B)),
             InstructionOrData::Instruction(Instruction::Jmp(20)),
@@ -157,4 +524,311 @@ mod tests {
         ]);
         assert_eq!(start, 16);
     }
+
+    #[test]
+    fn parse_8085() {
+        let source = b"
+                RIM
+                LDHI 10H
+                RSTV
+                JK 0
+                END
+        ";
+
+        let (instructions, _) = parse_assembly(source, Cpu::Intel8085).expect("Failed to parse program");
+        assert_eq!(instructions, vec![
+            InstructionOrData::Instruction(Instruction::Rim),
+            InstructionOrData::Instruction(Instruction::Ldhi(0x10)),
+            InstructionOrData::Instruction(Instruction::Rstv),
+            InstructionOrData::Instruction(Instruction::Jk(0)),
+        ]);
+
+        assert!(parse_assembly(source, Cpu::Intel8080).is_err());
+    }
+
+    #[test]
+    fn parse_symbols() {
+        let source = b"
+        PORT    EQU 10H
+        COUNT   SET 2
+        SIZE    EQU COUNT
+                MVI A, COUNT
+                OUT PORT
+        COUNT   SET 3
+                MVI B, COUNT
+                JMP PORT
+        BUF:    DS SIZE
+                LXI H, BUF
+                END
+        ";
+
+        let (instructions, _) = parse_assembly(source, Cpu::Intel8080).expect("Failed to parse program");
+        assert_eq!(instructions, vec![
+            InstructionOrData::Instruction(Instruction::Mvi(Register::A, 2)),
+            InstructionOrData::Instruction(Instruction::Out(0x10)),
+            InstructionOrData::Instruction(Instruction::Mvi(Register::B, 3)),
+            InstructionOrData::Instruction(Instruction::Jmp(0x10)),
+            InstructionOrData::Slice(vec![0; 2].into_boxed_slice()),
+            InstructionOrData::Instruction(Instruction::Lxi(RegisterPair::Hl, 9.into())),
+        ]);
+    }
+
+    #[test]
+    fn parse_symbol_redefinition() {
+        let equ_twice = b"
+        PORT    EQU 1
+        PORT    EQU 2
+                END
+        ";
+        let error = parse_assembly(equ_twice, Cpu::Intel8080).unwrap_err();
+        assert_eq!(error.to_string(), "3:9: Duplicate label PORT");
+
+        let set_label = b"
+        LOOP:   NOP
+        LOOP    SET 2
+                END
+        ";
+        let error = parse_assembly(set_label, Cpu::Intel8080).unwrap_err();
+        assert_eq!(error.to_string(), "3:9: Duplicate label LOOP");
+
+        let set_before_use = b"
+                MVI A, COUNT
+        COUNT   SET 1
+                END
+        ";
+        let error = parse_assembly(set_before_use, Cpu::Intel8080).unwrap_err();
+        assert_eq!(error.to_string(), "2:17: Unknown label");
+    }
+
+    #[test]
+    fn parse_forward_equates() {
+        let source = b"
+        COUNT   SET 1
+        LAST    EQU TABLE+SIZE-COUNT
+        SIZE    EQU TEND-TABLE
+        COUNT   SET 2
+                MVI A, SIZE
+                LXI H, LAST
+        TABLE:  DB 'ABC'
+        TEND:   END
+        ";
+
+        let (instructions, _) = parse_assembly(source, Cpu::Intel8080).expect("Failed to parse program");
+        assert_eq!(instructions, vec![
+            InstructionOrData::Instruction(Instruction::Mvi(Register::A, 3)),
+            InstructionOrData::Instruction(Instruction::Lxi(RegisterPair::Hl, 7.into())),
+            InstructionOrData::Slice(b"ABC".to_vec().into_boxed_slice()),
+        ]);
+
+        let unknown = b"
+        SIZE    EQU TEND-TABLE
+        TABLE:  DB 'ABC'
+                END
+        ";
+        let error = parse_assembly(unknown, Cpu::Intel8080).unwrap_err();
+        assert_eq!(error.to_string(), "2:21: Unknown label");
+
+        let duplicate = b"
+        SIZE    EQU TEND
+        SIZE:   NOP
+        TEND:   END
+        ";
+        let error = parse_assembly(duplicate, Cpu::Intel8080).unwrap_err();
+        assert_eq!(error.to_string(), "2:9: Duplicate label SIZE");
+    }
+
+    #[test]
+    fn parse_expressions() {
+        let source = b"
+                ORG 10H*2
+        SIZE    EQU 2*3
+                LXI H, TABLE+2
+                MVI A, LOW(TABLE)
+                MVI B, HIGH TABLE + 1
+                JMP $+3
+                DB -1
+                DW TABLE-$
+                DS SIZE/2
+        TABLE:  DB 'AB'
+                END
+        ";
+
+        let (instructions, start) = parse_assembly(source, Cpu::Intel8080).expect("Failed to parse program");
+        assert_eq!(start, 0x20);
+        assert_eq!(instructions, vec![
+            InstructionOrData::Instruction(Instruction::Lxi(RegisterPair::Hl, 0x32.into())),
+            InstructionOrData::Instruction(Instruction::Mvi(Register::A, 0x30)),
+            InstructionOrData::Instruction(Instruction::Mvi(Register::B, 0x01)),
+            InstructionOrData::Instruction(Instruction::Jmp(0x2a)),
+            InstructionOrData::Slice(Box::new([0xff])),
+            InstructionOrData::Byte(0x05),
+            InstructionOrData::Byte(0x00),
+            InstructionOrData::Slice(vec![0; 3].into_boxed_slice()),
+            InstructionOrData::Slice(Box::new(*b"AB")),
+        ]);
+
+        let source = b"        MVI A, 100H\n        END\n";
+        let error = parse_assembly(source, Cpu::Intel8080).unwrap_err();
+        assert_eq!(error.to_string(), "1:9: Value out of range");
+    }
+
+    #[test]
+    fn parse_conditionals() {
+        let source = b"
+        BOARD   EQU 2
+        IFLAG   SET 0
+                IF BOARD EQ 1       ; Excluded lines take up no space
+                MVI A, 1
+                ELSE
+                IF IFLAG
+                MVI A, 2
+                ELSE
+                MVI A, 3
+        IFLAG   SET 1
+                ENDIF
+                ENDIF
+                IF IFLAG AND BOARD GE 2
+        DONE:   JMP DONE
+                ENDIF
+                END
+        ";
+
+        let (instructions, _) = parse_assembly(source, Cpu::Intel8080).expect("Failed to parse program");
+        assert_eq!(instructions, vec![
+            InstructionOrData::Instruction(Instruction::Mvi(Register::A, 3)),
+            InstructionOrData::Instruction(Instruction::Jmp(2)),
+        ]);
+
+        let unterminated = b"
+                IF 1
+                NOP
+                END
+        ";
+        assert!(parse_assembly(unterminated, Cpu::Intel8080).is_err());
+    }
+
+    #[test]
+    fn assemble_macros() {
+        let source = b"\
+PUSHA   MACRO
+        PUSH B
+        PUSH D
+        ENDM
+WAIT    MACRO COUNT
+        LOCAL LOOP
+        MVI A, COUNT
+LOOP:   DCR A
+        JNZ LOOP
+        ENDM
+START:  PUSHA
+        WAIT 3
+        WAIT 5
+        JMP START
+        END
+";
+
+        let program = assemble(source, Cpu::Intel8080).expect("Failed to assemble program");
+        assert_eq!(program.bytes, [
+            0xC5, 0xD5,
+            0x3E, 0x03, 0x3D, 0xC2, 0x04, 0x00,
+            0x3E, 0x05, 0x3D, 0xC2, 0x0A, 0x00,
+            0xC3, 0x00, 0x00,
+        ]);
+
+        let listing = program.listing.to_string();
+        let listing: Vec<&str> = listing.lines().collect();
+        assert_eq!(listing[10], "   11                      START:  PUSHA");
+        assert_eq!(listing[11], "   11+ 0000  C5                    PUSH B");
+        assert_eq!(listing[15], "   12+ 0004  3D            ?0000:   DCR A");
+        assert_eq!(listing[21], "   14  000E  C3 00 00              JMP START");
+
+        let source = b"\
+LOAD    MACRO VALUE
+        MVI A, VALUE
+        ENDM
+        LOAD 1
+        LOAD 100H
+        END
+";
+        let error = assemble(source, Cpu::Intel8080).unwrap_err();
+        assert_eq!(error.to_string(), "5:9: Value out of range (in macro at 2:9)");
+    }
+
+    #[test]
+    fn assemble_conditional_macros() {
+        let source = b"\
+TARGET  EQU 2
+        IF TARGET EQ 1
+PUTC    MACRO
+        OUT 0
+        ENDM
+        ELSE
+PUTC    MACRO
+        OUT 1
+        ENDM
+        ENDIF
+        PUTC
+        END
+";
+
+        let program = assemble(source, Cpu::Intel8080).expect("Failed to assemble program");
+        assert_eq!(program.bytes, [0xD3, 0x01]);
+    }
+
+    #[test]
+    fn assemble_syntax_errors() {
+        let position = |position: Option<SourcePosition>| position.map(|position| (position.line, position.column));
+
+        // Lines after a macro call are reported at their own line.
+        let source = b"\
+PUSHA   MACRO
+        PUSH B
+        PUSH D
+        ENDM
+        PUSHA
+        MOV A,
+        END
+";
+        let error = assemble(source, Cpu::Intel8080).unwrap_err();
+        assert_eq!(position(error.position), Some((6, 9)));
+        assert_eq!(error.macro_position, None);
+
+        // A bad operand passed to a macro is reported at the call and the line in the macro.
+        let source = b"\
+LOAD    MACRO VALUE
+        NOP
+        MVI A, VALUE
+        ENDM
+        LOAD 1
+        LOAD (
+        END
+";
+        let error = assemble(source, Cpu::Intel8080).unwrap_err();
+        assert_eq!(position(error.position), Some((6, 9)));
+        assert_eq!(position(error.macro_position), Some((3, 9)));
+    }
+
+    #[test]
+    fn assemble_program() {
+        let source = b"
+                ORG 100H
+        START:  MVI A, 42H
+                JMP START
+                END
+        ";
+
+        let program = assemble(source, Cpu::Intel8080).expect("Failed to assemble program");
+        assert_eq!(program.origin, 0x100);
+        assert_eq!(program.bytes, [0x3E, 0x42, 0xC3, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn assemble_error_position() {
+        let source = b"        MOV A, B\n        JMP NOWHR\n        END\n";
+
+        let error = assemble(source, Cpu::Intel8080).unwrap_err();
+        let position = error.position.as_ref().unwrap();
+        assert_eq!((position.offset, position.line, position.column), (25, 2, 9));
+        assert_eq!(error.to_string(), "2:9: Unknown label");
+    }
 }
@@ -5,7 +5,13 @@ use parsable::{CharLiteral, CharRange, Parsable, Span, ZeroPlus};
 use crate::instruction::Address;
 
 pub struct LabelLookup {
-    map: HashMap<Vec<u8>, Address>,
+    map: HashMap<Vec<u8>, Symbol>,
+}
+
+struct Symbol {
+    value: Address,
+    // Defined with `SET`, so it may be defined again.
+    redefinable: bool,
 }
 
 impl LabelLookup {
@@ -19,19 +25,37 @@ impl LabelLookup {
         label.span[..label.span.len().min(5)].to_owned()
     }
 
+    /// Defines a label or `EQU` symbol, which may only be defined once.
     pub fn insert(&mut self, label: Label, address: Address) -> Result<(), ()> {
         let ident = LabelLookup::to_label_ident(&label);
         if self.map.contains_key(&ident) {
             Err(())
         } else {
-            self.map.insert(ident, address);
+            self.map.insert(ident, Symbol { value: address, redefinable: false });
             Ok(())
         }
     }
 
+    /// Defines or redefines a `SET` symbol. Fails if the name is already used by a label or
+    /// `EQU` symbol.
+    pub fn set(&mut self, label: Label, value: Address) -> Result<(), ()> {
+        let ident = LabelLookup::to_label_ident(&label);
+        if self.map.get(&ident).is_some_and(|symbol| !symbol.redefinable) {
+            Err(())
+        } else {
+            self.map.insert(ident, Symbol { value, redefinable: true });
+            Ok(())
+        }
+    }
+
+    /// Removes the `SET` symbols, so that they're undefined until their first `SET` again.
+    pub fn remove_set_symbols(&mut self) {
+        self.map.retain(|_, symbol| !symbol.redefinable);
+    }
+
     pub fn get(&self, label: Label) -> Option<Address> {
         let ident = LabelLookup::to_label_ident(&label);
-        self.map.get(&ident).copied()
+        self.map.get(&ident).map(|symbol| symbol.value)
     }
 }
 
@@ -0,0 +1,112 @@
+//! The assembly listing, which shows the address and machine code of each line of the source.
+
+use std::{fmt::Display, path::PathBuf};
+
+use crate::instruction::Address;
+
+/// A line of the source, or of a macro expansion, with the code assembled from it.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct ListingLine {
+    /// Index in `Listing::files` of the file the line is in.
+    pub file: usize,
+    /// Line number in the file, of the macro call for lines expanded from a macro.
+    pub line: usize,
+    /// Whether the line was expanded from a macro.
+    pub expanded: bool,
+    /// The address of the line's instruction or data, if it has any.
+    pub address: Option<Address>,
+    /// The machine code assembled from the line. Empty for `DS`, which only reserves memory.
+    pub bytes: Vec<u8>,
+    pub text: String,
+}
+
+/// The lines of an assembled program. Its `Display` implementation formats it with one row per
+/// line, like:
+///
+/// ```text
+///     3  0100  3E 42         START:  MVI A, 42H
+///     4+ 0102  C3 00 01              JMP START
+/// ```
+///
+/// where `+` marks the lines expanded from a macro. The lines of an included file are preceded by
+/// the path of the file, and followed by the path of the file which included it.
+#[derive(Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub struct Listing {
+    /// The source, which has no path if it wasn't read from a file, followed by the files it
+    /// included.
+    pub files: Vec<Option<PathBuf>>,
+    pub lines: Vec<ListingLine>,
+}
+
+// Longer code continues on the following rows.
+const BYTES_PER_ROW: usize = 4;
+
+fn format_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(" ")
+}
+
+impl Display for Listing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut file = 0;
+        for line in &self.lines {
+            if line.file != file {
+                file = line.file;
+                match self.files.get(file).cloned().flatten() {
+                    Some(path) => writeln!(f, "{:6} ; {}", "", path.display())?,
+                    None => writeln!(f, "{:6} ; (source)", "")?,
+                }
+            }
+            let marker = if line.expanded { '+' } else { ' ' };
+            let address = line.address.map_or(String::from("    "), |address| format!("{:04X}", address));
+            let mut rows = line.bytes.chunks(BYTES_PER_ROW);
+            let first = format_bytes(rows.next().unwrap_or_default());
+            let row = format!("{:5}{} {}  {:<12}  {}", line.line, marker, address, first, line.text);
+            writeln!(f, "{}", row.trim_end())?;
+
+            for (index, bytes) in rows.enumerate() {
+                let offset = (BYTES_PER_ROW * (index + 1)) as Address;
+                let address = line.address.unwrap_or_default().wrapping_add(offset);
+                writeln!(f, "{:6} {:04X}  {}", "", address, format_bytes(bytes))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listing_format() {
+        let listing = Listing {
+            files: vec![None, Some(PathBuf::from("lib.asm"))],
+            lines: vec![
+                ListingLine {
+                    file: 0,
+                    line: 1,
+                    expanded: false,
+                    address: None,
+                    bytes: Vec::new(),
+                    text: String::from("        ORG 100H"),
+                },
+                ListingLine {
+                    file: 1,
+                    line: 2,
+                    expanded: true,
+                    address: Some(0x100),
+                    bytes: b"Hello".to_vec(),
+                    text: String::from("        DB 'Hello'"),
+                },
+            ],
+        };
+
+        assert_eq!(
+            listing.to_string(),
+            "    1                              ORG 100H\n\
+             \x20      ; lib.asm\n\
+             \x20   2+ 0100  48 65 6C 6C           DB 'Hello'\n\
+             \x20      0104  6F\n",
+        );
+    }
+}
@@ -0,0 +1,764 @@
+//! Expands macros, included files and conditional assembly, giving the lines which are assembled.
+//! Macro definitions are blanked out, and each call is replaced by the lines of the macro, with
+//! the parameters replaced by the arguments and the `LOCAL` labels replaced by names which are
+//! unique to the expansion. Each `INCLUDE` is replaced by the expanded lines of the file.
+//!
+//! The lines are assembled as they're expanded, so that the condition of an `IF` can refer to the
+//! symbols defined above it, and macros are only defined and files only included by the lines
+//! which are assembled.
+
+use std::{
+    collections::HashMap,
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+use parsable::{Parsable, ScopedStream, Span};
+
+use crate::assembler::{
+    AssemblyError, AssemblySource, SourcePosition,
+    parse::{
+        ElseLine, EndIfLine, IfLine,
+        macros::{EndmLine, IncludeLine, LocalLine, MacroCallStart, MacroHeader},
+    },
+};
+
+// Deep enough for any sensible program, while catching a macro which calls itself.
+const MAX_DEPTH: usize = 64;
+
+/// Reads an included file.
+pub type ReadFile<'a> = dyn FnMut(&Path) -> io::Result<Vec<u8>> + 'a;
+
+/// Assembles the lines of the expansion as they're expanded.
+pub trait LineAssembler {
+    /// Assembles the code of line `line` of `expansion`.
+    fn assemble_line(&mut self, expansion: &Expansion, line: usize) -> Result<(), AssemblyError>;
+
+    /// Evaluates the condition of the `IF` which is the code of line `line` of `expansion`.
+    fn evaluate_condition(&mut self, expansion: &Expansion, line: usize) -> Result<bool, AssemblyError>;
+}
+
+/// The source passed to the assembler, or a file it included.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InputFile {
+    /// `None` for a source which wasn't read from a file.
+    pub path: Option<PathBuf>,
+    pub text: Vec<u8>,
+}
+
+impl InputFile {
+    pub fn position(&self, offset: usize) -> SourcePosition {
+        SourcePosition::new(self.path.clone(), &self.text, offset)
+    }
+}
+
+/// An offset in one of the input files.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    /// Index of the file in `Expansion::files`.
+    pub file: usize,
+    pub offset: usize,
+}
+
+/// Where a line of the expansion came from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LineOrigin {
+    /// The start of the line, or for lines expanded from a macro, the outermost macro call.
+    pub location: Location,
+    /// Line number in its file of `location`, starting at 1.
+    pub line: usize,
+    /// For lines expanded from a macro, the start of the line in the macro definition.
+    pub macro_location: Option<Location>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExpandedLine {
+    pub origin: LineOrigin,
+    /// The line as it's shown in a listing: as written in the source, or with the arguments
+    /// substituted for lines expanded from a macro.
+    pub text: Vec<u8>,
+    /// The code which is assembled. Empty for the lines which are handled while expanding, like
+    /// macro definitions, and the lines which aren't assembled because of an `IF`.
+    pub code: Vec<u8>,
+}
+
+/// The source with all macros and included files expanded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expansion {
+    pub lines: Vec<ExpandedLine>,
+    /// The source, followed by the included files in the order they were included.
+    pub files: Vec<InputFile>,
+}
+
+impl Expansion {
+    /// An error at byte `column` of the code of line `line`, positioned in the file it came from.
+    pub fn error(&self, line: usize, column: usize, message: String) -> AssemblyError {
+        origin_error(&self.files, &self.lines[line].origin, column, message)
+    }
+}
+
+fn origin_error(
+    files: &[InputFile],
+    origin: &LineOrigin,
+    column: usize,
+    message: String,
+) -> AssemblyError {
+    let location = origin.location;
+    match origin.macro_location {
+        None => AssemblyError::at(&files[location.file], location.offset + column, message),
+        Some(macro_location) => {
+            // The arguments may be longer or shorter than the parameters they replaced, so the
+            // column in the macro is approximate.
+            let file = &files[macro_location.file];
+            let line_end = file.text[macro_location.offset..]
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .map_or(file.text.len(), |index| macro_location.offset + index);
+            AssemblyError {
+                position: Some(files[location.file].position(location.offset)),
+                macro_position: Some(file.position((macro_location.offset + column).min(line_end))),
+                message,
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+struct SourceLine {
+    text: Vec<u8>,
+    origin: LineOrigin,
+}
+
+// The lines of a file. A newline at the end of the file doesn't start another line.
+fn split_lines(file: usize, text: &[u8]) -> Vec<SourceLine> {
+    let text = text.strip_suffix(b"\n").unwrap_or(text);
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    for (index, text) in text.split(|&byte| byte == b'\n').enumerate() {
+        let origin = LineOrigin { location: Location { file, offset }, line: index + 1, macro_location: None };
+        lines.push(SourceLine { text: text.to_vec(), origin });
+        offset += text.len() + 1;
+    }
+    lines
+}
+
+/// The number of spaces and tabs at the start of `text`.
+pub fn indentation(text: &[u8]) -> usize {
+    text.iter().take_while(|&&byte| byte == b' ' || byte == b'\t').count()
+}
+
+#[derive(Clone)]
+struct Macro {
+    parameters: Vec<Vec<u8>>,
+    locals: Vec<Vec<u8>>,
+    body: Vec<SourceLine>,
+}
+
+impl Macro {
+    // Compares the text of the definitions, but not where their lines came from.
+    fn same_definition(&self, other: &Macro) -> bool {
+        self.parameters == other.parameters
+            && self.locals == other.locals
+            && self.body.iter().map(|line| &line.text).eq(other.body.iter().map(|line| &line.text))
+    }
+}
+
+// An `IF` whose `ENDIF` hasn't been reached yet.
+struct Conditional {
+    // The `IF` line and the column of the `IF`, where a missing `ENDIF` is reported.
+    line: SourceLine,
+    column: usize,
+    // Whether the lines around the block are assembled.
+    enclosing_active: bool,
+    condition: bool,
+    in_else: bool,
+}
+
+impl Conditional {
+    fn active(&self) -> bool {
+        self.enclosing_active && self.condition != self.in_else
+    }
+}
+
+struct Expander<'a, 'b> {
+    read_file: &'a mut ReadFile<'b>,
+    assembler: &'a mut dyn LineAssembler,
+    macros: HashMap<Vec<u8>, Macro>,
+    local_count: u32,
+    // The files being included, innermost last, to catch files which include themselves.
+    include_stack: Vec<PathBuf>,
+    expansion: Expansion,
+}
+
+/// Expands `source`, which was read from `path` if it's given, and assembles the expanded lines
+/// with `assembler`. Included files are read with `read_file`, and their paths are relative to
+/// the file including them, or for the source without a path, to the current directory.
+pub fn expand(
+    source: AssemblySource,
+    path: Option<&Path>,
+    read_file: &mut ReadFile,
+    assembler: &mut dyn LineAssembler,
+) -> Result<Expansion, AssemblyError> {
+    let lines = split_lines(0, source);
+    let path = path.map(normalize);
+    let mut expander = Expander {
+        read_file,
+        assembler,
+        macros: HashMap::new(),
+        local_count: 0,
+        include_stack: path.iter().cloned().collect(),
+        expansion: Expansion {
+            lines: Vec::new(),
+            files: vec![InputFile { path, text: source.to_vec() }],
+        },
+    };
+    expander.expand_lines(&lines, 0)?;
+    Ok(expander.expansion)
+}
+
+fn parse_line<'a, T: Parsable<'a>>(text: &'a [u8]) -> parsable::ParseOutcome<T> {
+    T::parse(&mut ScopedStream::new(text))
+}
+
+impl Expander<'_, '_> {
+    fn error(&self, line: &SourceLine, column: usize, message: String) -> AssemblyError {
+        origin_error(&self.expansion.files, &line.origin, column, message)
+    }
+
+    // Adds a line to the expansion, returning its index.
+    fn emit(&mut self, code: &[u8], line: &SourceLine) -> usize {
+        let text = line.text.strip_suffix(b"\r").unwrap_or(&line.text);
+        let lines = &mut self.expansion.lines;
+        lines.push(ExpandedLine { origin: line.origin, text: text.to_vec(), code: code.to_vec() });
+        lines.len() - 1
+    }
+
+    fn expand_lines(&mut self, lines: &[SourceLine], depth: usize) -> Result<(), AssemblyError> {
+        // Conditional blocks end in the file or macro they start in.
+        let mut conditionals: Vec<Conditional> = Vec::new();
+        let mut index = 0;
+        while index < lines.len() {
+            let line = &lines[index];
+            let start = indentation(&line.text);
+            let content = &line.text[start..];
+            let active = conditionals.last().is_none_or(Conditional::active);
+            index += 1;
+
+            if parse_line::<IfLine>(content).is_some() {
+                let condition = if active {
+                    let expanded = self.emit(&line.text, line);
+                    self.assembler.evaluate_condition(&self.expansion, expanded)?
+                } else {
+                    self.emit(b"", line);
+                    false
+                };
+                conditionals.push(Conditional {
+                    line: line.clone(),
+                    column: start,
+                    enclosing_active: active,
+                    condition,
+                    in_else: false,
+                });
+                continue;
+            }
+
+            if let Some(else_line) = parse_line::<ElseLine>(content) {
+                else_line.map_err(|_| self.error(line, start, String::from("Invalid ELSE")))?;
+                match conditionals.last_mut() {
+                    Some(conditional) if !conditional.in_else => conditional.in_else = true,
+                    Some(_) => return Err(self.error(line, start, String::from("Duplicate ELSE"))),
+                    None => return Err(self.error(line, start, String::from("ELSE without IF"))),
+                }
+                self.emit(b"", line);
+                continue;
+            }
+
+            if let Some(end_if_line) = parse_line::<EndIfLine>(content) {
+                end_if_line.map_err(|_| self.error(line, start, String::from("Invalid ENDIF")))?;
+                if conditionals.pop().is_none() {
+                    return Err(self.error(line, start, String::from("ENDIF without IF")));
+                }
+                self.emit(b"", line);
+                continue;
+            }
+
+            if !active {
+                self.emit(b"", line);
+                continue;
+            }
+
+            if let Some(header) = parse_line::<MacroHeader>(content) {
+                let header = header
+                    .map_err(|_| self.error(line, start, String::from("Invalid macro definition")))?;
+                let end = find_endm(lines, index - 1)
+                    .ok_or_else(|| self.error(line, start, String::from("Missing ENDM")))?;
+                self.define(header, &lines[index..end], line, start)?;
+                for line in &lines[index - 1..=end] {
+                    self.emit(b"", line);
+                }
+                index = end + 1;
+                continue;
+            }
+
+            if let Some(include) = parse_line::<IncludeLine>(content) {
+                let include = include
+                    .map_err(|_| self.error(line, start, String::from("Invalid INCLUDE")))?;
+                self.emit(b"", line);
+                self.include(include, line, start, depth)?;
+                continue;
+            }
+
+            if let Some(Ok(call)) = parse_line::<Span<MacroCallStart>>(content)
+                && self.macros.contains_key(&call.node.name.span)
+            {
+                if depth == MAX_DEPTH {
+                    return Err(self.error(line, start, String::from("Macro calls are nested too deeply")));
+                }
+                self.call(&call, &content[call.span.len()..], line, start, depth)?;
+            } else {
+                let expanded = self.emit(&line.text, line);
+                self.assembler.assemble_line(&self.expansion, expanded)?;
+            }
+        }
+
+        match conditionals.first() {
+            Some(conditional) => Err(self.error(&conditional.line, conditional.column, String::from("Missing ENDIF"))),
+            None => Ok(()),
+        }
+    }
+
+    fn define(
+        &mut self,
+        header: MacroHeader,
+        mut body: &[SourceLine],
+        line: &SourceLine,
+        column: usize,
+    ) -> Result<(), AssemblyError> {
+        let mut locals = Vec::new();
+        while let Some(first) = body.first() {
+            let start = indentation(&first.text);
+            match parse_line::<LocalLine>(&first.text[start..]) {
+                Some(Ok(local_line)) => locals.extend(local_line.names.into_iter().map(|name| name.span)),
+                Some(Err(_)) => return Err(self.error(first, start, String::from("Invalid LOCAL"))),
+                None => break,
+            }
+            body = &body[1..];
+        }
+
+        let name = header.name.span;
+        let definition = Macro {
+            parameters: header.parameters.into_iter().map(|parameter| parameter.span).collect(),
+            locals,
+            body: body.to_vec(),
+        };
+        // A macro defined inside another macro is defined again by every call, which is allowed as
+        // long as the definition doesn't change.
+        if let Some(existing) = self.macros.get(&name) {
+            if existing.same_definition(&definition) {
+                return Ok(());
+            }
+            let message = format!("Duplicate macro {}", String::from_utf8_lossy(&name));
+            return Err(self.error(line, column, message));
+        }
+        self.macros.insert(name, definition);
+        Ok(())
+    }
+
+    fn call(
+        &mut self,
+        call: &Span<MacroCallStart>,
+        arguments: &[u8],
+        line: &SourceLine,
+        column: usize,
+        depth: usize,
+    ) -> Result<(), AssemblyError> {
+        let name = &call.node.name.span;
+        let definition = self.macros[name].clone();
+        let arguments = split_arguments(arguments);
+        if arguments.len() > definition.parameters.len() {
+            let message = format!("Too many arguments to macro {}", String::from_utf8_lossy(name));
+            return Err(self.error(line, column, message));
+        }
+
+        // Missing arguments are empty.
+        let mut replacements: HashMap<Vec<u8>, Vec<u8>> = definition.parameters.iter()
+            .cloned()
+            .zip(arguments.into_iter().chain(std::iter::repeat(Vec::new())))
+            .collect();
+        // Local labels are named `?` followed by 4 hexadecimal digits, which fits in the 5
+        // significant characters of a label, so there can be at most 0x10000 of them.
+        for local in &definition.locals {
+            if self.local_count > 0xffff {
+                return Err(self.error(line, column, String::from("Too many LOCAL labels")));
+            }
+            let unique = format!("?{:04X}", self.local_count);
+            replacements.insert(local.clone(), unique.into_bytes());
+            self.local_count += 1;
+        }
+
+        // The call line keeps only its label.
+        let label = match &call.node.label {
+            Some(label) => [&label.0.node.span[..], b":"].concat(),
+            None => Vec::new(),
+        };
+        let expanded = self.emit(&label, line);
+        self.assembler.assemble_line(&self.expansion, expanded)?;
+
+        let call_site = match line.origin.macro_location {
+            None => Location { offset: line.origin.location.offset + column, ..line.origin.location },
+            Some(_) => line.origin.location,
+        };
+        let expanded: Vec<SourceLine> = definition.body.iter()
+            .map(|body_line| SourceLine {
+                text: substitute(&body_line.text, &replacements),
+                origin: LineOrigin {
+                    location: call_site,
+                    line: line.origin.line,
+                    macro_location: body_line.origin.macro_location.or(Some(body_line.origin.location)),
+                },
+            })
+            .collect();
+        self.expand_lines(&expanded, depth + 1)
+    }
+
+    fn include(
+        &mut self,
+        include: IncludeLine,
+        line: &SourceLine,
+        column: usize,
+        depth: usize,
+    ) -> Result<(), AssemblyError> {
+        // Errors are reported at the file name.
+        let column = column + line.text[column..].iter().position(|&byte| byte == b'\'').unwrap_or(0);
+        let name = PathBuf::from(String::from_utf8_lossy(&include.file.contents.span).into_owned());
+        // In a macro, the path is relative to the file the macro is defined in.
+        let including = line.origin.macro_location.unwrap_or(line.origin.location).file;
+        let path = match &self.expansion.files[including].path {
+            Some(including) => normalize(&including.parent().unwrap_or(Path::new("")).join(&name)),
+            None => normalize(&name),
+        };
+
+        if self.include_stack.contains(&path) {
+            let message = format!("{} includes itself", path.display());
+            return Err(self.error(line, column, message));
+        }
+        if self.include_stack.len() > MAX_DEPTH {
+            return Err(self.error(line, column, String::from("Includes are nested too deeply")));
+        }
+        let text = (self.read_file)(&path)
+            .map_err(|err| self.error(line, column, format!("Couldn't read {}: {}", path.display(), err)))?;
+
+        let file = self.expansion.files.len();
+        let lines = split_lines(file, &text);
+        self.expansion.files.push(InputFile { path: Some(path.clone()), text });
+        self.include_stack.push(path);
+        self.expand_lines(&lines, depth)?;
+        self.include_stack.pop();
+        Ok(())
+    }
+}
+
+// Removes the `.` and `..` components of `path` which can be, so that each file has one path.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir if matches!(normalized.components().next_back(), Some(Component::Normal(_))) => {
+                normalized.pop();
+            },
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+// The index of the `ENDM` ending the definition starting at `start`, skipping over macros
+// defined inside it.
+fn find_endm(lines: &[SourceLine], start: usize) -> Option<usize> {
+    let mut nesting = 0;
+    for (index, line) in lines.iter().enumerate().skip(start + 1) {
+        let content = line.text.trim_ascii_start();
+        if parse_line::<MacroHeader>(content).is_some() {
+            nesting += 1;
+        } else if parse_line::<EndmLine>(content).is_some() {
+            if nesting == 0 {
+                return Some(index);
+            }
+            nesting -= 1;
+        }
+    }
+    None
+}
+
+// Splits the arguments of a call at the commas which aren't in a string, up to the comment.
+fn split_arguments(text: &[u8]) -> Vec<Vec<u8>> {
+    let mut arguments = vec![Vec::new()];
+    let mut in_string = false;
+    for &byte in text {
+        match byte {
+            b';' if !in_string => break,
+            b',' if !in_string => arguments.push(Vec::new()),
+            _ => {
+                if byte == b'\'' {
+                    in_string = !in_string;
+                }
+                arguments.last_mut().unwrap().push(byte);
+            }
+        }
+    }
+    let arguments: Vec<Vec<u8>> = arguments.iter().map(|argument| argument.trim_ascii().to_vec()).collect();
+    if arguments == [Vec::<u8>::new()] { Vec::new() } else { arguments }
+}
+
+// Replaces the names in `text` which are keys of `replacements`, except in strings and comments.
+fn substitute(text: &[u8], replacements: &HashMap<Vec<u8>, Vec<u8>>) -> Vec<u8> {
+    let is_name_char = |byte: u8| byte.is_ascii_alphanumeric() || byte == b'@' || byte == b'?';
+    let mut output = Vec::new();
+    let mut index = 0;
+    while index < text.len() {
+        let byte = text[index];
+        let end = if byte == b';' {
+            text.len()
+        } else if byte == b'\'' {
+            text[index + 1..].iter().position(|&byte| byte == b'\'').map_or(text.len(), |end| index + end + 2)
+        } else if is_name_char(byte) {
+            // Numbers are skipped as a whole, so that the `FFH` in `0FFH` isn't a name.
+            let end = text[index..].iter().position(|&byte| !is_name_char(byte)).map_or(text.len(), |end| index + end);
+            if let Some(replacement) = replacements.get(&text[index..end]) {
+                output.extend_from_slice(replacement);
+                index = end;
+                continue;
+            }
+            end
+        } else {
+            index + 1
+        };
+        output.extend_from_slice(&text[index..end]);
+        index = end;
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Records the code of the lines it assembles, and evaluates conditions which end with a digit
+    // to whether the digit is `1`.
+    #[derive(Default)]
+    struct Recorder {
+        code: Vec<String>,
+    }
+
+    impl LineAssembler for Recorder {
+        fn assemble_line(&mut self, expansion: &Expansion, line: usize) -> Result<(), AssemblyError> {
+            self.code.push(String::from_utf8_lossy(&expansion.lines[line].code).into_owned());
+            Ok(())
+        }
+
+        fn evaluate_condition(&mut self, expansion: &Expansion, line: usize) -> Result<bool, AssemblyError> {
+            Ok(expansion.lines[line].code.trim_ascii().ends_with(b"1"))
+        }
+    }
+
+    fn expand_without_files(source: AssemblySource) -> Result<(Expansion, Vec<String>), AssemblyError> {
+        let mut recorder = Recorder::default();
+        let expansion = expand(source, None, &mut |_| Err(io::ErrorKind::NotFound.into()), &mut recorder)?;
+        Ok((expansion, recorder.code))
+    }
+
+    #[test]
+    fn test_expand() {
+        let source = b"\
+SWAP    MACRO R1, R2
+        LOCAL SKIP
+        MOV A, R1
+        JZ SKIP         ; R1 isn't replaced here
+        MOV R1, R2
+SKIP:   MOV R2, A
+        ENDM
+START:  SWAP B, C
+        SWAP D
+        END";
+
+        let (expansion, code) = expand_without_files(source).unwrap();
+        assert_eq!(code, [
+            "START:",
+            "        MOV A, B",
+            "        JZ ?0000         ; R1 isn't replaced here",
+            "        MOV B, C",
+            "?0000:   MOV C, A",
+            "",
+            "        MOV A, D",
+            "        JZ ?0001         ; R1 isn't replaced here",
+            "        MOV D, ",
+            "?0001:   MOV , A",
+            "        END",
+        ]);
+
+        // The call, and then the lines of the macro it expanded to.
+        let origins: Vec<_> = expansion.lines[7..12].iter().map(|line| line.origin).collect();
+        let call = Location { file: 0, offset: 157 };
+        assert_eq!(origins[0], LineOrigin { location: call, line: 8, macro_location: None });
+        let body = Location { file: 0, offset: 40 };
+        assert_eq!(origins[1], LineOrigin { location: call, line: 8, macro_location: Some(body) });
+        assert_eq!(expansion.lines[10].text, b"        MOV B, C");
+        assert_eq!(expansion.lines[0].code, b"");
+    }
+
+    #[test]
+    fn test_expand_locals() {
+        let source = b"\
+TWO     MACRO
+        LOCAL A, B
+A:      JMP B
+B:      JMP A
+        ENDM
+        TWO
+        TWO
+";
+        let (_, code) = expand_without_files(source).unwrap();
+        assert_eq!(code[1..3], ["?0000:      JMP ?0001", "?0001:      JMP ?0000"]);
+        assert_eq!(code[4..6], ["?0002:      JMP ?0003", "?0003:      JMP ?0002"]);
+
+        // Every name must be unique in 5 characters.
+        let mut source = b"ONE     MACRO\n        LOCAL L\nL:      NOP\n        ENDM\n".to_vec();
+        source.extend(b"        ONE\n".repeat(0x10001));
+        let error = expand_without_files(&source).unwrap_err();
+        assert_eq!(error.to_string(), "65541:9: Too many LOCAL labels");
+    }
+
+    #[test]
+    fn test_expand_unchanged() {
+        let (expansion, code) = expand_without_files(b"  MOV A, B\r\n  END\r\n").unwrap();
+        assert_eq!(code, ["  MOV A, B\r", "  END\r"]);
+        assert_eq!(expansion.lines[1].text, b"  END");
+    }
+
+    #[test]
+    fn test_expand_errors() {
+        let recursive = b"LOOP    MACRO\n        LOOP\n        ENDM\n        LOOP\n        END\n";
+        let error = expand_without_files(recursive).unwrap_err();
+        assert_eq!(error.to_string(), "4:9: Macro calls are nested too deeply (in macro at 2:9)");
+
+        let unterminated = b"NOP2    MACRO\n        NOP\n        END\n";
+        assert_eq!(expand_without_files(unterminated).unwrap_err().to_string(), "1:1: Missing ENDM");
+
+        let arguments = b"ONE     MACRO X\n        ENDM\n        ONE 1, 2\n";
+        assert_eq!(expand_without_files(arguments).unwrap_err().to_string(), "3:9: Too many arguments to macro ONE");
+    }
+
+    #[test]
+    fn test_expand_nested_definition() {
+        // Each call of OUTER defines INNER again, unchanged unless the definition uses a parameter.
+        let source = "\
+OUTER   MACRO X
+INNER   MACRO
+        OUT 1
+        ENDM
+        INNER
+        ENDM
+        OUTER 2
+        OUTER 3
+        INNER
+";
+        let (_, code) = expand_without_files(source.as_bytes()).unwrap();
+        let outs: Vec<_> = code.iter().filter(|code| !code.is_empty()).collect();
+        assert_eq!(outs, ["        OUT 1"; 3]);
+
+        let changed = source.replace("OUT 1", "OUT X");
+        let error = expand_without_files(changed.as_bytes()).unwrap_err();
+        assert_eq!(error.to_string(), "8:9: Duplicate macro INNER (in macro at 2:1)");
+    }
+
+    #[test]
+    fn test_expand_conditionals() {
+        // Only the macro in the assembled branch is defined, and files are only included by
+        // assembled lines.
+        let source = b"\
+        IF 0
+OUTA    MACRO
+        OUT 1
+        ENDM
+        INCLUDE 'missing.asm'
+        IF 1
+        NOP
+        ENDIF
+        ELSE
+OUTA    MACRO
+        OUT 2
+        ENDM
+        ENDIF
+        OUTA
+        END
+";
+        let (_, code) = expand_without_files(source).unwrap();
+        assert_eq!(code, ["", "        OUT 2", "        END"]);
+
+        // Conditionals in a macro are evaluated for each call.
+        let source = b"\
+PICK    MACRO FLAG
+        IF FLAG
+        NOP
+        ELSE
+        HLT
+        ENDIF
+        ENDM
+        PICK 1
+        PICK 0
+";
+        let (_, code) = expand_without_files(source).unwrap();
+        assert_eq!(code, ["", "        NOP", "", "        HLT"]);
+
+        let error = expand_without_files(b"        IF 1\n        NOP\n").unwrap_err();
+        assert_eq!(error.to_string(), "1:9: Missing ENDIF");
+        let error = expand_without_files(b"        NOP\n        ELSE\n").unwrap_err();
+        assert_eq!(error.to_string(), "2:9: ELSE without IF");
+        let error = expand_without_files(b"        IF 0\n        ENDIF\n        ENDIF\n").unwrap_err();
+        assert_eq!(error.to_string(), "3:9: ENDIF without IF");
+    }
+
+    #[test]
+    fn test_expand_include() {
+        let files = HashMap::from([
+            (PathBuf::from("src/main.asm"), b"        INCLUDE 'lib/io.asm'\n        END\n".to_vec()),
+            (PathBuf::from("src/lib/io.asm"), b"        INCLUDE '../util.asm'\nOUT1    MACRO\n        OUT X\n        ENDM\n".to_vec()),
+            (PathBuf::from("src/util.asm"), b"        NOP\n".to_vec()),
+        ]);
+        let mut read_file = |path: &Path| files.get(path).cloned().ok_or(io::Error::from(io::ErrorKind::NotFound));
+
+        let main = &files[Path::new("src/main.asm")];
+        let mut recorder = Recorder::default();
+        let expansion = expand(main, Some(Path::new("src/./main.asm")), &mut read_file, &mut recorder).unwrap();
+        assert_eq!(recorder.code, ["        NOP", "        END"]);
+        let paths: Vec<_> = expansion.files.iter().map(|file| file.path.clone().unwrap()).collect();
+        assert_eq!(paths, ["src/main.asm", "src/lib/io.asm", "src/util.asm"].map(PathBuf::from));
+        assert_eq!(expansion.lines[2].origin.location, Location { file: 2, offset: 0 });
+
+        // Errors in a macro from an included file are reported in both files.
+        let source = b"        INCLUDE 'lib/io.asm'\n        OUT1\n        END\n";
+        let expansion = expand(source, Some(Path::new("src/main.asm")), &mut read_file, &mut Recorder::default()).unwrap();
+        let line = expansion.lines.iter().position(|line| line.code == b"        OUT X").unwrap();
+        let error = expansion.error(line, 12, String::from("Unknown label"));
+        assert_eq!(error.to_string(), "src/main.asm:2:9: Unknown label (in macro at src/lib/io.asm:3:13)");
+    }
+
+    #[test]
+    fn test_include_errors() {
+        let mut read_file = |path: &Path| match path.to_str() {
+            Some("a.asm") => Ok(b"        INCLUDE 'b.asm'\n".to_vec()),
+            Some("b.asm") => Ok(b"\n        INCLUDE './a.asm'\n".to_vec()),
+            _ => Err(io::Error::from(io::ErrorKind::NotFound)),
+        };
+
+        let error = expand(b"        INCLUDE 'a.asm'\n", None, &mut read_file, &mut Recorder::default()).unwrap_err();
+        assert_eq!(error.to_string(), "b.asm:2:17: a.asm includes itself");
+
+        let error = expand(b"\n  INCLUDE 'c.asm'", None, &mut read_file, &mut Recorder::default()).unwrap_err();
+        assert_eq!(error.to_string(), "2:11: Couldn't read c.asm: entity not found");
+
+        let error = expand_without_files(b"        INCLUDE FILE\n").unwrap_err();
+        assert_eq!(error.to_string(), "1:9: Invalid INCLUDE");
+    }
+}
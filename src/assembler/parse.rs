@@ -1,32 +1,33 @@
+pub mod expression;
 pub mod instruction;
 mod literals;
+pub mod macros;
 mod token;
 
 use std::fmt::Debug;
-use parsable::{CharLiteral, CharRange, EndOfStream, Ignore, Parsable, WithIndex, ZeroPlus, ok_or_throw};
+use parsable::{CharLiteral, CharRange, Ignore, Parsable, WithIndex, ZeroPlus, ok_or_throw};
 
-use crate::assembler::{labels::Label, parse::{instruction::Statement, literals::LiteralNumber, token::{Colon, EndOfAssembly, Origin, Semicolon}}};
-
-#[derive(Clone, PartialEq, Eq, Parsable)]
-pub struct SourceFile {
-    _0: WsNl,
-    _1: ZeroPlus<CommentOnlyLine>,
-    pub origin_line: Option<OriginLine>,
-    pub lines: ZeroPlus<StatementLine>,
-    end: EndOfAssemblyLine,
-    _2: ZeroPlus<CommentOnlyLine>,
-}
+use crate::assembler::{labels::Label, parse::{expression::Expression, instruction::Statement, token::{Colon, Equate, Semicolon, SetSymbol}}};
 
+/// A line of the source, after macros and included files are expanded, without the indentation
+/// before it. Conditional assembly is handled while expanding, so `IF`, `ELSE` and `ENDIF` lines
+/// aren't parsed as source lines.
 #[derive(Clone, Debug, PartialEq, Eq, Parsable)]
-pub struct CommentOnlyLine(CommentSegment, WsNl);
+pub enum SourceLine {
+    // Before the others, since a label can come before `END` and `ORG`.
+    End(EndOfAssemblyLine),
+    Origin(OriginLine),
+    Statement(StatementLine),
+}
 
 #[derive(Clone, PartialEq, Eq)]
 pub struct OriginLine {
     pub label: Option<LabelSegment>,
-    keyword: Origin,
     _0: Ws,
-    pub address: WithIndex<LiteralNumber>,
-    _1: WsNl,
+    pub address: WithIndex<Expression>,
+    _1: Ws,
+    _2: Option<CommentSegment>,
+    _3: WsNl,
 }
 
 impl<'a> Parsable<'a> for OriginLine {
@@ -35,12 +36,15 @@ impl<'a> Parsable<'a> for OriginLine {
         Self: Sized
     {
         stream.scope(|stream| {
+            let label = ok_or_throw!(Option::<LabelSegment>::parse(stream)?);
+            ok_or_throw!(keyword(stream, b"ORG")?);
             Some(Ok(OriginLine {
-                label: ok_or_throw!(Option::<LabelSegment>::parse(stream)?),
-                keyword: ok_or_throw!(Origin::parse(stream)?),
+                label,
                 _0: ok_or_throw!(Ws::parse_or_error(stream)),
-                address: ok_or_throw!(WithIndex::<LiteralNumber>::parse_or_error(stream)),
-                _1: ok_or_throw!(WsNl::parse_or_error(stream)),
+                address: ok_or_throw!(WithIndex::<Expression>::parse_or_error(stream)),
+                _1: ok_or_throw!(Ws::parse_or_error(stream)),
+                _2: ok_or_throw!(Option::<CommentSegment>::parse_or_error(stream)),
+                _3: ok_or_throw!(WsNl::parse_or_error(stream)),
             }))
         })
     }
@@ -50,6 +54,105 @@ impl<'a> Parsable<'a> for OriginLine {
     }
 }
 
+impl Debug for OriginLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OriginLine")
+            .field("label", &self.label)
+            .field("address", &self.address.node)
+            .finish()
+    }
+}
+
+// Unlike instruction mnemonics, directive keywords have to be the whole name, so that a symbol
+// like `IFLAG` isn't read as `IF LAG`.
+fn keyword(stream: &mut parsable::ScopedStream, word: &[u8]) -> parsable::ParseOutcome<()> {
+    stream.scope(|stream| match Label::parse(stream)? {
+        Ok(label) if label.span == word => Some(Ok(())),
+        _ => None,
+    })
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct IfLine {
+    _0: Ws,
+    pub condition: WithIndex<Expression>,
+    _1: Option<CommentSegment>,
+    _2: WsNl,
+}
+
+impl<'a> Parsable<'a> for IfLine {
+    fn parse(stream: &mut parsable::ScopedStream<'a>) -> parsable::ParseOutcome<Self>
+    where
+        Self: Sized
+    {
+        stream.scope(|stream| {
+            ok_or_throw!(keyword(stream, b"IF")?);
+            Some(Ok(IfLine {
+                _0: ok_or_throw!(Ws::parse_or_error(stream)),
+                condition: ok_or_throw!(WithIndex::<Expression>::parse_or_error(stream)),
+                _1: ok_or_throw!(Option::<CommentSegment>::parse_or_error(stream)),
+                _2: ok_or_throw!(WsNl::parse_or_error(stream)),
+            }))
+        })
+    }
+
+    fn error() -> parsable::ParseError {
+        String::from("IfLine")
+    }
+}
+
+impl Debug for IfLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IfLine").field("condition", &self.condition.node).finish()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ElseLine(Ws, Option<CommentSegment>, WsNl);
+
+impl<'a> Parsable<'a> for ElseLine {
+    fn parse(stream: &mut parsable::ScopedStream<'a>) -> parsable::ParseOutcome<Self>
+    where
+        Self: Sized
+    {
+        stream.scope(|stream| {
+            ok_or_throw!(keyword(stream, b"ELSE")?);
+            Some(Ok(ElseLine(
+                ok_or_throw!(Ws::parse_or_error(stream)),
+                ok_or_throw!(Option::<CommentSegment>::parse_or_error(stream)),
+                ok_or_throw!(WsNl::parse_or_error(stream)),
+            )))
+        })
+    }
+
+    fn error() -> parsable::ParseError {
+        String::from("ElseLine")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EndIfLine(Ws, Option<CommentSegment>, WsNl);
+
+impl<'a> Parsable<'a> for EndIfLine {
+    fn parse(stream: &mut parsable::ScopedStream<'a>) -> parsable::ParseOutcome<Self>
+    where
+        Self: Sized
+    {
+        stream.scope(|stream| {
+            ok_or_throw!(keyword(stream, b"ENDIF")?);
+            Some(Ok(EndIfLine(
+                ok_or_throw!(Ws::parse_or_error(stream)),
+                ok_or_throw!(Option::<CommentSegment>::parse_or_error(stream)),
+                ok_or_throw!(WsNl::parse_or_error(stream)),
+            )))
+        })
+    }
+
+    fn error() -> parsable::ParseError {
+        String::from("EndIfLine")
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Parsable)]
 pub struct StatementLine {
     pub content: StatementLineContent,
@@ -58,13 +161,42 @@ pub struct StatementLine {
 
 #[derive(Clone, Debug, PartialEq, Eq, Parsable)]
 pub enum StatementLineContent {
+    // Before the others, since a symbol name can look like an instruction.
+    Definition(SymbolDefinition, Option<CommentSegment>),
     Labeled(LabelSegment, Option<StatementSegment>, Option<CommentSegment>),
     NoLabel(StatementSegment, Option<CommentSegment>),
     OnlyComment(CommentSegment),
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Parsable)]
-pub struct EndOfAssemblyLine(Option<LabelSegment>, EndOfAssembly, WsNl, EndOfStream);
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EndOfAssemblyLine {
+    pub label: Option<LabelSegment>,
+    _0: Ws,
+    _1: Option<CommentSegment>,
+    _2: WsNl,
+}
+
+impl<'a> Parsable<'a> for EndOfAssemblyLine {
+    fn parse(stream: &mut parsable::ScopedStream<'a>) -> parsable::ParseOutcome<Self>
+    where
+        Self: Sized
+    {
+        stream.scope(|stream| {
+            let label = ok_or_throw!(Option::<LabelSegment>::parse(stream)?);
+            ok_or_throw!(keyword(stream, b"END")?);
+            Some(Ok(EndOfAssemblyLine {
+                label,
+                _0: ok_or_throw!(Ws::parse_or_error(stream)),
+                _1: ok_or_throw!(Option::<CommentSegment>::parse_or_error(stream)),
+                _2: ok_or_throw!(WsNl::parse_or_error(stream)),
+            }))
+        })
+    }
+
+    fn error() -> parsable::ParseError {
+        String::from("EndOfAssemblyLine")
+    }
+}
 
 #[derive(Clone, PartialEq, Eq)]
 pub struct LabelSegment(pub WithIndex<Label>, Colon, Ws);
@@ -94,6 +226,55 @@ impl Debug for LabelSegment {
     }
 }
 
+/// `NAME EQU value` or `NAME SET value`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SymbolDefinition {
+    pub name: WithIndex<Label>,
+    _0: Ws,
+    pub kind: DefinitionKind,
+    _1: Ws,
+    pub value: WithIndex<Expression>,
+    _2: Ws,
+}
+
+impl<'a> Parsable<'a> for SymbolDefinition {
+    fn parse(stream: &mut parsable::ScopedStream<'a>) -> parsable::ParseOutcome<Self>
+    where
+        Self: Sized
+    {
+        stream.scope(|stream| {
+            Some(Ok(SymbolDefinition {
+                name: ok_or_throw!(WithIndex::<Label>::parse(stream)?),
+                _0: ok_or_throw!(Ws::parse(stream)?),
+                kind: ok_or_throw!(DefinitionKind::parse(stream)?),
+                _1: ok_or_throw!(Ws::parse_or_error(stream)),
+                value: ok_or_throw!(WithIndex::<Expression>::parse_or_error(stream)),
+                _2: ok_or_throw!(Ws::parse_or_error(stream)),
+            }))
+        })
+    }
+
+    fn error() -> parsable::ParseError {
+        String::from("SymbolDefinition")
+    }
+}
+
+impl Debug for SymbolDefinition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SymbolDefinition")
+            .field("name", &self.name.node)
+            .field("kind", &self.kind)
+            .field("value", &self.value.node)
+            .finish()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Parsable)]
+pub enum DefinitionKind {
+    Equ(Equate),
+    Set(SetSymbol),
+}
+
 #[derive(Clone, PartialEq, Eq, Parsable)]
 pub struct StatementSegment {
     pub statement: WithIndex<Statement>,
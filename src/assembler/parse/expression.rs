@@ -0,0 +1,342 @@
+//! Operand expressions, such as `TABLE+2` or `LOW(BUF)`.
+//!
+//! The grammar only splits an expression into tokens. Names are looked up and the operators are
+//! applied in `Expression::evaluate`, so that a word operator like `MOD` is only recognized when
+//! it's the whole name, and `LOWER` is still a symbol.
+
+use std::fmt::Display;
+
+use parsable::{CharLiteral, OnePlus, Parsable};
+
+use crate::assembler::labels::{Label, LabelLookup};
+use crate::assembler::parse::Ws;
+use crate::assembler::parse::literals::LiteralNumber;
+use crate::instruction::Address;
+
+/// What an expression can refer to.
+pub struct Environment<'a> {
+    pub symbols: &'a LabelLookup,
+    /// The address of the statement the expression is in, which is the value of `$`.
+    pub address: Address,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ExpressionError {
+    UnknownLabel,
+    InvalidNumber,
+    OutOfRange,
+    DivisionByZero,
+    Syntax,
+}
+
+impl Display for ExpressionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ExpressionError::UnknownLabel => "Unknown label",
+            ExpressionError::InvalidNumber => "Invalid number",
+            ExpressionError::OutOfRange => "Value out of range",
+            ExpressionError::DivisionByZero => "Division by zero",
+            ExpressionError::Syntax => "Invalid expression",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Parsable)]
+pub struct Expression {
+    tokens: OnePlus<ExpressionToken>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Parsable)]
+struct ExpressionToken(ExpressionTokenInner, Ws);
+
+#[derive(Clone, Debug, PartialEq, Eq, Parsable)]
+enum ExpressionTokenInner {
+    // Before numbers, since a name like `BUF` starts with a hexadecimal digit.
+    Name(Label),
+    Number(LiteralNumber),
+    Dollar(CharLiteral<b'$'>),
+    Plus(CharLiteral<b'+'>),
+    Minus(CharLiteral<b'-'>),
+    Times(CharLiteral<b'*'>),
+    Divide(CharLiteral<b'/'>),
+    Open(CharLiteral<b'('>),
+    Close(CharLiteral<b')'>),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Value(u16),
+    Operator(Operator),
+    Open,
+    Close,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Operator {
+    Plus,
+    Minus,
+    Times,
+    Divide,
+    Mod,
+    Shl,
+    Shr,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Not,
+    And,
+    Or,
+    Xor,
+    High,
+    Low,
+}
+
+impl Operator {
+    fn from_name(name: &[u8]) -> Option<Self> {
+        Some(match name {
+            b"MOD" => Operator::Mod,
+            b"SHL" => Operator::Shl,
+            b"SHR" => Operator::Shr,
+            b"EQ" => Operator::Equal,
+            b"NE" => Operator::NotEqual,
+            b"LT" => Operator::Less,
+            b"LE" => Operator::LessOrEqual,
+            b"GT" => Operator::Greater,
+            b"GE" => Operator::GreaterOrEqual,
+            b"NOT" => Operator::Not,
+            b"AND" => Operator::And,
+            b"OR" => Operator::Or,
+            b"XOR" => Operator::Xor,
+            b"HIGH" => Operator::High,
+            b"LOW" => Operator::Low,
+            _ => return None,
+        })
+    }
+
+    fn apply_unary(self, value: u16) -> u16 {
+        match self {
+            Operator::Plus => value,
+            Operator::Minus => value.wrapping_neg(),
+            Operator::Not => !value,
+            Operator::High => value >> 8,
+            Operator::Low => value & 0xff,
+            _ => unreachable!("{:?} isn't a unary operator", self),
+        }
+    }
+
+    fn apply_binary(self, lhs: u16, rhs: u16) -> Result<u16, ExpressionError> {
+        Ok(match self {
+            Operator::Plus => lhs.wrapping_add(rhs),
+            Operator::Minus => lhs.wrapping_sub(rhs),
+            Operator::Times => lhs.wrapping_mul(rhs),
+            Operator::Divide => lhs.checked_div(rhs).ok_or(ExpressionError::DivisionByZero)?,
+            Operator::Mod => lhs.checked_rem(rhs).ok_or(ExpressionError::DivisionByZero)?,
+            Operator::Shl => lhs.checked_shl(rhs.into()).unwrap_or(0),
+            Operator::Shr => lhs.checked_shr(rhs.into()).unwrap_or(0),
+            // Comparisons are unsigned, and true is all ones.
+            Operator::Equal => truth(lhs == rhs),
+            Operator::NotEqual => truth(lhs != rhs),
+            Operator::Less => truth(lhs < rhs),
+            Operator::LessOrEqual => truth(lhs <= rhs),
+            Operator::Greater => truth(lhs > rhs),
+            Operator::GreaterOrEqual => truth(lhs >= rhs),
+            Operator::And => lhs & rhs,
+            Operator::Or => lhs | rhs,
+            Operator::Xor => lhs ^ rhs,
+            _ => unreachable!("{:?} isn't a binary operator", self),
+        })
+    }
+}
+
+fn truth(condition: bool) -> u16 {
+    if condition { 0xffff } else { 0 }
+}
+
+impl Expression {
+    /// Evaluates the expression with 16 bit arithmetic, which wraps around like the 8080's.
+    pub fn evaluate(&self, environment: &Environment) -> Result<u16, ExpressionError> {
+        let tokens = self.lex(environment)?;
+        let mut evaluator = Evaluator { tokens: &tokens, position: 0 };
+        let value = evaluator.or()?;
+        if evaluator.position == tokens.len() {
+            Ok(value)
+        } else {
+            Err(ExpressionError::Syntax)
+        }
+    }
+
+    /// Like `evaluate`, but the value must fit in a byte. Negative values down to -128 are
+    /// allowed, so that `MVI A, -1` works.
+    pub fn evaluate_8(&self, environment: &Environment) -> Result<u8, ExpressionError> {
+        let value = self.evaluate(environment)?;
+        if value <= 0xff || value >= 0xff80 {
+            Ok(value as u8)
+        } else {
+            Err(ExpressionError::OutOfRange)
+        }
+    }
+
+    fn lex(&self, environment: &Environment) -> Result<Vec<Token>, ExpressionError> {
+        use ExpressionTokenInner as T;
+        self.tokens.nodes.iter().map(|ExpressionToken(token, _)| Ok(match token {
+            T::Name(name) => match Operator::from_name(&name.span) {
+                Some(operator) => Token::Operator(operator),
+                None => Token::Value(environment.symbols.get(name.clone())
+                    .ok_or(ExpressionError::UnknownLabel)?),
+            },
+            T::Number(number) => Token::Value(u16::try_from(number.clone())
+                .map_err(|_| ExpressionError::InvalidNumber)?),
+            T::Dollar(_) => Token::Value(environment.address),
+            T::Plus(_) => Token::Operator(Operator::Plus),
+            T::Minus(_) => Token::Operator(Operator::Minus),
+            T::Times(_) => Token::Operator(Operator::Times),
+            T::Divide(_) => Token::Operator(Operator::Divide),
+            T::Open(_) => Token::Open,
+            T::Close(_) => Token::Close,
+        })).collect()
+    }
+}
+
+// Operator precedence, from lowest to highest, as in Intel's assembler:
+// `OR` `XOR`, `AND`, `NOT`, `EQ` `NE` `LT` `LE` `GT` `GE`, binary `+` `-`, `*` `/` `MOD` `SHL` `SHR`,
+// unary `+` `-` `HIGH` `LOW`.
+struct Evaluator<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl Evaluator<'_> {
+    fn next_if(&mut self, operators: &[Operator]) -> Option<Operator> {
+        match self.tokens.get(self.position) {
+            Some(Token::Operator(operator)) if operators.contains(operator) => {
+                self.position += 1;
+                Some(*operator)
+            }
+            _ => None,
+        }
+    }
+
+    fn binary(
+        &mut self,
+        operators: &[Operator],
+        operand: fn(&mut Self) -> Result<u16, ExpressionError>,
+    ) -> Result<u16, ExpressionError> {
+        let mut value = operand(self)?;
+        while let Some(operator) = self.next_if(operators) {
+            value = operator.apply_binary(value, operand(self)?)?;
+        }
+        Ok(value)
+    }
+
+    fn or(&mut self) -> Result<u16, ExpressionError> {
+        self.binary(&[Operator::Or, Operator::Xor], Self::and)
+    }
+
+    fn and(&mut self) -> Result<u16, ExpressionError> {
+        self.binary(&[Operator::And], Self::not)
+    }
+
+    fn not(&mut self) -> Result<u16, ExpressionError> {
+        match self.next_if(&[Operator::Not]) {
+            Some(operator) => Ok(operator.apply_unary(self.not()?)),
+            None => self.comparison(),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<u16, ExpressionError> {
+        let operators = [
+            Operator::Equal,
+            Operator::NotEqual,
+            Operator::Less,
+            Operator::LessOrEqual,
+            Operator::Greater,
+            Operator::GreaterOrEqual,
+        ];
+        self.binary(&operators, Self::sum)
+    }
+
+    fn sum(&mut self) -> Result<u16, ExpressionError> {
+        self.binary(&[Operator::Plus, Operator::Minus], Self::product)
+    }
+
+    fn product(&mut self) -> Result<u16, ExpressionError> {
+        let operators = [Operator::Times, Operator::Divide, Operator::Mod, Operator::Shl, Operator::Shr];
+        self.binary(&operators, Self::unary)
+    }
+
+    fn unary(&mut self) -> Result<u16, ExpressionError> {
+        match self.next_if(&[Operator::Plus, Operator::Minus, Operator::High, Operator::Low]) {
+            Some(operator) => Ok(operator.apply_unary(self.unary()?)),
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<u16, ExpressionError> {
+        let token = self.tokens.get(self.position).ok_or(ExpressionError::Syntax)?;
+        self.position += 1;
+        match token {
+            Token::Value(value) => Ok(*value),
+            Token::Open => {
+                let value = self.or()?;
+                match self.tokens.get(self.position) {
+                    Some(Token::Close) => {
+                        self.position += 1;
+                        Ok(value)
+                    }
+                    _ => Err(ExpressionError::Syntax),
+                }
+            }
+            _ => Err(ExpressionError::Syntax),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parsable::{ScopedStream, WithEnd};
+
+    use super::*;
+
+    fn evaluate(source: &[u8]) -> Result<u16, ExpressionError> {
+        let mut symbols = LabelLookup::new();
+        let buf = Label::parse(&mut ScopedStream::new(b"BUF")).unwrap().unwrap();
+        symbols.insert(buf, 0x1234).unwrap();
+        let environment = Environment { symbols: &symbols, address: 0x100 };
+
+        let expression = WithEnd::<Expression>::parse(&mut ScopedStream::new(source))
+            .unwrap()
+            .unwrap()
+            .node;
+        expression.evaluate(&environment)
+    }
+
+    #[test]
+    fn test_expressions() {
+        assert_eq!(evaluate(b"1+2*3"), Ok(7));
+        assert_eq!(evaluate(b"(1+2)*3"), Ok(9));
+        assert_eq!(evaluate(b"BUF+2"), Ok(0x1236));
+        assert_eq!(evaluate(b"HIGH BUF"), Ok(0x12));
+        assert_eq!(evaluate(b"LOW(BUF)"), Ok(0x34));
+        assert_eq!(evaluate(b"$+3"), Ok(0x103));
+        assert_eq!(evaluate(b"-1"), Ok(0xffff));
+        assert_eq!(evaluate(b"17 MOD 5 SHL 1"), Ok(4));
+        assert_eq!(evaluate(b"NOT 0 AND 0FH OR 30H"), Ok(0x3f));
+        assert_eq!(evaluate(b"1 XOR 3"), Ok(2));
+        assert_eq!(evaluate(b"100H SHR 4 - 1"), Ok(0x0f));
+        assert_eq!(evaluate(b"BUF EQ 1234H"), Ok(0xffff));
+        assert_eq!(evaluate(b"1+1 GT 2"), Ok(0));
+        assert_eq!(evaluate(b"NOT 2 LE 1 AND 3 NE 4"), Ok(0xffff));
+    }
+
+    #[test]
+    fn test_expression_errors() {
+        assert_eq!(evaluate(b"1/0"), Err(ExpressionError::DivisionByZero));
+        assert_eq!(evaluate(b"NOWHR"), Err(ExpressionError::UnknownLabel));
+        assert_eq!(evaluate(b"(1+2"), Err(ExpressionError::Syntax));
+        assert_eq!(evaluate(b"1 2"), Err(ExpressionError::Syntax));
+        assert_eq!(evaluate(b"19Q"), Err(ExpressionError::InvalidNumber));
+    }
+}
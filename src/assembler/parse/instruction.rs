@@ -2,11 +2,11 @@ use std::ops::Deref;
 
 use parsable::Parsable;
 
-use crate::assembler::labels::{Label, LabelLookup};
-use crate::assembler::parse::literals::{LiteralNumber, LiteralString};
+use crate::assembler::parse::expression::{Environment, Expression, ExpressionError};
+use crate::assembler::parse::literals::LiteralString;
 use crate::assembler::parse::Ws;
 use crate::assembler::parse::token::*;
-use crate::instruction::{Address, Condition, Data16, Instruction, Register, RegisterPair, RegisterPairIndirect, RegisterPairOrStatus};
+use crate::instruction::{Condition, Data16, Instruction, Register, RegisterPair, RegisterPairIndirect, RegisterPairOrStatus};
 
 #[derive(Clone, Debug, PartialEq, Eq, Parsable)]
 pub enum Statement {
@@ -17,18 +17,18 @@ pub enum Statement {
 #[derive(Clone, Debug, PartialEq, Eq, Parsable)]
 pub enum LiteralStringOrNumber {
     String(LiteralString),
-    Number(LiteralNumber),
+    Number(Expression),
 }
 
 impl LiteralStringOrNumber {
-    pub fn get(self) -> Option<Box<[u8]>> {
+    pub fn get(self, environment: &Environment) -> Result<Box<[u8]>, ExpressionError> {
         match self {
             LiteralStringOrNumber::String(literal_string) => {
-                Some(literal_string.contents.span.clone().into_boxed_slice())
+                Ok(literal_string.contents.span.clone().into_boxed_slice())
             },
-            LiteralStringOrNumber::Number(literal_number) => {
-                let value: u8 = literal_number.try_into().ok()?;
-                Some(Box::new([value]))
+            LiteralStringOrNumber::Number(number) => {
+                let value = number.evaluate_8(environment)?;
+                Ok(Box::new([value]))
             },
         }
     }
@@ -37,45 +37,26 @@ impl LiteralStringOrNumber {
 #[derive(Clone, Debug, PartialEq, Eq, Parsable)]
 pub enum DataStatement {
     DefineByte(DefineByte, Ws, LiteralStringOrNumber),
-    DefineWord(DefineWord, Ws, LabelOrLiteralNumber),
-    DefineStorage(DefineStorage, Ws, LiteralNumber),
+    DefineWord(DefineWord, Ws, Expression),
+    DefineStorage(DefineStorage, Ws, Expression),
 }
 
 impl DataStatement {
-    pub fn byte_length(&self) -> Option<u16> {
+    pub fn byte_length(&self, environment: &Environment) -> Result<u16, ExpressionError> {
         match self {
             DataStatement::DefineByte(_, _, literal) => {
                 match literal {
                     LiteralStringOrNumber::String(literal_string) => {
-                        Some(literal_string.contents.span.len() as u16)
+                        Ok(literal_string.contents.span.len() as u16)
                     },
                     LiteralStringOrNumber::Number(_) => {
-                        Some(1)
+                        Ok(1)
                     },
                 }
             }
-            DataStatement::DefineWord(..) => Some(2),
-            DataStatement::DefineStorage(_, _, literal_number) => {
-                literal_number.clone().try_into().ok()
-            }
-        }
-    }
-}
-
-#[derive(Clone, Debug, PartialEq, Eq, Parsable)]
-pub enum LabelOrLiteralNumber {
-    Label(Label),
-    LiteralNumber(LiteralNumber),
-}
-
-impl LabelOrLiteralNumber {
-    pub fn get(self, label_lookup: &LabelLookup) -> Option<Address> {
-        match self {
-            LabelOrLiteralNumber::Label(label) => {
-                label_lookup.get(label)
-            }
-            LabelOrLiteralNumber::LiteralNumber(literal_number) => {
-                literal_number.try_into().ok()
+            DataStatement::DefineWord(..) => Ok(2),
+            DataStatement::DefineStorage(_, _, length) => {
+                length.evaluate(environment)
             }
         }
     }
@@ -87,92 +68,106 @@ pub struct ParsedInstruction {
 }
 
 impl ParsedInstruction {
-    pub fn into_inner(self, label_lookup: &LabelLookup) -> Option<Instruction> {
+    pub fn into_inner(self, environment: &Environment) -> Result<Instruction, ExpressionError> {
         use Instruction as I;
         use ParsedInstructionInner as PI;
         match self.inner {
-            PI::Mov(_, _, r1, _, _, _, r2) => Some(I::Mov(r1, r2)),
-            PI::Mvi(_, _, r1, _, _, _, data) => Some(I::Mvi(r1, data.try_into().ok()?)),
-            PI::Lxi(_, _, rp, _, _, _, data) => Some(I::Lxi(rp, data.get(label_lookup)?.into())),
-            PI::Lda(_, _, label) => Some(I::Lda(label_lookup.get(label)?)),
-            PI::Sta(_, _, label) => Some(I::Sta(label_lookup.get(label)?)),
-            PI::Lhld(_, _, data) => Some(I::Lhld(data.get(label_lookup)?)),
-            PI::Shld(_, _, data) => Some(I::Shld(data.get(label_lookup)?)),
-            PI::Ldax(_, _, rp) => Some(I::Ldax(rp)),
-            PI::Stax(_, _, rp) => Some(I::Stax(rp)),
-            PI::Xchg(_) => Some(I::Xchg),
+            PI::Mov(_, _, r1, _, _, _, r2) => Ok(I::Mov(r1, r2)),
+            PI::Mvi(_, _, r1, _, _, _, data) => Ok(I::Mvi(r1, data.evaluate_8(environment)?)),
+            PI::Lxi(_, _, rp, _, _, _, data) => Ok(I::Lxi(rp, data.evaluate(environment)?.into())),
+            PI::Lda(_, _, address) => Ok(I::Lda(address.evaluate(environment)?)),
+            PI::Sta(_, _, address) => Ok(I::Sta(address.evaluate(environment)?)),
+            PI::Lhld(_, _, data) => Ok(I::Lhld(data.evaluate(environment)?)),
+            PI::Shld(_, _, data) => Ok(I::Shld(data.evaluate(environment)?)),
+            PI::Ldax(_, _, rp) => Ok(I::Ldax(rp)),
+            PI::Stax(_, _, rp) => Ok(I::Stax(rp)),
+            PI::Xchg(_) => Ok(I::Xchg),
+
+            PI::Add(_, _, r1) => Ok(I::Add(r1)),
+            PI::Adi(_, _, data) => Ok(I::Adi(data.evaluate_8(environment)?)),
+            PI::Adc(_, _, r1) => Ok(I::Adc(r1)),
+            PI::Aci(_, _, data) => Ok(I::Aci(data.evaluate_8(environment)?)),
+            PI::Sub(_, _, r1) => Ok(I::Sub(r1)),
+            PI::Sui(_, _, data) => Ok(I::Sui(data.evaluate_8(environment)?)),
+            PI::Sbb(_, _, r1) => Ok(I::Sbb(r1)),
+            PI::Sbi(_, _, data) => Ok(I::Sbi(data.evaluate_8(environment)?)),
+            PI::Inr(_, _, r1) => Ok(I::Inr(r1)),
+            PI::Dcr(_, _, r1) => Ok(I::Dcr(r1)),
+            PI::Inx(_, _, rp) => Ok(I::Inx(rp)),
+            PI::Dcx(_, _, rp) => Ok(I::Dcx(rp)),
+            PI::Dad(_, _, rp) => Ok(I::Dad(rp)),
+            PI::Daa(_) => Ok(I::Daa),
 
-            PI::Add(_, _, r1) => Some(I::Add(r1)),
-            PI::Adi(_, _, data) => Some(I::Adi(data.try_into().ok()?)),
-            PI::Adc(_, _, r1) => Some(I::Adc(r1)),
-            PI::Aci(_, _, data) => Some(I::Aci(data.try_into().ok()?)),
-            PI::Sub(_, _, r1) => Some(I::Sub(r1)),
-            PI::Sui(_, _, data) => Some(I::Sui(data.try_into().ok()?)),
-            PI::Sbb(_, _, r1) => Some(I::Sbb(r1)),
-            PI::Sbi(_, _, data) => Some(I::Sbi(data.try_into().ok()?)),
-            PI::Inr(_, _, r1) => Some(I::Inr(r1)),
-            PI::Dcr(_, _, r1) => Some(I::Dcr(r1)),
-            PI::Inx(_, _, rp) => Some(I::Inx(rp)),
-            PI::Dcx(_, _, rp) => Some(I::Dcx(rp)),
-            PI::Dad(_, _, rp) => Some(I::Dad(rp)),
-            PI::Daa(_) => Some(I::Daa),
+            PI::Ana(_, _, r1) => Ok(I::Ana(r1)),
+            PI::Ani(_, _, data) => Ok(I::Ani(data.evaluate_8(environment)?)),
+            PI::Xra(_, _, r1) => Ok(I::Xra(r1)),
+            PI::Xri(_, _, data) => Ok(I::Xri(data.evaluate_8(environment)?)),
+            PI::Ora(_, _, r1) => Ok(I::Ora(r1)),
+            PI::Ori(_, _, data) => Ok(I::Ori(data.evaluate_8(environment)?)),
+            PI::Cmp(_, _, r1) => Ok(I::Cmp(r1)),
+            PI::Cpi(_, _, data) => Ok(I::Cpi(data.evaluate_8(environment)?)),
+            PI::Rlc(_) => Ok(I::Rlc),
+            PI::Rrc(_) => Ok(I::Rrc),
+            PI::Ral(_) => Ok(I::Ral),
+            PI::Rar(_) => Ok(I::Rar),
+            PI::Cma(_) => Ok(I::Cma),
+            PI::Cmc(_) => Ok(I::Cmc),
+            PI::Stc(_) => Ok(I::Stc),
 
-            PI::Ana(_, _, r1) => Some(I::Ana(r1)),
-            PI::Ani(_, _, data) => Some(I::Ani(data.try_into().ok()?)),
-            PI::Xra(_, _, r1) => Some(I::Xra(r1)),
-            PI::Xri(_, _, data) => Some(I::Xri(data.try_into().ok()?)),
-            PI::Ora(_, _, r1) => Some(I::Ora(r1)),
-            PI::Ori(_, _, data) => Some(I::Ori(data.try_into().ok()?)),
-            PI::Cmp(_, _, r1) => Some(I::Cmp(r1)),
-            PI::Cpi(_, _, data) => Some(I::Cpi(data.try_into().ok()?)),
-            PI::Rlc(_) => Some(I::Rlc),
-            PI::Rrc(_) => Some(I::Rrc),
-            PI::Ral(_) => Some(I::Ral),
-            PI::Rar(_) => Some(I::Rar),
-            PI::Cma(_) => Some(I::Cma),
-            PI::Cmc(_) => Some(I::Cmc),
-            PI::Stc(_) => Some(I::Stc),
+            PI::Jmp(_, _, address) => Ok(I::Jmp(address.evaluate(environment)?)),
+            PI::Jc(_, _, address) => Ok(I::Jcc(Condition::Carry, address.evaluate(environment)?)),
+            PI::Jnc(_, _, address) => Ok(I::Jcc(Condition::NoCarry, address.evaluate(environment)?)),
+            PI::Jz(_, _, address) => Ok(I::Jcc(Condition::Zero, address.evaluate(environment)?)),
+            PI::Jnz(_, _, address) => Ok(I::Jcc(Condition::NoZero, address.evaluate(environment)?)),
+            PI::Jp(_, _, address) => Ok(I::Jcc(Condition::Positive, address.evaluate(environment)?)),
+            PI::Jm(_, _, address) => Ok(I::Jcc(Condition::Minus, address.evaluate(environment)?)),
+            PI::Jpe(_, _, address) => Ok(I::Jcc(Condition::ParityEven, address.evaluate(environment)?)),
+            PI::Jpo(_, _, address) => Ok(I::Jcc(Condition::ParityOdd, address.evaluate(environment)?)),
+            PI::Call(_, _, address) => Ok(I::Call(address.evaluate(environment)?)),
+            PI::Cc(_, _, address) => Ok(I::Ccc(Condition::Carry, address.evaluate(environment)?)),
+            PI::Cnc(_, _, address) => Ok(I::Ccc(Condition::NoCarry, address.evaluate(environment)?)),
+            PI::Cz(_, _, address) => Ok(I::Ccc(Condition::Zero, address.evaluate(environment)?)),
+            PI::Cnz(_, _, address) => Ok(I::Ccc(Condition::NoZero, address.evaluate(environment)?)),
+            PI::Cp(_, _, address) => Ok(I::Ccc(Condition::Positive, address.evaluate(environment)?)),
+            PI::Cm(_, _, address) => Ok(I::Ccc(Condition::Minus, address.evaluate(environment)?)),
+            PI::Cpe(_, _, address) => Ok(I::Ccc(Condition::ParityEven, address.evaluate(environment)?)),
+            PI::Cpo(_, _, address) => Ok(I::Ccc(Condition::ParityOdd, address.evaluate(environment)?)),
+            PI::Ret(_) => Ok(I::Ret),
+            PI::Rc(_) => Ok(I::Rcc(Condition::Carry)),
+            PI::Rnc(_) => Ok(I::Rcc(Condition::NoCarry)),
+            PI::Rz(_) => Ok(I::Rcc(Condition::Zero)),
+            PI::Rnz(_) => Ok(I::Rcc(Condition::NoZero)),
+            PI::Rp(_) => Ok(I::Rcc(Condition::Positive)),
+            PI::Rm(_) => Ok(I::Rcc(Condition::Minus)),
+            PI::Rpe(_) => Ok(I::Rcc(Condition::ParityEven)),
+            PI::Rpo(_) => Ok(I::Rcc(Condition::ParityOdd)),
+            PI::Rst(_, _, data) => Ok(I::Rst(data.evaluate_8(environment)?.try_into()
+                .map_err(|_| ExpressionError::OutOfRange)?)),
+            PI::Pchl(_) => Ok(I::Pchl),
 
-            PI::Jmp(_, _, address) => Some(I::Jmp(address.get(label_lookup)?)),
-            PI::Jc(_, _, address) => Some(I::Jcc(Condition::Carry, address.get(label_lookup)?)),
-            PI::Jnc(_, _, address) => Some(I::Jcc(Condition::NoCarry, address.get(label_lookup)?)),
-            PI::Jz(_, _, address) => Some(I::Jcc(Condition::Zero, address.get(label_lookup)?)),
-            PI::Jnz(_, _, address) => Some(I::Jcc(Condition::NoZero, address.get(label_lookup)?)),
-            PI::Jp(_, _, address) => Some(I::Jcc(Condition::Positive, address.get(label_lookup)?)),
-            PI::Jm(_, _, address) => Some(I::Jcc(Condition::Minus, address.get(label_lookup)?)),
-            PI::Jpe(_, _, address) => Some(I::Jcc(Condition::ParityEven, address.get(label_lookup)?)),
-            PI::Jpo(_, _, address) => Some(I::Jcc(Condition::ParityOdd, address.get(label_lookup)?)),
-            PI::Call(_, _, address) => Some(I::Call(address.get(label_lookup)?)),
-            PI::Cc(_, _, address) => Some(I::Ccc(Condition::Carry, address.get(label_lookup)?)),
-            PI::Cnc(_, _, address) => Some(I::Ccc(Condition::NoCarry, address.get(label_lookup)?)),
-            PI::Cz(_, _, address) => Some(I::Ccc(Condition::Zero, address.get(label_lookup)?)),
-            PI::Cnz(_, _, address) => Some(I::Ccc(Condition::NoZero, address.get(label_lookup)?)),
-            PI::Cp(_, _, address) => Some(I::Ccc(Condition::Positive, address.get(label_lookup)?)),
-            PI::Cm(_, _, address) => Some(I::Ccc(Condition::Minus, address.get(label_lookup)?)),
-            PI::Cpe(_, _, address) => Some(I::Ccc(Condition::ParityEven, address.get(label_lookup)?)),
-            PI::Cpo(_, _, address) => Some(I::Ccc(Condition::ParityOdd, address.get(label_lookup)?)),
-            PI::Ret(_) => Some(I::Ret),
-            PI::Rc(_) => Some(I::Rcc(Condition::Carry)),
-            PI::Rnc(_) => Some(I::Rcc(Condition::NoCarry)),
-            PI::Rz(_) => Some(I::Rcc(Condition::Zero)),
-            PI::Rnz(_) => Some(I::Rcc(Condition::NoZero)),
-            PI::Rp(_) => Some(I::Rcc(Condition::Positive)),
-            PI::Rm(_) => Some(I::Rcc(Condition::Minus)),
-            PI::Rpe(_) => Some(I::Rcc(Condition::ParityEven)),
-            PI::Rpo(_) => Some(I::Rcc(Condition::ParityOdd)),
-            PI::Rst(_, _, data) => Some(I::Rst(data.try_into().ok()?)),
-            PI::Pchl(_) => Some(I::Pchl),
+            PI::Push(_, _, rp) => Ok(I::Push(rp)),
+            PI::Pop(_, _, rp) => Ok(I::Pop(rp)),
+            PI::Xthl(_) => Ok(I::Xthl),
+            PI::Sphl(_) => Ok(I::Sphl),
+            PI::Out(_, _, data) => Ok(I::Out(data.evaluate_8(environment)?)),
+            PI::In(_, _, data) => Ok(I::In(data.evaluate_8(environment)?)),
+            PI::Ei(_) => Ok(I::Ei),
+            PI::Di(_) => Ok(I::Di),
+            PI::Hlt(_) => Ok(I::Hlt),
+            PI::Nop(_) => Ok(I::Nop),
 
-            PI::Push(_, _, rp) => Some(I::Push(rp)),
-            PI::Pop(_, _, rp) => Some(I::Pop(rp)),
-            PI::Xthl(_) => Some(I::Xthl),
-            PI::Sphl(_) => Some(I::Sphl),
-            PI::Out(_, _, data) => Some(I::Out(data.try_into().ok()?)),
-            PI::In(_, _, data) => Some(I::In(data.try_into().ok()?)),
-            PI::Ei(_) => Some(I::Ei),
-            PI::Di(_) => Some(I::Di),
-            PI::Hlt(_) => Some(I::Hlt),
-            PI::Nop(_) => Some(I::Nop),
+            PI::Rim(_) => Ok(I::Rim),
+            PI::Sim(_) => Ok(I::Sim),
+            PI::Dsub(_) => Ok(I::Dsub),
+            PI::Arhl(_) => Ok(I::Arhl),
+            PI::Rdel(_) => Ok(I::Rdel),
+            PI::Ldhi(_, _, data) => Ok(I::Ldhi(data.evaluate_8(environment)?)),
+            PI::Ldsi(_, _, data) => Ok(I::Ldsi(data.evaluate_8(environment)?)),
+            PI::Shlx(_) => Ok(I::Shlx),
+            PI::Lhlx(_) => Ok(I::Lhlx),
+            PI::Jnk(_, _, address) => Ok(I::Jnk(address.evaluate(environment)?)),
+            PI::Jk(_, _, address) => Ok(I::Jk(address.evaluate(environment)?)),
+            PI::Rstv(_) => Ok(I::Rstv),
         }
     }
 
@@ -256,6 +251,18 @@ impl ParsedInstruction {
             ParsedInstructionInner::Di(..) => 1,
             ParsedInstructionInner::Hlt(..) => 1,
             ParsedInstructionInner::Nop(..) => 1,
+            ParsedInstructionInner::Rim(..) => 1,
+            ParsedInstructionInner::Sim(..) => 1,
+            ParsedInstructionInner::Dsub(..) => 1,
+            ParsedInstructionInner::Arhl(..) => 1,
+            ParsedInstructionInner::Rdel(..) => 1,
+            ParsedInstructionInner::Ldhi(..) => 2,
+            ParsedInstructionInner::Ldsi(..) => 2,
+            ParsedInstructionInner::Shlx(..) => 1,
+            ParsedInstructionInner::Lhlx(..) => 1,
+            ParsedInstructionInner::Jnk(..) => 3,
+            ParsedInstructionInner::Jk(..) => 3,
+            ParsedInstructionInner::Rstv(..) => 1,
         }
     }
 }
@@ -263,24 +270,24 @@ impl ParsedInstruction {
 #[derive(Clone, Debug, PartialEq, Eq, Parsable)]
 enum ParsedInstructionInner {
     Mov(Mov, Ws, Register, Ws, Comma, Ws, Register),
-    Mvi(Mvi, Ws, Register, Ws, Comma, Ws, LiteralNumber),
-    Lxi(Lxi, Ws, RegisterPair, Ws, Comma, Ws, LabelOrLiteralNumber),
-    Lda(Lda, Ws, Label),
-    Sta(Sta, Ws, Label),
-    Lhld(Lhld, Ws, LabelOrLiteralNumber),
-    Shld(Shld, Ws, LabelOrLiteralNumber),
+    Mvi(Mvi, Ws, Register, Ws, Comma, Ws, Expression),
+    Lxi(Lxi, Ws, RegisterPair, Ws, Comma, Ws, Expression),
+    Lda(Lda, Ws, Expression),
+    Sta(Sta, Ws, Expression),
+    Lhld(Lhld, Ws, Expression),
+    Shld(Shld, Ws, Expression),
     Ldax(Ldax, Ws, RegisterPairIndirect),
     Stax(Stax, Ws, RegisterPairIndirect),
     Xchg(Xchg),
 
     Add(Add, Ws, Register),
-    Adi(Adi, Ws, LiteralNumber),
+    Adi(Adi, Ws, Expression),
     Adc(Adc, Ws, Register),
-    Aci(Aci, Ws, LiteralNumber),
+    Aci(Aci, Ws, Expression),
     Sub(Sub, Ws, Register),
-    Sui(Sui, Ws, LiteralNumber),
+    Sui(Sui, Ws, Expression),
     Sbb(Sbb, Ws, Register),
-    Sbi(Sbi, Ws, LiteralNumber),
+    Sbi(Sbi, Ws, Expression),
     Inr(Inr, Ws, Register),
     Dcr(Dcr, Ws, Register),
     Inx(Inx, Ws, RegisterPair),
@@ -289,13 +296,13 @@ enum ParsedInstructionInner {
     Daa(Daa),
 
     Ana(Ana, Ws, Register),
-    Ani(Ani, Ws, LiteralNumber),
+    Ani(Ani, Ws, Expression),
     Xra(Xra, Ws, Register),
-    Xri(Xri, Ws, LiteralNumber),
+    Xri(Xri, Ws, Expression),
     Ora(Ora, Ws, Register),
-    Ori(Ori, Ws, LiteralNumber),
+    Ori(Ori, Ws, Expression),
     Cmp(Cmp, Ws, Register),
-    Cpi(Cpi, Ws, LiteralNumber),
+    Cpi(Cpi, Ws, Expression),
     Rlc(Rlc),
     Rrc(Rrc),
     Ral(Ral),
@@ -304,24 +311,24 @@ enum ParsedInstructionInner {
     Cmc(Cmc),
     Stc(Stc),
 
-    Jmp(Jmp, Ws, LabelOrLiteralNumber),
-    Jc(Jc, Ws, LabelOrLiteralNumber),
-    Jnc(Jnc, Ws, LabelOrLiteralNumber),
-    Jz(Jz, Ws, LabelOrLiteralNumber),
-    Jnz(Jnz, Ws, LabelOrLiteralNumber),
-    Jp(Jp, Ws, LabelOrLiteralNumber),
-    Jm(Jm, Ws, LabelOrLiteralNumber),
-    Jpe(Jpe, Ws, LabelOrLiteralNumber),
-    Jpo(Jpo, Ws, LabelOrLiteralNumber),
-    Call(Call, Ws, LabelOrLiteralNumber),
-    Cc(Cc, Ws, LabelOrLiteralNumber),
-    Cnc(Cnc, Ws, LabelOrLiteralNumber),
-    Cz(Cz, Ws, LabelOrLiteralNumber),
-    Cnz(Cnz, Ws, LabelOrLiteralNumber),
-    Cp(Cp, Ws, LabelOrLiteralNumber),
-    Cm(Cm, Ws, LabelOrLiteralNumber),
-    Cpe(Cpe, Ws, LabelOrLiteralNumber),
-    Cpo(Cpo, Ws, LabelOrLiteralNumber),
+    Jmp(Jmp, Ws, Expression),
+    Jc(Jc, Ws, Expression),
+    Jnc(Jnc, Ws, Expression),
+    Jz(Jz, Ws, Expression),
+    Jnz(Jnz, Ws, Expression),
+    Jp(Jp, Ws, Expression),
+    Jm(Jm, Ws, Expression),
+    Jpe(Jpe, Ws, Expression),
+    Jpo(Jpo, Ws, Expression),
+    Call(Call, Ws, Expression),
+    Cc(Cc, Ws, Expression),
+    Cnc(Cnc, Ws, Expression),
+    Cz(Cz, Ws, Expression),
+    Cnz(Cnz, Ws, Expression),
+    Cp(Cp, Ws, Expression),
+    Cm(Cm, Ws, Expression),
+    Cpe(Cpe, Ws, Expression),
+    Cpo(Cpo, Ws, Expression),
     Ret(Ret),
     Rc(Rc),
     Rnc(Rnc),
@@ -331,17 +338,32 @@ enum ParsedInstructionInner {
     Rm(Rm),
     Rpe(Rpe),
     Rpo(Rpo),
-    Rst(Rst, Ws, LiteralNumber),
+    // Before RST, which is a prefix of it.
+    Rstv(Rstv),
+    Rst(Rst, Ws, Expression),
     Pchl(Pchl),
 
     Push(Push, Ws, RegisterPairOrStatus),
     Pop(Pop, Ws, RegisterPairOrStatus),
     Xthl(Xthl),
     Sphl(Sphl),
-    In(In, Ws, LiteralNumber),
-    Out(Out, Ws, LiteralNumber),
+    In(In, Ws, Expression),
+    Out(Out, Ws, Expression),
     Ei(Ei),
     Di(Di),
     Hlt(Hlt),
     Nop(Nop),
+
+    // 8085 only
+    Rim(Rim),
+    Sim(Sim),
+    Dsub(Dsub),
+    Arhl(Arhl),
+    Rdel(Rdel),
+    Ldhi(Ldhi, Ws, Expression),
+    Ldsi(Ldsi, Ws, Expression),
+    Shlx(Shlx),
+    Lhlx(Lhlx),
+    Jnk(Jnk, Ws, Expression),
+    Jk(Jk, Ws, Expression),
 }
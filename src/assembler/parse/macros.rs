@@ -0,0 +1,133 @@
+//! The lines which define and call macros, and which include files. These are recognized one line
+//! at a time while macros are expanded, before the rest of the source is parsed.
+
+use parsable::{EndOfStream, Parsable, ZeroPlus, ok_or_throw};
+
+use crate::assembler::labels::Label;
+use crate::assembler::parse::{CommentSegment, LabelSegment, Ws, WsNl, keyword, literals::LiteralString, token::Comma};
+
+/// `NAME MACRO PARAM, ...`, the first line of a macro definition.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MacroHeader {
+    pub name: Label,
+    pub parameters: Vec<Label>,
+}
+
+impl<'a> Parsable<'a> for MacroHeader {
+    fn parse(stream: &mut parsable::ScopedStream<'a>) -> parsable::ParseOutcome<Self>
+    where
+        Self: Sized
+    {
+        stream.scope(|stream| {
+            let name = ok_or_throw!(Label::parse(stream)?);
+            ok_or_throw!(Ws::parse(stream)?);
+            ok_or_throw!(keyword(stream, b"MACRO")?);
+            ok_or_throw!(Ws::parse_or_error(stream));
+            let parameters = ok_or_throw!(Option::<NameList>::parse_or_error(stream));
+            ok_or_throw!(EndOfLine::parse_or_error(stream));
+            Some(Ok(MacroHeader {
+                name,
+                parameters: parameters.map_or_else(Vec::new, NameList::into_names),
+            }))
+        })
+    }
+
+    fn error() -> parsable::ParseError {
+        String::from("MacroHeader")
+    }
+}
+
+/// `LOCAL NAME, ...`, which follows the `MACRO` line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LocalLine {
+    pub names: Vec<Label>,
+}
+
+impl<'a> Parsable<'a> for LocalLine {
+    fn parse(stream: &mut parsable::ScopedStream<'a>) -> parsable::ParseOutcome<Self>
+    where
+        Self: Sized
+    {
+        stream.scope(|stream| {
+            ok_or_throw!(keyword(stream, b"LOCAL")?);
+            ok_or_throw!(Ws::parse_or_error(stream));
+            let names = ok_or_throw!(NameList::parse_or_error(stream));
+            ok_or_throw!(EndOfLine::parse_or_error(stream));
+            Some(Ok(LocalLine { names: names.into_names() }))
+        })
+    }
+
+    fn error() -> parsable::ParseError {
+        String::from("LocalLine")
+    }
+}
+
+/// `ENDM`, the last line of a macro definition.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EndmLine;
+
+impl<'a> Parsable<'a> for EndmLine {
+    fn parse(stream: &mut parsable::ScopedStream<'a>) -> parsable::ParseOutcome<Self>
+    where
+        Self: Sized
+    {
+        stream.scope(|stream| {
+            ok_or_throw!(keyword(stream, b"ENDM")?);
+            ok_or_throw!(EndOfLine::parse_or_error(stream));
+            Some(Ok(EndmLine))
+        })
+    }
+
+    fn error() -> parsable::ParseError {
+        String::from("EndmLine")
+    }
+}
+
+/// `INCLUDE 'FILE'`, which is replaced by the lines of the file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IncludeLine {
+    pub file: LiteralString,
+}
+
+impl<'a> Parsable<'a> for IncludeLine {
+    fn parse(stream: &mut parsable::ScopedStream<'a>) -> parsable::ParseOutcome<Self>
+    where
+        Self: Sized
+    {
+        stream.scope(|stream| {
+            ok_or_throw!(keyword(stream, b"INCLUDE")?);
+            ok_or_throw!(Ws::parse_or_error(stream));
+            let file = ok_or_throw!(LiteralString::parse_or_error(stream));
+            ok_or_throw!(EndOfLine::parse_or_error(stream));
+            Some(Ok(IncludeLine { file }))
+        })
+    }
+
+    fn error() -> parsable::ParseError {
+        String::from("IncludeLine")
+    }
+}
+
+/// The start of a line which may be a macro call, up to the name of the macro. Whether it is one
+/// depends on whether a macro with that name has been defined.
+#[derive(Clone, Debug, PartialEq, Eq, Parsable)]
+pub struct MacroCallStart {
+    pub label: Option<LabelSegment>,
+    pub name: Label,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Parsable)]
+struct NameList(Label, Ws, ZeroPlus<MoreNames>);
+
+#[derive(Clone, Debug, PartialEq, Eq, Parsable)]
+struct MoreNames(Comma, Ws, Label, Ws);
+
+impl NameList {
+    fn into_names(self) -> Vec<Label> {
+        let NameList(first, _, rest) = self;
+        std::iter::once(first).chain(rest.nodes.into_iter().map(|MoreNames(_, _, name, _)| name)).collect()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Parsable)]
+struct EndOfLine(Ws, Option<CommentSegment>, WsNl, EndOfStream);
@@ -3,11 +3,11 @@ parsable::literals! {
     pub struct Colon = b":";
     pub struct Semicolon = b";";
 
-    pub struct Origin = b"ORG";
-    pub struct EndOfAssembly = b"END";
     pub struct DefineByte = b"DB";
     pub struct DefineWord = b"DW";
     pub struct DefineStorage = b"DS";
+    pub struct Equate = b"EQU";
+    pub struct SetSymbol = b"SET";
 
     pub struct Mov = b"MOV";
     pub struct Mvi = b"MVI";
@@ -91,4 +91,17 @@ parsable::literals! {
     pub struct Di = b"DI";
     pub struct Hlt = b"HLT";
     pub struct Nop = b"NOP";
+
+    pub struct Rim = b"RIM";
+    pub struct Sim = b"SIM";
+    pub struct Dsub = b"DSUB";
+    pub struct Arhl = b"ARHL";
+    pub struct Rdel = b"RDEL";
+    pub struct Ldhi = b"LDHI";
+    pub struct Ldsi = b"LDSI";
+    pub struct Shlx = b"SHLX";
+    pub struct Lhlx = b"LHLX";
+    pub struct Jnk = b"JNK";
+    pub struct Jk = b"JK";
+    pub struct Rstv = b"RSTV";
 }
@@ -1,9 +1,25 @@
-use std::{fs, io, path};
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path,
+    process::ExitCode,
+};
 
 use anyhow::anyhow;
 use clap::Parser;
 
-use crate::{assembler, coding, machine::Machine, ui};
+use crate::{
+    assembler,
+    clock::ClockSpeed,
+    cpm::Cpm,
+    headless,
+    instruction::Cpu,
+    machine::{
+        HaltReason, Machine,
+        trace::{TraceFormat, TraceSink, TraceWriter},
+    },
+    ui,
+};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -14,12 +30,72 @@ struct Args {
     binary: Option<path::PathBuf>,
     #[arg(long)]
     assembly: Option<path::PathBuf>,
+    /// Write a listing of the program loaded with --assembly to the specified file, with the
+    /// address and machine code of each line.
+    #[arg(long, requires = "assembly")]
+    listing: Option<path::PathBuf>,
+    /// CPU to emulate and assemble for: '8080' or '8085'. A snapshot restores the CPU it was
+    /// saved with.
+    #[arg(long, default_value_t = Cpu::Intel8080)]
+    cpu: Cpu,
+    /// Run the program to completion without the interactive UI. Output is written directly to
+    /// stdout, and the exit status reflects the reason the machine halted.
+    #[arg(long)]
+    headless: bool,
+    /// Halt the machine when the stack pointer or a 16-bit memory access wraps around the end of
+    /// memory, instead of wrapping like the real CPU.
+    #[arg(long)]
+    sanitize: bool,
+    /// Halt the machine on the undocumented 8080 opcodes, instead of executing them as the NOP,
+    /// JMP, RET or CALL they're aliases of.
+    #[arg(long)]
+    strict_opcodes: bool,
+    /// Seed for the random numbers read from port 1, which makes runs reproducible. By default
+    /// the seed is random. Overrides the random state restored from a snapshot.
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Cache decoded instructions, which speeds up programs that spend their time in loops.
+    #[arg(long)]
+    instruction_cache: bool,
+    /// Clock speed to run the machine at: 'unthrottled', a frequency such as '2MHz' or '500kHz',
+    /// or a multiple of the original 2 MHz such as '4x'.
+    #[arg(long, default_value_t = ClockSpeed::Unthrottled)]
+    clock: ClockSpeed,
+    /// Restore the machine from a snapshot file before running it.
+    #[arg(long)]
+    load_snapshot: Option<path::PathBuf>,
+    /// Write a snapshot of the machine to the specified file when it halts in headless mode, or
+    /// when pressing S in the interactive UI.
+    #[arg(long)]
+    save_snapshot: Option<path::PathBuf>,
+    /// Number of executed instructions which can be stepped back in the interactive UI.
+    #[arg(long, default_value_t = 10_000)]
+    history: usize,
+    /// Run a CP/M .COM program, loaded at 0x0100 with calls to CP/M handled by the emulator.
+    /// Implies --headless.
+    #[arg(long)]
+    cpm: Option<path::PathBuf>,
+    /// Directory which CP/M programs access as drive A:.
+    #[arg(long, default_value = ".")]
+    cpm_directory: path::PathBuf,
+    /// Arguments passed to the CP/M program.
+    #[arg(requires = "cpm", trailing_var_arg = true)]
+    cpm_arguments: Vec<String>,
+    /// Write a trace of every executed instruction to the specified file. Specify '-' to write to
+    /// stderr, which is only allowed in headless mode.
+    #[arg(long)]
+    trace: Option<path::PathBuf>,
+    /// Format of the trace: 'text' for one readable line per instruction, or 'json' for one JSON
+    /// object per line.
+    #[arg(long, default_value_t = TraceFormat::Text)]
+    trace_format: TraceFormat,
 }
 
-pub fn start() -> anyhow::Result<()> {
+pub fn start() -> anyhow::Result<ExitCode> {
     let args = Args::parse();
     
     let mut machine = Machine::new();
+    machine.set_cpu(args.cpu);
 
     if let Some(path) = args.binary {
         let mut file: Box<dyn io::Read> = if path.to_str() == Some("-") {
@@ -36,37 +112,128 @@ pub fn start() -> anyhow::Result<()> {
         file.read_to_end(&mut buf)?;
         
         if machine.memory_mut().write_slice(0, &buf).is_none() {
-            return Err(anyhow!("Program doesn't fit in memory. Must be smaller than 256 Kib (65536 bytes)."));
+            return Err(anyhow!("Program doesn't fit in memory. Must fit in 64 KiB (65536 bytes)."));
             
         }
     }
     
     if let Some(path) = args.assembly {
-        let mut file: Box<dyn io::Read> = if path.to_str() == Some("-") {
-            Box::new(io::stdin())
+        // Files included from stdin are relative to the current directory.
+        let program = if path.to_str() == Some("-") {
+            let mut buf = Vec::new();
+            io::stdin().read_to_end(&mut buf)?;
+            assembler::assemble(&buf, args.cpu)
         } else {
-            Box::new(
-                fs::OpenOptions::new()
-                    .read(true)
-                    .open(path)?,
-            )
-        };
-
-        let mut buf = Vec::new();
-        file.read_to_end(&mut buf)?;
-        
-        let (instructions, base_addr) = assembler::parse_assembly(&buf).map_err(|err| anyhow!("{}", err))?;
+            assembler::assemble_file(&path, args.cpu)
+        }
+        .map_err(|err| anyhow!("{}", err))?;
         
-        let mut program = Vec::new();
-        coding::encode_program(&mut program, &instructions)?;
+        if let Some(path) = &args.listing {
+            fs::write(path, program.listing.to_string())?;
+        }
         
-        if machine.memory_mut().write_slice(base_addr, &program).is_none() {
-            return Err(anyhow!("Program doesn't fit in memory. It is {} bytes large, but must be smaller than 256 Kib (65536 bytes).", program.len()));
+        if machine.memory_mut().write_slice(program.origin, &program.bytes).is_none() {
+            return Err(anyhow!("Program doesn't fit in memory. It is {} bytes large, but must fit in 64 KiB (65536 bytes).", program.bytes.len()));
             
         }
     }
     
-    ui::start(machine)?;
+    let mut cpm = None;
+    if let Some(path) = &args.cpm {
+        let program = fs::read(path)?;
+        let mut system = Cpm::new(&args.cpm_directory);
+        system
+            .load(&mut machine, &program, &args.cpm_arguments)
+            .map_err(|err| anyhow!("Couldn't load {}: {}", path.display(), err))?;
+        cpm = Some(system);
+    }
+
+    if let Some(path) = &args.load_snapshot {
+        let mut file = io::BufReader::new(fs::File::open(path)?);
+        machine
+            .load_snapshot(&mut file)
+            .map_err(|err| anyhow!("Couldn't load snapshot {}: {}", path.display(), err))?;
+    }
+
+    // Set after loading a snapshot, which contains the sanitizer mode and random state.
+    if args.sanitize {
+        machine.set_sanitizer(true);
+    }
+
+    if let Some(seed) = args.seed {
+        machine.set_random_seed(seed);
+    }
+
+    if args.strict_opcodes {
+        machine.set_strict_opcodes(true);
+    }
+
+    if args.instruction_cache {
+        machine.memory_mut().set_instruction_cache(true);
+    }
+
+    let run_headless = args.headless || cpm.is_some();
+
+    if let Some(path) = &args.trace {
+        if path.to_str() == Some("-") && !run_headless {
+            return Err(anyhow!("Can't write a trace to stderr in the interactive UI"));
+        }
+        machine.set_trace_sink(Some(open_trace(path, args.trace_format, run_headless)?));
+    }
 
+    if run_headless {
+        let exit = headless::start(&mut machine, args.clock, cpm.as_mut())?;
+        match exit {
+            headless::Exit::Halted(HaltReason::HaltInstruction) => {}
+            headless::Exit::Halted(_) => {
+                if let Some(diagnostics) = machine.halt_diagnostics() {
+                    eprintln!("Machine halted: {}", diagnostics);
+                }
+            }
+            headless::Exit::WaitingForInterrupt => eprintln!(
+                "Machine halted: Waiting for an interrupt at PC={:04X}H, which can't be raised in headless mode",
+                machine.pc().value()
+            ),
+        }
+        if let Some(mut trace) = machine.set_trace_sink(None) {
+            trace
+                .finish()
+                .map_err(|err| anyhow!("Couldn't write trace: {}", err))?;
+        }
+        if let Some(path) = &args.save_snapshot {
+            save_snapshot(&machine, path)?;
+        }
+        return Ok(ExitCode::from(exit.exit_code()));
+    }
+
+    machine.set_history_capacity(args.history);
+    ui::start(machine, args.clock, args.save_snapshot)?;
+
+    Ok(ExitCode::SUCCESS)
+}
+
+pub fn save_snapshot(machine: &Machine, path: &path::Path) -> anyhow::Result<()> {
+    let mut file = io::BufWriter::new(fs::File::create(path)?);
+    machine.save_snapshot(&mut file)?;
+    file.flush()?;
     Ok(())
 }
+
+// The UI never returns control to flush buffered output, so the trace is flushed line by line.
+fn open_trace(
+    path: &path::Path,
+    format: TraceFormat,
+    headless: bool,
+) -> anyhow::Result<Box<dyn TraceSink>> {
+    let file: Box<dyn Write + Send> = if path.to_str() == Some("-") {
+        Box::new(io::stderr())
+    } else {
+        Box::new(fs::File::create(path)?)
+    };
+
+    if headless {
+        Ok(Box::new(TraceWriter::new(io::BufWriter::new(file), format)))
+    } else {
+        Ok(Box::new(TraceWriter::new(io::LineWriter::new(file), format)))
+    }
+}
@@ -0,0 +1,166 @@
+use std::{
+    fmt::Display,
+    str::FromStr,
+    thread,
+    time::{Duration, Instant},
+};
+
+// Sleeping for shorter than this isn't precise, so the machine is allowed to run slightly ahead.
+static MIN_SLEEP: Duration = Duration::from_millis(2);
+// If the machine falls further behind than this, e.g. because the host was busy, it doesn't try
+// to catch up.
+static MAX_LAG: Duration = Duration::from_millis(100);
+
+/// The rate at which the machine executes T-states.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ClockSpeed {
+    Unthrottled,
+    /// Frequency in Hz, i.e. T-states per second.
+    Hz(f64),
+}
+
+impl ClockSpeed {
+    /// The clock frequency of the original Intel 8080.
+    pub const INTEL_8080_HZ: f64 = 2_000_000.0;
+
+    /// A multiple of the clock frequency of the original Intel 8080.
+    pub fn multiplier(multiplier: f64) -> Self {
+        ClockSpeed::Hz(Self::INTEL_8080_HZ * multiplier)
+    }
+}
+
+impl Display for ClockSpeed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClockSpeed::Unthrottled => write!(f, "unthrottled"),
+            ClockSpeed::Hz(hz) => write!(f, "{}Hz", hz),
+        }
+    }
+}
+
+/// Parses `unthrottled`, a frequency such as `2MHz`, `500kHz` or `1000Hz`, or a multiple of the
+/// original 2 MHz such as `4x`. Units are case insensitive.
+impl FromStr for ClockSpeed {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase();
+        if s == "unthrottled" {
+            return Ok(ClockSpeed::Unthrottled);
+        }
+
+        let (number, scale, is_multiplier) = if let Some(number) = s.strip_suffix("mhz") {
+            (number, 1_000_000.0, false)
+        } else if let Some(number) = s.strip_suffix("khz") {
+            (number, 1_000.0, false)
+        } else if let Some(number) = s.strip_suffix("hz") {
+            (number, 1.0, false)
+        } else if let Some(number) = s.strip_suffix("x") {
+            (number, 1.0, true)
+        } else {
+            (s.as_str(), 1.0, false)
+        };
+
+        let number: f64 = number
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid clock speed '{}'", s))?;
+        if !number.is_finite() || number <= 0.0 {
+            return Err(format!("Clock speed must be positive, got '{}'", s));
+        }
+
+        if is_multiplier {
+            Ok(ClockSpeed::multiplier(number))
+        } else {
+            Ok(ClockSpeed::Hz(number * scale))
+        }
+    }
+}
+
+/// Keeps a running machine in step with real time, by sleeping whenever it gets ahead of its clock
+/// speed.
+pub struct Throttle {
+    speed: ClockSpeed,
+    start: Instant,
+    // T-states executed since `start`.
+    cycles: u64,
+}
+
+impl Throttle {
+    pub fn new(speed: ClockSpeed) -> Self {
+        Self {
+            speed,
+            start: Instant::now(),
+            cycles: 0,
+        }
+    }
+
+    pub fn speed(&self) -> ClockSpeed {
+        self.speed
+    }
+
+    /// Restarts the timing from now. Should be called when the machine resumes after being paused,
+    /// so that it doesn't try to catch up on the time it wasn't running.
+    pub fn reset(&mut self) {
+        self.start = Instant::now();
+        self.cycles = 0;
+    }
+
+    /// Accounts for `cycles` T-states having been executed, and sleeps if the machine is now ahead
+    /// of real time.
+    pub fn wait(&mut self, cycles: u32) {
+        if let Some(delay) = self.delay(cycles, self.start.elapsed()) {
+            thread::sleep(delay);
+        }
+    }
+
+    // Accounts for `cycles` T-states, and returns how long to sleep when `elapsed` has passed
+    // since `start`.
+    fn delay(&mut self, cycles: u32, elapsed: Duration) -> Option<Duration> {
+        let ClockSpeed::Hz(hz) = self.speed else {
+            return None;
+        };
+        self.cycles += cycles as u64;
+
+        let target = Duration::from_secs_f64(self.cycles as f64 / hz);
+        if target > elapsed + MIN_SLEEP {
+            return Some(target - elapsed);
+        } else if elapsed > target + MAX_LAG {
+            self.reset();
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_clock_speed() {
+        assert_eq!("unthrottled".parse(), Ok(ClockSpeed::Unthrottled));
+        assert_eq!("2MHz".parse(), Ok(ClockSpeed::Hz(2_000_000.0)));
+        assert_eq!("3.125mhz".parse(), Ok(ClockSpeed::Hz(3_125_000.0)));
+        assert_eq!("500kHz".parse(), Ok(ClockSpeed::Hz(500_000.0)));
+        assert_eq!("1000".parse(), Ok(ClockSpeed::Hz(1_000.0)));
+        assert_eq!("0.5x".parse(), Ok(ClockSpeed::Hz(1_000_000.0)));
+        assert!("fast".parse::<ClockSpeed>().is_err());
+        assert!("0Hz".parse::<ClockSpeed>().is_err());
+    }
+
+    #[test]
+    fn test_throttle() {
+        let mut throttle = Throttle::new(ClockSpeed::Hz(1_000.0));
+        assert_eq!(throttle.delay(4, Duration::ZERO), Some(Duration::from_millis(4)));
+        assert_eq!(throttle.delay(4, Duration::from_millis(5)), Some(Duration::from_millis(3)));
+        // Too short to sleep for.
+        assert_eq!(throttle.delay(1, Duration::from_millis(8)), None);
+
+        // Lagging too far behind restarts the timing instead of catching up.
+        assert_eq!(throttle.delay(4, Duration::from_millis(500)), None);
+        assert_eq!(throttle.delay(4, Duration::ZERO), Some(Duration::from_millis(4)));
+
+        let mut unthrottled = Throttle::new(ClockSpeed::Unthrottled);
+        assert_eq!(unthrottled.delay(1_000_000, Duration::ZERO), None);
+    }
+}
@@ -1,14 +1,16 @@
 use std::io::{self, Write};
 
 use crate::{
-    coding::reader::Reader,
-    instruction::{Instruction, InstructionOrData},
+    coding::{reader::Reader, table::Opcode},
+    instruction::{Cpu, Instruction, InstructionOrData},
 };
 
 mod decode;
 mod encode;
 pub mod reader;
+pub mod table;
 
+/// Writes the machine code of `items` to `buffer`, in order.
 pub fn encode_program(buffer: &mut impl Write, items: &[InstructionOrData]) -> io::Result<()> {
     for item in items {
         match item {
@@ -27,6 +29,7 @@ pub fn encode_program(buffer: &mut impl Write, items: &[InstructionOrData]) -> i
     Ok(())
 }
 
+/// Writes the machine code of a single instruction to `buffer`.
 pub fn encode(buffer: &mut impl Write, instruction: Instruction) -> std::io::Result<()> {
     match instruction {
         Instruction::Mov(register, register1) => encode::encode_mov(buffer, register, register1),
@@ -96,10 +99,34 @@ pub fn encode(buffer: &mut impl Write, instruction: Instruction) -> std::io::Res
         Instruction::Di => encode::encode_di(buffer),
         Instruction::Hlt => encode::encode_hlt(buffer),
         Instruction::Nop => encode::encode_nop(buffer),
+        Instruction::Rim => encode::encode_rim(buffer),
+        Instruction::Sim => encode::encode_sim(buffer),
+        Instruction::Dsub => encode::encode_dsub(buffer),
+        Instruction::Arhl => encode::encode_arhl(buffer),
+        Instruction::Rdel => encode::encode_rdel(buffer),
+        Instruction::Ldhi(data) => encode::encode_ldhi(buffer, data),
+        Instruction::Ldsi(data) => encode::encode_ldsi(buffer, data),
+        Instruction::Shlx => encode::encode_shlx(buffer),
+        Instruction::Lhlx => encode::encode_lhlx(buffer),
+        Instruction::Jnk(addr) => encode::encode_jnk(buffer, addr),
+        Instruction::Jk(addr) => encode::encode_jk(buffer, addr),
+        Instruction::Rstv => encode::encode_rstv(buffer),
     }
 }
 
-pub fn decode<'a>(stream: &mut Reader<'a>) -> Option<Instruction> {
+/// Decodes the instruction at the start of `stream` and advances past it, using the opcode table
+/// of `cpu`. Also returns the opcode it was decoded from, whose `alias_of` is the documented
+/// opcode for an undocumented 8080 opcode.
+pub fn decode<'a>(stream: &mut Reader<'a>, cpu: Cpu) -> Option<(Instruction, &'static Opcode)> {
+    let opcode = table::opcode(cpu, stream.peek()?)?;
+    let bytes = stream.peek_n(opcode.length as usize)?;
+    stream.skip_n(opcode.length as usize);
+    Some((opcode.decode(bytes), opcode))
+}
+
+// Matches the opcode against every instruction encoding of `cpu` in turn. Only used to build the
+// opcode tables.
+fn decode_masked<'a>(stream: &mut Reader<'a>, cpu: Cpu) -> Option<Instruction> {
     None.or_else(|| decode::parse_mov(stream))
         .or_else(|| decode::parse_mvi(stream))
         .or_else(|| decode::parse_lxi(stream))
@@ -157,4 +184,23 @@ pub fn decode<'a>(stream: &mut Reader<'a>) -> Option<Instruction> {
         .or_else(|| decode::parse_di(stream))
         .or_else(|| decode::parse_hlt(stream))
         .or_else(|| decode::parse_nop(stream))
+        .or_else(|| match cpu {
+            Cpu::Intel8080 => None,
+            Cpu::Intel8085 => decode_masked_8085(stream),
+        })
+}
+
+fn decode_masked_8085<'a>(stream: &mut Reader<'a>) -> Option<Instruction> {
+    None.or_else(|| decode::parse_rim(stream))
+        .or_else(|| decode::parse_sim(stream))
+        .or_else(|| decode::parse_dsub(stream))
+        .or_else(|| decode::parse_arhl(stream))
+        .or_else(|| decode::parse_rdel(stream))
+        .or_else(|| decode::parse_ldhi(stream))
+        .or_else(|| decode::parse_ldsi(stream))
+        .or_else(|| decode::parse_shlx(stream))
+        .or_else(|| decode::parse_lhlx(stream))
+        .or_else(|| decode::parse_jnk(stream))
+        .or_else(|| decode::parse_jk(stream))
+        .or_else(|| decode::parse_rstv(stream))
 }
@@ -600,7 +600,7 @@ pub fn parse_cma<'a>(stream: &mut Reader<'a>) -> Option<Instruction> {
     static LEN: usize = 1;
     let bytes = stream.peek_n(LEN)?;
     let opcode = bytes[0];
-    if !is_eq_masked(opcode, 0b0010_1010, 0b1111_1111) {
+    if !is_eq_masked(opcode, 0b0010_1111, 0b1111_1111) {
         return None;
     };
 
@@ -815,7 +815,7 @@ pub fn parse_xthl<'a>(stream: &mut Reader<'a>) -> Option<Instruction> {
 
     stream.skip_n(LEN);
 
-    return Some(Instruction::Xchg);
+    return Some(Instruction::Xthl);
 }
 
 pub fn parse_sphl<'a>(stream: &mut Reader<'a>) -> Option<Instruction> {
@@ -828,7 +828,7 @@ pub fn parse_sphl<'a>(stream: &mut Reader<'a>) -> Option<Instruction> {
 
     stream.skip_n(LEN);
 
-    return Some(Instruction::Xchg);
+    return Some(Instruction::Sphl);
 }
 
 pub fn parse_in<'a>(stream: &mut Reader<'a>) -> Option<Instruction> {
@@ -914,13 +914,200 @@ pub fn parse_nop<'a>(stream: &mut Reader<'a>) -> Option<Instruction> {
 }
 
 
+// 8085 instructions, which use opcodes the 8080 leaves undefined.
+
+pub fn parse_rim<'a>(stream: &mut Reader<'a>) -> Option<Instruction> {
+    static LEN: usize = 1;
+    let bytes = stream.peek_n(LEN)?;
+    let opcode = bytes[0];
+    if !is_eq_masked(opcode, 0b0010_0000, 0b1111_1111) {
+        return None;
+    };
+
+    stream.skip_n(LEN);
+
+    Some(Instruction::Rim)
+}
+
+pub fn parse_sim<'a>(stream: &mut Reader<'a>) -> Option<Instruction> {
+    static LEN: usize = 1;
+    let bytes = stream.peek_n(LEN)?;
+    let opcode = bytes[0];
+    if !is_eq_masked(opcode, 0b0011_0000, 0b1111_1111) {
+        return None;
+    };
+
+    stream.skip_n(LEN);
+
+    Some(Instruction::Sim)
+}
+
+pub fn parse_dsub<'a>(stream: &mut Reader<'a>) -> Option<Instruction> {
+    static LEN: usize = 1;
+    let bytes = stream.peek_n(LEN)?;
+    let opcode = bytes[0];
+    if !is_eq_masked(opcode, 0b0000_1000, 0b1111_1111) {
+        return None;
+    };
+
+    stream.skip_n(LEN);
+
+    Some(Instruction::Dsub)
+}
+
+pub fn parse_arhl<'a>(stream: &mut Reader<'a>) -> Option<Instruction> {
+    static LEN: usize = 1;
+    let bytes = stream.peek_n(LEN)?;
+    let opcode = bytes[0];
+    if !is_eq_masked(opcode, 0b0001_0000, 0b1111_1111) {
+        return None;
+    };
+
+    stream.skip_n(LEN);
+
+    Some(Instruction::Arhl)
+}
+
+pub fn parse_rdel<'a>(stream: &mut Reader<'a>) -> Option<Instruction> {
+    static LEN: usize = 1;
+    let bytes = stream.peek_n(LEN)?;
+    let opcode = bytes[0];
+    if !is_eq_masked(opcode, 0b0001_1000, 0b1111_1111) {
+        return None;
+    };
+
+    stream.skip_n(LEN);
+
+    Some(Instruction::Rdel)
+}
+
+pub fn parse_ldhi<'a>(stream: &mut Reader<'a>) -> Option<Instruction> {
+    static LEN: usize = 2;
+    let bytes = stream.peek_n(LEN)?;
+    let opcode = bytes[0];
+    if !is_eq_masked(opcode, 0b0010_1000, 0b1111_1111) {
+        return None;
+    };
+
+    let data = bytes[1];
+
+    stream.skip_n(LEN);
+
+    Some(Instruction::Ldhi(data))
+}
+
+pub fn parse_ldsi<'a>(stream: &mut Reader<'a>) -> Option<Instruction> {
+    static LEN: usize = 2;
+    let bytes = stream.peek_n(LEN)?;
+    let opcode = bytes[0];
+    if !is_eq_masked(opcode, 0b0011_1000, 0b1111_1111) {
+        return None;
+    };
+
+    let data = bytes[1];
+
+    stream.skip_n(LEN);
+
+    Some(Instruction::Ldsi(data))
+}
+
+pub fn parse_shlx<'a>(stream: &mut Reader<'a>) -> Option<Instruction> {
+    static LEN: usize = 1;
+    let bytes = stream.peek_n(LEN)?;
+    let opcode = bytes[0];
+    if !is_eq_masked(opcode, 0b1101_1001, 0b1111_1111) {
+        return None;
+    };
+
+    stream.skip_n(LEN);
+
+    Some(Instruction::Shlx)
+}
+
+pub fn parse_lhlx<'a>(stream: &mut Reader<'a>) -> Option<Instruction> {
+    static LEN: usize = 1;
+    let bytes = stream.peek_n(LEN)?;
+    let opcode = bytes[0];
+    if !is_eq_masked(opcode, 0b1110_1101, 0b1111_1111) {
+        return None;
+    };
+
+    stream.skip_n(LEN);
+
+    Some(Instruction::Lhlx)
+}
+
+pub fn parse_jnk<'a>(stream: &mut Reader<'a>) -> Option<Instruction> {
+    static LEN: usize = 3;
+    let bytes = stream.peek_n(LEN)?;
+    let opcode = bytes[0];
+    if !is_eq_masked(opcode, 0b1101_1101, 0b1111_1111) {
+        return None;
+    };
+
+    let data = Data16::new(bytes[1], bytes[2]);
+
+    stream.skip_n(LEN);
+
+    Some(Instruction::Jnk(data.into()))
+}
+
+pub fn parse_jk<'a>(stream: &mut Reader<'a>) -> Option<Instruction> {
+    static LEN: usize = 3;
+    let bytes = stream.peek_n(LEN)?;
+    let opcode = bytes[0];
+    if !is_eq_masked(opcode, 0b1111_1101, 0b1111_1111) {
+        return None;
+    };
+
+    let data = Data16::new(bytes[1], bytes[2]);
+
+    stream.skip_n(LEN);
+
+    Some(Instruction::Jk(data.into()))
+}
+
+pub fn parse_rstv<'a>(stream: &mut Reader<'a>) -> Option<Instruction> {
+    static LEN: usize = 1;
+    let bytes = stream.peek_n(LEN)?;
+    let opcode = bytes[0];
+    if !is_eq_masked(opcode, 0b1100_1011, 0b1111_1111) {
+        return None;
+    };
+
+    stream.skip_n(LEN);
+
+    Some(Instruction::Rstv)
+}
+
+
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::coding::encode;
 
     #[test]
     fn test_extract_bits() {
         assert_eq!(extract_bits(0b1101_0011, 2..6), 0b0100)
     }
+
+    #[test]
+    fn test_cma_xthl_sphl() {
+        // CMA used to be decoded from and encoded as 0x2A, which is LHLD, and XTHL and SPHL used
+        // to be decoded as XCHG.
+        assert_eq!(parse_cma(&mut Reader::new(&[0x2F])), Some(Instruction::Cma));
+        assert_eq!(parse_cma(&mut Reader::new(&[0x2A])), None);
+        assert_eq!(parse_xthl(&mut Reader::new(&[0xE3])), Some(Instruction::Xthl));
+        assert_eq!(parse_sphl(&mut Reader::new(&[0xF9])), Some(Instruction::Sphl));
+
+        let encoded = |encode_fn: fn(&mut Vec<u8>) -> std::io::Result<()>| {
+            let mut bytes = Vec::new();
+            encode_fn(&mut bytes).unwrap();
+            bytes
+        };
+        assert_eq!(encoded(encode::encode_cma), [0x2F]);
+        assert_eq!(encoded(encode::encode_xthl), [0xE3]);
+        assert_eq!(encoded(encode::encode_sphl), [0xF9]);
+    }
 }
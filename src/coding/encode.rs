@@ -233,7 +233,7 @@ pub fn encode_rar<'a>(stream: &mut impl io::Write) -> io::Result<()> {
 }
 
 pub fn encode_cma<'a>(stream: &mut impl io::Write) -> io::Result<()> {
-    write_opcode(stream, 0b0010_1010)
+    write_opcode(stream, 0b0010_1111)
 }
 
 pub fn encode_cmc<'a>(stream: &mut impl io::Write) -> io::Result<()> {
@@ -323,3 +323,55 @@ pub fn encode_hlt<'a>(stream: &mut impl io::Write) -> io::Result<()> {
 pub fn encode_nop<'a>(stream: &mut impl io::Write) -> io::Result<()> {
     write_opcode(stream, 0b0000_0000)
 }
+
+pub fn encode_rim(stream: &mut impl io::Write) -> io::Result<()> {
+    write_opcode(stream, 0b0010_0000)
+}
+
+pub fn encode_sim(stream: &mut impl io::Write) -> io::Result<()> {
+    write_opcode(stream, 0b0011_0000)
+}
+
+pub fn encode_dsub(stream: &mut impl io::Write) -> io::Result<()> {
+    write_opcode(stream, 0b0000_1000)
+}
+
+pub fn encode_arhl(stream: &mut impl io::Write) -> io::Result<()> {
+    write_opcode(stream, 0b0001_0000)
+}
+
+pub fn encode_rdel(stream: &mut impl io::Write) -> io::Result<()> {
+    write_opcode(stream, 0b0001_1000)
+}
+
+pub fn encode_ldhi(stream: &mut impl io::Write, data: Data8) -> io::Result<()> {
+    write_opcode(stream, 0b0010_1000)?;
+    write_data_8(stream, data)
+}
+
+pub fn encode_ldsi(stream: &mut impl io::Write, data: Data8) -> io::Result<()> {
+    write_opcode(stream, 0b0011_1000)?;
+    write_data_8(stream, data)
+}
+
+pub fn encode_shlx(stream: &mut impl io::Write) -> io::Result<()> {
+    write_opcode(stream, 0b1101_1001)
+}
+
+pub fn encode_lhlx(stream: &mut impl io::Write) -> io::Result<()> {
+    write_opcode(stream, 0b1110_1101)
+}
+
+pub fn encode_jnk(stream: &mut impl io::Write, addr: Address) -> io::Result<()> {
+    write_opcode(stream, 0b1101_1101)?;
+    write_addr(stream, addr)
+}
+
+pub fn encode_jk(stream: &mut impl io::Write, addr: Address) -> io::Result<()> {
+    write_opcode(stream, 0b1111_1101)?;
+    write_addr(stream, addr)
+}
+
+pub fn encode_rstv(stream: &mut impl io::Write) -> io::Result<()> {
+    write_opcode(stream, 0b1100_1011)
+}
@@ -0,0 +1,291 @@
+use std::sync::LazyLock;
+
+use crate::{
+    coding::{self, reader::Reader},
+    instruction::{Cpu, Data16, Instruction, RegisterPairOrStatus},
+};
+
+/// The condition flags an instruction can change. The undocumented 8085 flags V and K aren't
+/// included.
+#[derive(Copy, Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub struct FlagsAffected {
+    pub sign: bool,
+    pub zero: bool,
+    pub auxiliary_carry: bool,
+    pub parity: bool,
+    pub carry: bool,
+}
+
+impl FlagsAffected {
+    pub const NONE: Self = FlagsAffected {
+        sign: false,
+        zero: false,
+        auxiliary_carry: false,
+        parity: false,
+        carry: false,
+    };
+    pub const ALL: Self = FlagsAffected {
+        sign: true,
+        zero: true,
+        auxiliary_carry: true,
+        parity: true,
+        carry: true,
+    };
+    pub const CARRY: Self = FlagsAffected {
+        carry: true,
+        ..Self::NONE
+    };
+    pub const ALL_EXCEPT_CARRY: Self = FlagsAffected {
+        carry: false,
+        ..Self::ALL
+    };
+
+    fn of(instruction: Instruction) -> Self {
+        match instruction {
+            Instruction::Add(..)
+            | Instruction::Adi(..)
+            | Instruction::Adc(..)
+            | Instruction::Aci(..)
+            | Instruction::Sub(..)
+            | Instruction::Sui(..)
+            | Instruction::Sbb(..)
+            | Instruction::Sbi(..)
+            | Instruction::Ana(..)
+            | Instruction::Ani(..)
+            | Instruction::Xra(..)
+            | Instruction::Xri(..)
+            | Instruction::Ora(..)
+            | Instruction::Ori(..)
+            | Instruction::Cmp(..)
+            | Instruction::Cpi(..)
+            | Instruction::Daa
+            | Instruction::Dsub
+            | Instruction::Pop(RegisterPairOrStatus::StatusWord) => Self::ALL,
+            Instruction::Inr(..) | Instruction::Dcr(..) => Self::ALL_EXCEPT_CARRY,
+            Instruction::Dad(..)
+            | Instruction::Rlc
+            | Instruction::Rrc
+            | Instruction::Ral
+            | Instruction::Rar
+            | Instruction::Cmc
+            | Instruction::Stc
+            | Instruction::Arhl
+            | Instruction::Rdel => Self::CARRY,
+            _ => Self::NONE,
+        }
+    }
+}
+
+/// Everything known about an opcode without looking at its operands.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Opcode {
+    /// The instruction with all operands set to zero.
+    pub instruction: Instruction,
+    /// The length in bytes, including the opcode.
+    pub length: u16,
+    /// T-states when a conditional branch isn't taken, and for all other instructions.
+    pub cycles: u32,
+    /// T-states when a conditional branch is taken.
+    pub cycles_taken: u32,
+    pub flags: FlagsAffected,
+    /// For an undocumented 8080 opcode, the documented opcode it behaves like.
+    pub alias_of: Option<u8>,
+}
+
+impl Opcode {
+    fn new(cpu: Cpu, instruction: Instruction, alias_of: Option<u8>) -> Self {
+        Self {
+            instruction,
+            length: instruction.byte_length(),
+            cycles: instruction.cycles(cpu, false),
+            cycles_taken: instruction.cycles(cpu, true),
+            flags: FlagsAffected::of(instruction),
+            alias_of,
+        }
+    }
+
+    pub fn cycles(&self, taken: bool) -> u32 {
+        if taken { self.cycles_taken } else { self.cycles }
+    }
+
+    /// The instruction encoded by `bytes`, which starts with this opcode and must be at least
+    /// `length` bytes long.
+    pub fn decode(&self, bytes: &[u8]) -> Instruction {
+        let data8 = || bytes[1];
+        let data16 = || Data16::new(bytes[1], bytes[2]);
+
+        match self.instruction {
+            Instruction::Mvi(register, _) => Instruction::Mvi(register, data8()),
+            Instruction::Lxi(register_pair, _) => Instruction::Lxi(register_pair, data16()),
+            Instruction::Lda(_) => Instruction::Lda(data16().into()),
+            Instruction::Sta(_) => Instruction::Sta(data16().into()),
+            Instruction::Lhld(_) => Instruction::Lhld(data16().into()),
+            Instruction::Shld(_) => Instruction::Shld(data16().into()),
+            Instruction::Adi(_) => Instruction::Adi(data8()),
+            Instruction::Aci(_) => Instruction::Aci(data8()),
+            Instruction::Sui(_) => Instruction::Sui(data8()),
+            Instruction::Sbi(_) => Instruction::Sbi(data8()),
+            Instruction::Ani(_) => Instruction::Ani(data8()),
+            Instruction::Xri(_) => Instruction::Xri(data8()),
+            Instruction::Ori(_) => Instruction::Ori(data8()),
+            Instruction::Cpi(_) => Instruction::Cpi(data8()),
+            Instruction::Jmp(_) => Instruction::Jmp(data16().into()),
+            Instruction::Jcc(condition, _) => Instruction::Jcc(condition, data16().into()),
+            Instruction::Call(_) => Instruction::Call(data16().into()),
+            Instruction::Ccc(condition, _) => Instruction::Ccc(condition, data16().into()),
+            Instruction::In(_) => Instruction::In(data8()),
+            Instruction::Out(_) => Instruction::Out(data8()),
+            Instruction::Ldhi(_) => Instruction::Ldhi(data8()),
+            Instruction::Ldsi(_) => Instruction::Ldsi(data8()),
+            Instruction::Jnk(_) => Instruction::Jnk(data16().into()),
+            Instruction::Jk(_) => Instruction::Jk(data16().into()),
+            instruction => instruction,
+        }
+    }
+}
+
+pub type OpcodeTable = [Option<Opcode>; 256];
+
+// The documented opcode which an opcode the 8080 leaves undefined behaves like on real silicon.
+fn alias_8080(opcode: u8) -> Option<u8> {
+    match opcode {
+        0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 => Some(0x00), // NOP
+        0xCB => Some(0xC3),                                           // JMP
+        0xD9 => Some(0xC9),                                           // RET
+        0xDD | 0xED | 0xFD => Some(0xCD),                             // CALL
+        _ => None,
+    }
+}
+
+fn build_table(cpu: Cpu) -> OpcodeTable {
+    let decode = |opcode: u8| {
+        let bytes = [opcode, 0, 0];
+        coding::decode_masked(&mut Reader::new(&bytes), cpu)
+    };
+
+    std::array::from_fn(|opcode| {
+        let opcode = opcode as u8;
+        if let Some(instruction) = decode(opcode) {
+            return Some(Opcode::new(cpu, instruction, None));
+        }
+        let documented = match cpu {
+            Cpu::Intel8080 => alias_8080(opcode)?,
+            Cpu::Intel8085 => return None,
+        };
+        decode(documented).map(|instruction| Opcode::new(cpu, instruction, Some(documented)))
+    })
+}
+
+/// All 256 opcodes of the 8080, indexed by the first byte of the instruction. The 12 undocumented
+/// opcodes are aliases of NOP, JMP, RET and CALL, and have `alias_of` set.
+pub static OPCODES_8080: LazyLock<OpcodeTable> = LazyLock::new(|| build_table(Cpu::Intel8080));
+
+/// All 256 opcodes of the 8085, including the undocumented ones, with 8085 timings.
+pub static OPCODES_8085: LazyLock<OpcodeTable> = LazyLock::new(|| build_table(Cpu::Intel8085));
+
+pub fn opcodes(cpu: Cpu) -> &'static OpcodeTable {
+    match cpu {
+        Cpu::Intel8080 => &OPCODES_8080,
+        Cpu::Intel8085 => &OPCODES_8085,
+    }
+}
+
+pub fn opcode(cpu: Cpu, byte: u8) -> Option<&'static Opcode> {
+    opcodes(cpu)[byte as usize].as_ref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::{Register, RegisterPair};
+
+    const UNDEFINED_8080: [u8; 12] =
+        [0x08, 0x10, 0x18, 0x20, 0x28, 0x30, 0x38, 0xCB, 0xD9, 0xDD, 0xED, 0xFD];
+
+    #[test]
+    fn test_opcode_table() {
+        // The 8080 leaves 12 opcodes undefined, which are aliases of documented ones.
+        assert_eq!(OPCODES_8080.iter().flatten().count(), 256);
+        let documented = OPCODES_8080.iter().flatten().filter(|opcode| opcode.alias_of.is_none());
+        assert_eq!(documented.count(), 244);
+        for undefined in UNDEFINED_8080 {
+            assert!(opcode(Cpu::Intel8080, undefined).unwrap().alias_of.is_some());
+        }
+        let jmp = opcode(Cpu::Intel8080, 0xCB).unwrap();
+        assert_eq!((jmp.instruction, jmp.alias_of), (Instruction::Jmp(0), Some(0xC3)));
+        let call = opcode(Cpu::Intel8080, 0xFD).unwrap();
+        assert_eq!((call.instruction, call.cycles), (Instruction::Call(0), 17));
+
+        let opcode = |byte| opcode(Cpu::Intel8080, byte);
+        assert_eq!(opcode(0x2F).unwrap().instruction, Instruction::Cma);
+        assert_eq!(opcode(0xE3).unwrap().instruction, Instruction::Xthl);
+        assert_eq!(opcode(0xF9).unwrap().instruction, Instruction::Sphl);
+        assert_eq!(opcode(0x76).unwrap().instruction, Instruction::Hlt);
+
+        let inr_m = opcode(0x34).unwrap();
+        assert_eq!(inr_m.instruction, Instruction::Inr(Register::M));
+        assert_eq!(inr_m.cycles, 10);
+        assert_eq!(inr_m.flags, FlagsAffected::ALL_EXCEPT_CARRY);
+
+        let cnz = opcode(0xC4).unwrap();
+        assert_eq!((cnz.length, cnz.cycles(false), cnz.cycles(true)), (3, 11, 17));
+    }
+
+    #[test]
+    fn test_opcode_table_8085() {
+        // The 8085 uses every opcode the 8080 leaves undefined.
+        assert_eq!(OPCODES_8085.iter().flatten().count(), 256);
+        for byte in 0..=255 {
+            if !UNDEFINED_8080.contains(&byte) {
+                assert_eq!(
+                    opcode(Cpu::Intel8085, byte).unwrap().instruction,
+                    opcode(Cpu::Intel8080, byte).unwrap().instruction
+                );
+            }
+        }
+
+        let opcode = |byte| opcode(Cpu::Intel8085, byte);
+        assert_eq!(opcode(0x20).unwrap().instruction, Instruction::Rim);
+        assert_eq!(opcode(0x30).unwrap().instruction, Instruction::Sim);
+        assert_eq!(opcode(0xCB).unwrap().instruction, Instruction::Rstv);
+
+        let mov = opcode(0x78).unwrap();
+        assert_eq!(mov.cycles, 4);
+        let jnz = opcode(0xC2).unwrap();
+        assert_eq!((jnz.cycles(false), jnz.cycles(true)), (7, 10));
+        let cnz = opcode(0xC4).unwrap();
+        assert_eq!((cnz.cycles(false), cnz.cycles(true)), (9, 18));
+        let jk = opcode(0xFD).unwrap();
+        assert_eq!((jk.length, jk.cycles(false), jk.cycles(true)), (3, 7, 10));
+    }
+
+    #[test]
+    fn test_decode_round_trip() {
+        for cpu in [Cpu::Intel8080, Cpu::Intel8085] {
+            for (byte, opcode) in opcodes(cpu).iter().enumerate() {
+                let Some(opcode) = opcode else {
+                    continue;
+                };
+                let bytes = [byte as u8, 0x34, 0x12];
+                let instruction = opcode.decode(&bytes);
+
+                // Aliases are encoded as the documented opcode.
+                let mut expected = bytes;
+                expected[0] = opcode.alias_of.unwrap_or(expected[0]);
+
+                let mut encoded = Vec::new();
+                coding::encode(&mut encoded, instruction).unwrap();
+                assert_eq!(encoded, &expected[..opcode.length as usize], "{}", instruction);
+            }
+        }
+
+        assert_eq!(
+            opcode(Cpu::Intel8080, 0x21).unwrap().decode(&[0x21, 0x34, 0x12]),
+            Instruction::Lxi(RegisterPair::Hl, Data16::new(0x34, 0x12))
+        );
+        assert_eq!(
+            opcode(Cpu::Intel8085, 0x28).unwrap().decode(&[0x28, 0x34, 0x12]),
+            Instruction::Ldhi(0x34)
+        );
+    }
+}
@@ -0,0 +1,746 @@
+//! Runs CP/M 2.2 programs by emulating the parts of the operating system they call.
+//!
+//! A `.COM` program is loaded at `0x0100` below a fake BDOS and BIOS. The BDOS entry point and the
+//! BIOS jump table contain `RET` instructions, and calls to them are handled by `Cpm::trap` before
+//! they are executed. Files are read from and written to a directory on the host, which acts as
+//! drive `A:`.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufRead, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    instruction::{Address, Data8, Register, RegisterPair},
+    machine::Machine,
+};
+
+/// Where programs are loaded, at the start of the transient program area.
+pub const TPA_START: Address = 0x0100;
+/// Programs call the BDOS through this address.
+pub const BDOS_ENTRY: Address = 0x0005;
+/// The address the BDOS entry point jumps to. Programs treat this as the top of their memory.
+pub const BDOS_ADDRESS: Address = 0xFE00;
+/// The BIOS jump table, with one 3-byte entry per BIOS function.
+pub const BIOS_ADDRESS: Address = 0xFF00;
+const BIOS_FUNCTIONS: Address = 17;
+
+const DEFAULT_FCB: Address = 0x005C;
+const SECOND_FCB: Address = 0x006C;
+const DEFAULT_DMA: Address = 0x0080;
+const RECORD_SIZE: usize = 128;
+// Marks the end of text files and console input.
+const EOF_CHARACTER: Data8 = 0x1A;
+// Console status, which is never ready. Programs poll it to check for a key press, e.g. to stop
+// on Ctrl-C, and reading input they didn't ask for would take it from the functions which do.
+const CONSOLE_STATUS: Data8 = 0x00;
+
+// Offsets into a file control block.
+const FCB_NAME: Address = 1;
+const FCB_EXTENT: Address = 12;
+const FCB_S2: Address = 14;
+const FCB_RECORD_COUNT: Address = 15;
+const FCB_CURRENT_RECORD: Address = 32;
+const FCB_RANDOM_RECORD: Address = 33;
+
+/// The CP/M operating system of a machine.
+pub struct Cpm {
+    directory: PathBuf,
+    console_input: Box<dyn BufRead + Send>,
+    dma: Address,
+    // Files opened by the program, by their CP/M name.
+    files: HashMap<String, fs::File>,
+}
+
+impl Cpm {
+    /// Creates a CP/M system whose drive `A:` is `directory`, and which reads console input from
+    /// stdin.
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self::with_console_input(directory, Box::new(io::BufReader::new(io::stdin())))
+    }
+
+    pub fn with_console_input(
+        directory: impl Into<PathBuf>,
+        console_input: Box<dyn BufRead + Send>,
+    ) -> Self {
+        Self {
+            directory: directory.into(),
+            console_input,
+            dma: DEFAULT_DMA,
+            files: HashMap::new(),
+        }
+    }
+
+    /// Loads a `.COM` program into the machine and sets up the zero page like the CP/M command
+    /// processor, with `arguments` as the command tail and parsed into the default FCBs. The
+    /// machine starts executing the program at `0x0100`.
+    pub fn load(&mut self, machine: &mut Machine, program: &[u8], arguments: &[String]) -> io::Result<()> {
+        if program.len() > (BDOS_ADDRESS - TPA_START) as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Program is {} bytes large, but must fit in the {} bytes of the TPA",
+                    program.len(),
+                    BDOS_ADDRESS - TPA_START
+                ),
+            ));
+        }
+
+        let memory = machine.memory_mut();
+        memory.write_slice(TPA_START, program).expect("program fits in the TPA");
+
+        // Warm boot and BDOS entry point, IOBYTE and current drive.
+        let [wboot_low, wboot_high] = (BIOS_ADDRESS + 3).to_le_bytes();
+        let [bdos_low, bdos_high] = BDOS_ADDRESS.to_le_bytes();
+        memory
+            .write_slice(0x0000, &[0xC3, wboot_low, wboot_high, 0x00, 0x00])
+            .unwrap();
+        memory
+            .write_slice(BDOS_ENTRY, &[0xC3, bdos_low, bdos_high])
+            .unwrap();
+        memory.write_slice(BDOS_ADDRESS, &[0xC9]).unwrap();
+        for function in 0..BIOS_FUNCTIONS {
+            memory
+                .write_slice(BIOS_ADDRESS + function * 3, &[0xC9, 0x00, 0x00])
+                .unwrap();
+        }
+
+        // The second FCB overlaps the end of the first, so it's only usable after copying it.
+        let mut fcbs = [0; (DEFAULT_DMA - DEFAULT_FCB) as usize];
+        for (fcb, argument) in [(DEFAULT_FCB, arguments.first()), (SECOND_FCB, arguments.get(1))] {
+            let name = (fcb - DEFAULT_FCB + FCB_NAME) as usize;
+            fcbs[name..name + 11].copy_from_slice(&fcb_name(argument));
+        }
+        memory.write_slice(DEFAULT_FCB, &fcbs).unwrap();
+
+        let mut tail: Vec<u8> = arguments
+            .iter()
+            .flat_map(|argument| format!(" {}", argument.to_ascii_uppercase()).into_bytes())
+            .take(RECORD_SIZE - 2)
+            .collect();
+        tail.insert(0, tail.len() as u8);
+        tail.push(0);
+        memory.write_slice(DEFAULT_DMA, &tail).unwrap();
+
+        // Returning from the program warm boots, like returning to the command processor.
+        machine.set_register_16(RegisterPair::Sp, BDOS_ADDRESS.into());
+        machine.stack_push(0x0000.into()).expect("stack has room");
+        machine.set_pc(TPA_START.into());
+        self.dma = DEFAULT_DMA;
+
+        Ok(())
+    }
+
+    /// Handles a BDOS or BIOS call if the machine is about to execute one. The machine then
+    /// executes the `RET` at the entry point to return to the program. Returns `true` if the
+    /// program has exited.
+    pub fn trap(&mut self, machine: &mut Machine) -> io::Result<bool> {
+        let pc = machine.pc().value();
+        if pc == BDOS_ADDRESS {
+            return self.bdos_call(machine);
+        }
+        if (BIOS_ADDRESS..BIOS_ADDRESS + BIOS_FUNCTIONS * 3).contains(&pc)
+            && (pc - BIOS_ADDRESS).is_multiple_of(3)
+        {
+            return self.bios_call(machine, (pc - BIOS_ADDRESS) / 3);
+        }
+        Ok(false)
+    }
+
+    fn bios_call(&mut self, machine: &mut Machine, function: Address) -> io::Result<bool> {
+        match function {
+            // BOOT and WBOOT
+            0 | 1 => return Ok(true),
+            // CONST
+            2 => machine.set_register_8(Register::A, CONSOLE_STATUS),
+            // CONIN
+            3 => {
+                let character = self.read_console()?;
+                machine.set_register_8(Register::A, character);
+            }
+            // CONOUT
+            4 => machine.stdout.push(machine.register_8(Register::C)),
+            // LIST and PUNCH
+            5 | 6 => {}
+            // READER
+            7 => machine.set_register_8(Register::A, EOF_CHARACTER),
+            // Disk functions aren't supported, programs have to use the BDOS.
+            _ => machine.set_register_8(Register::A, 0x01),
+        }
+        Ok(false)
+    }
+
+    fn bdos_call(&mut self, machine: &mut Machine) -> io::Result<bool> {
+        let function = machine.register_8(Register::C);
+        let de = machine.register_16(RegisterPair::De).value();
+        let e = machine.register_8(Register::E);
+
+        let result: u16 = match function {
+            // System reset
+            0 => return Ok(true),
+            // Console input
+            1 => self.read_console()? as u16,
+            // Console output
+            2 => {
+                machine.stdout.push(e);
+                0
+            }
+            // Direct console I/O
+            6 => match e {
+                0xFF => self.read_console()? as u16,
+                0xFE => CONSOLE_STATUS as u16,
+                _ => {
+                    machine.stdout.push(e);
+                    0
+                }
+            },
+            // Print string
+            9 => {
+                let mut address = de;
+                loop {
+                    let character = machine.memory().peek_8(address);
+                    if character == b'$' {
+                        break;
+                    }
+                    machine.stdout.push(character);
+                    address = address.wrapping_add(1);
+                }
+                0
+            }
+            // Read console buffer
+            10 => {
+                self.read_console_buffer(machine, de)?;
+                0
+            }
+            // Get console status
+            11 => CONSOLE_STATUS as u16,
+            // Return version number: CP/M 2.2
+            12 => 0x0022,
+            // Reset disk system
+            13 => {
+                self.dma = DEFAULT_DMA;
+                0
+            }
+            // Select disk, only A: exists
+            14 => 0,
+            15 => self.open_file(machine, de),
+            16 => self.close_file(machine, de)?,
+            19 => self.delete_file(machine, de),
+            20 => self.read_sequential(machine, de)?,
+            21 => self.write_sequential(machine, de)?,
+            22 => self.make_file(machine, de)?,
+            // Return current disk
+            25 => 0,
+            // Set DMA address
+            26 => {
+                self.dma = de;
+                0
+            }
+            // Get or set user code, only user 0 exists
+            32 => 0,
+            33 => self.read_random(machine, de)?,
+            34 => self.write_random(machine, de)?,
+            35 => self.compute_file_size(machine, de)?,
+            // Set random record
+            36 => {
+                let record = current_record(machine, de);
+                set_random_record(machine, de, record);
+                0
+            }
+            // Unsupported functions fail.
+            _ => 0x00FF,
+        };
+
+        // Results are returned in both HL and BA.
+        let [low, high] = result.to_le_bytes();
+        machine.set_register_16(RegisterPair::Hl, result.into());
+        machine.set_register_8(Register::A, low);
+        machine.set_register_8(Register::B, high);
+        Ok(false)
+    }
+
+    fn read_console(&mut self) -> io::Result<Data8> {
+        let mut byte = [0];
+        match self.console_input.read(&mut byte)? {
+            0 => Ok(EOF_CHARACTER),
+            // CP/M programs expect lines to end with a carriage return.
+            _ if byte[0] == b'\n' => Ok(b'\r'),
+            _ => Ok(byte[0]),
+        }
+    }
+
+    fn read_console_buffer(&mut self, machine: &mut Machine, buffer: Address) -> io::Result<()> {
+        let max_length = machine.memory().peek_8(buffer) as usize;
+        let mut line = String::new();
+        self.console_input.read_line(&mut line)?;
+        let line = line.trim_end_matches(['\r', '\n']).as_bytes();
+        let line = &line[..line.len().min(max_length)];
+
+        let memory = machine.memory_mut();
+        memory.write_8(buffer.wrapping_add(1), line.len() as Data8);
+        for (offset, character) in line.iter().enumerate() {
+            memory.write_8(buffer.wrapping_add(2 + offset as Address), *character);
+        }
+        Ok(())
+    }
+
+    // The path of the file named by an FCB, matching existing files case-insensitively.
+    fn host_path(&self, name: &str) -> PathBuf {
+        let existing = fs::read_dir(&self.directory).ok().and_then(|entries| {
+            entries
+                .flatten()
+                .map(|entry| entry.path())
+                .find(|path| {
+                    path.file_name()
+                        .and_then(|file_name| file_name.to_str())
+                        .is_some_and(|file_name| file_name.eq_ignore_ascii_case(name))
+                })
+        });
+        existing.unwrap_or_else(|| self.directory.join(name))
+    }
+
+    // The open file named by an FCB. Files are opened on first use if the program didn't open
+    // them.
+    fn file(&mut self, machine: &Machine, fcb: Address) -> Option<&mut fs::File> {
+        let name = fcb_file_name(machine, fcb)?;
+        if !self.files.contains_key(&name) {
+            let file = open_existing(&self.host_path(&name)).ok()?;
+            self.files.insert(name.clone(), file);
+        }
+        self.files.get_mut(&name)
+    }
+
+    fn open_file(&mut self, machine: &mut Machine, fcb: Address) -> u16 {
+        let Some(name) = fcb_file_name(machine, fcb) else {
+            return 0xFF;
+        };
+        let Ok(file) = open_existing(&self.host_path(&name)) else {
+            return 0xFF;
+        };
+        let records = file_records(&file).unwrap_or(0);
+        self.files.insert(name, file);
+
+        machine.memory_mut().write_8(fcb.wrapping_add(FCB_S2), 0);
+        set_record_count(machine, fcb, records);
+        0
+    }
+
+    fn close_file(&mut self, machine: &Machine, fcb: Address) -> io::Result<u16> {
+        let Some(name) = fcb_file_name(machine, fcb) else {
+            return Ok(0xFF);
+        };
+        match self.files.remove(&name) {
+            Some(mut file) => {
+                file.flush()?;
+                Ok(0)
+            }
+            None => Ok(0xFF),
+        }
+    }
+
+    fn delete_file(&mut self, machine: &Machine, fcb: Address) -> u16 {
+        let Some(name) = fcb_file_name(machine, fcb) else {
+            return 0xFF;
+        };
+        self.files.remove(&name);
+        match fs::remove_file(self.host_path(&name)) {
+            Ok(()) => 0,
+            Err(_) => 0xFF,
+        }
+    }
+
+    fn make_file(&mut self, machine: &mut Machine, fcb: Address) -> io::Result<u16> {
+        let Some(name) = fcb_file_name(machine, fcb) else {
+            return Ok(0xFF);
+        };
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(self.host_path(&name));
+        let Ok(file) = file else {
+            return Ok(0xFF);
+        };
+        self.files.insert(name, file);
+
+        let memory = machine.memory_mut();
+        memory.write_8(fcb.wrapping_add(FCB_S2), 0);
+        memory.write_8(fcb.wrapping_add(FCB_RECORD_COUNT), 0);
+        Ok(0)
+    }
+
+    fn read_sequential(&mut self, machine: &mut Machine, fcb: Address) -> io::Result<u16> {
+        let record = current_record(machine, fcb);
+        let result = self.read_record(machine, fcb, record)?;
+        if result == 0 {
+            self.set_position(machine, fcb, record + 1)?;
+        }
+        Ok(result)
+    }
+
+    fn write_sequential(&mut self, machine: &mut Machine, fcb: Address) -> io::Result<u16> {
+        let record = current_record(machine, fcb);
+        let result = self.write_record(machine, fcb, record)?;
+        if result == 0 {
+            self.set_position(machine, fcb, record + 1)?;
+        }
+        Ok(result)
+    }
+
+    fn read_random(&mut self, machine: &mut Machine, fcb: Address) -> io::Result<u16> {
+        let record = random_record(machine, fcb);
+        let result = self.read_record(machine, fcb, record)?;
+        // Random access sets the position for following sequential access.
+        self.set_position(machine, fcb, record)?;
+        Ok(result)
+    }
+
+    fn write_random(&mut self, machine: &mut Machine, fcb: Address) -> io::Result<u16> {
+        let record = random_record(machine, fcb);
+        let result = self.write_record(machine, fcb, record)?;
+        self.set_position(machine, fcb, record)?;
+        Ok(result)
+    }
+
+    // Sets the sequential position of an FCB, and its record count to the number of records of
+    // the file in the extent of the position.
+    fn set_position(&mut self, machine: &mut Machine, fcb: Address, record: usize) -> io::Result<()> {
+        set_current_record(machine, fcb, record);
+        let records = match self.file(machine, fcb) {
+            Some(file) => file_records(file)?,
+            None => 0,
+        };
+        set_record_count(machine, fcb, records);
+        Ok(())
+    }
+
+    fn compute_file_size(&mut self, machine: &mut Machine, fcb: Address) -> io::Result<u16> {
+        let Some(file) = self.file(machine, fcb) else {
+            return Ok(0xFF);
+        };
+        let records = file_records(file)?;
+        set_random_record(machine, fcb, records);
+        Ok(0)
+    }
+
+    // Reads a record into the DMA buffer. A partial record at the end of the file is padded with
+    // the end of file character. Returns 1 at the end of the file.
+    fn read_record(&mut self, machine: &mut Machine, fcb: Address, record: usize) -> io::Result<u16> {
+        let dma = self.dma;
+        let Some(file) = self.file(machine, fcb) else {
+            return Ok(0xFF);
+        };
+
+        let mut buffer = [EOF_CHARACTER; RECORD_SIZE];
+        file.seek(SeekFrom::Start((record * RECORD_SIZE) as u64))?;
+        let mut length = 0;
+        while length < RECORD_SIZE {
+            match file.read(&mut buffer[length..])? {
+                0 => break,
+                read => length += read,
+            }
+        }
+        if length == 0 {
+            return Ok(1);
+        }
+
+        let memory = machine.memory_mut();
+        for (offset, byte) in buffer.iter().enumerate() {
+            memory.write_8(dma.wrapping_add(offset as Address), *byte);
+        }
+        Ok(0)
+    }
+
+    // Writes the DMA buffer as a record.
+    fn write_record(&mut self, machine: &Machine, fcb: Address, record: usize) -> io::Result<u16> {
+        let dma = self.dma;
+        let buffer: Vec<Data8> = (0..RECORD_SIZE)
+            .map(|offset| machine.memory().peek_8(dma.wrapping_add(offset as Address)))
+            .collect();
+        let Some(file) = self.file(machine, fcb) else {
+            return Ok(0xFF);
+        };
+
+        file.seek(SeekFrom::Start((record * RECORD_SIZE) as u64))?;
+        file.write_all(&buffer)?;
+        Ok(0)
+    }
+}
+
+// The name and type fields of an FCB for a command line argument.
+fn fcb_name(argument: Option<&String>) -> [u8; 11] {
+    let mut name = [b' '; 11];
+    let Some(argument) = argument else {
+        return name;
+    };
+    let argument = argument.to_ascii_uppercase();
+    // Drive letters are ignored, since only A: exists.
+    let argument = match argument.split_once(':') {
+        Some((_, file)) => file.to_string(),
+        None => argument,
+    };
+    let (base, extension) = argument.split_once('.').unwrap_or((&argument, ""));
+
+    let (name_field, extension_field) = name.split_at_mut(8);
+    for (field, text) in [(name_field, base), (extension_field, extension)] {
+        for (index, character) in text.bytes().take(field.len()).enumerate() {
+            if character == b'*' {
+                field[index..].fill(b'?');
+                break;
+            }
+            field[index] = character;
+        }
+    }
+    name
+}
+
+// The file name in an FCB, as `NAME.TYP`. `None` if the name has characters which aren't allowed
+// in CP/M file names, which also keeps programs from naming files outside the directory of drive
+// `A:`, with `/` or `..`.
+fn fcb_file_name(machine: &Machine, fcb: Address) -> Option<String> {
+    let field = |start: Address, length: Address| -> Option<String> {
+        let field: String = (start..start + length)
+            .map(|offset| (machine.memory().peek_8(fcb.wrapping_add(offset)) & 0x7F) as char)
+            .collect();
+        // Names are padded with spaces, and can't contain them.
+        let field = field.trim_end();
+        field.bytes().all(is_file_name_character).then(|| field.to_string())
+    };
+    let base = field(FCB_NAME, 8)?;
+    let extension = field(FCB_NAME + 8, 3)?;
+    if base.is_empty() {
+        None
+    } else if extension.is_empty() {
+        Some(base)
+    } else {
+        Some(format!("{}.{}", base, extension))
+    }
+}
+
+fn is_file_name_character(character: u8) -> bool {
+    character.is_ascii_alphanumeric() || b"!#$%&'()-@^_`{}~".contains(&character)
+}
+
+// The record count of an FCB, for a file of `records` records.
+fn set_record_count(machine: &mut Machine, fcb: Address, records: usize) {
+    let extent = current_record(machine, fcb) >> 7;
+    let records_in_extent = records.saturating_sub(extent * 128).min(128);
+    machine
+        .memory_mut()
+        .write_8(fcb.wrapping_add(FCB_RECORD_COUNT), records_in_extent as Data8);
+}
+
+fn open_existing(path: &Path) -> io::Result<fs::File> {
+    fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .or_else(|_| fs::File::open(path))
+}
+
+fn file_records(file: &fs::File) -> io::Result<usize> {
+    let length = file.metadata()?.len() as usize;
+    Ok(length.div_ceil(RECORD_SIZE))
+}
+
+// The sequential position of an FCB, from its extent and current record fields.
+fn current_record(machine: &Machine, fcb: Address) -> usize {
+    let memory = machine.memory();
+    let s2 = memory.peek_8(fcb.wrapping_add(FCB_S2)) as usize;
+    let extent = memory.peek_8(fcb.wrapping_add(FCB_EXTENT)) as usize & 0x1F;
+    let record = memory.peek_8(fcb.wrapping_add(FCB_CURRENT_RECORD)) as usize & 0x7F;
+    (s2 << 12) | (extent << 7) | record
+}
+
+fn set_current_record(machine: &mut Machine, fcb: Address, record: usize) {
+    let memory = machine.memory_mut();
+    memory.write_8(fcb.wrapping_add(FCB_S2), (record >> 12) as Data8);
+    memory.write_8(fcb.wrapping_add(FCB_EXTENT), ((record >> 7) & 0x1F) as Data8);
+    memory.write_8(fcb.wrapping_add(FCB_CURRENT_RECORD), (record & 0x7F) as Data8);
+}
+
+fn random_record(machine: &Machine, fcb: Address) -> usize {
+    let memory = machine.memory();
+    let low = memory.peek_8(fcb.wrapping_add(FCB_RANDOM_RECORD)) as usize;
+    let high = memory.peek_8(fcb.wrapping_add(FCB_RANDOM_RECORD + 1)) as usize;
+    (high << 8) | low
+}
+
+fn set_random_record(machine: &mut Machine, fcb: Address, record: usize) {
+    let memory = machine.memory_mut();
+    memory.write_8(fcb.wrapping_add(FCB_RANDOM_RECORD), record as Data8);
+    memory.write_8(fcb.wrapping_add(FCB_RANDOM_RECORD + 1), (record >> 8) as Data8);
+    memory.write_8(fcb.wrapping_add(FCB_RANDOM_RECORD + 2), (record >> 16) as Data8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::MachineState;
+
+    // Machine code which calls BDOS function `function` with DE set to `de`.
+    fn bdos(function: Data8, de: Address) -> Vec<u8> {
+        let [low, high] = de.to_le_bytes();
+        vec![0x11, low, high, 0x0E, function, 0xCD, 0x05, 0x00]
+    }
+
+    fn run(cpm: &mut Cpm, machine: &mut Machine) {
+        for _ in 0..10_000 {
+            if cpm.trap(machine).unwrap() {
+                return;
+            }
+            machine.run_cycle();
+            assert_eq!(machine.state(), MachineState::Running);
+        }
+        panic!("program didn't exit");
+    }
+
+    #[test]
+    fn test_console() {
+        let mut program = bdos(9, 0x0120);
+        program.extend(bdos(1, 0));
+        // MOV E, A; MVI C, 2; CALL 5; RET
+        program.extend([0x5F, 0x0E, 0x02, 0xCD, 0x05, 0x00, 0xC9]);
+        program.resize(0x20, 0x00);
+        program.extend(b"Hello, CP/M!$");
+
+        let mut machine = Machine::new();
+        let mut cpm = Cpm::with_console_input(".", Box::new(&b"x"[..]));
+        cpm.load(&mut machine, &program, &["one".to_string(), "b:two.txt".to_string()])
+            .unwrap();
+        assert_eq!(machine.memory().peek_8(0x0080), 14);
+        assert_eq!(&machine.memory().as_raw()[0x81..0x8F], b" ONE B:TWO.TXT");
+        assert_eq!(&machine.memory().as_raw()[0x5D..0x68], b"ONE        ");
+        assert_eq!(&machine.memory().as_raw()[0x6D..0x78], b"TWO     TXT");
+
+        run(&mut cpm, &mut machine);
+        assert_eq!(machine.stdout, b"Hello, CP/M!x");
+    }
+
+    #[test]
+    fn test_files() {
+        let directory = std::env::temp_dir().join(format!("cpm-test-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+
+        // Write a record to the file named by the first argument.
+        let mut program = bdos(22, DEFAULT_FCB);
+        program.extend(bdos(26, 0x0200));
+        program.extend(bdos(21, DEFAULT_FCB));
+        program.extend(bdos(16, DEFAULT_FCB));
+        program.push(0xC9);
+        program.resize(0x100, 0x00);
+        program.extend(b"Some data");
+
+        let mut machine = Machine::new();
+        let mut cpm = Cpm::new(&directory);
+        cpm.load(&mut machine, &program, &["data.txt".to_string()]).unwrap();
+        run(&mut cpm, &mut machine);
+
+        let contents = fs::read(directory.join("DATA.TXT")).unwrap();
+        assert_eq!(contents.len(), RECORD_SIZE);
+        assert!(contents.starts_with(b"Some data"));
+
+        // Read it back, and read past the end of the file.
+        let mut program = bdos(15, DEFAULT_FCB);
+        program.extend(bdos(26, 0x0300));
+        program.extend(bdos(20, DEFAULT_FCB));
+        // STA 0x0400
+        program.extend([0x32, 0x00, 0x04]);
+        program.extend(bdos(20, DEFAULT_FCB));
+        program.extend([0x32, 0x01, 0x04]);
+        program.push(0xC9);
+
+        let mut machine = Machine::new();
+        cpm.load(&mut machine, &program, &["DATA.TXT".to_string()]).unwrap();
+        run(&mut cpm, &mut machine);
+
+        let memory = machine.memory().as_raw();
+        assert!(memory[0x0300..].starts_with(b"Some data"));
+        assert_eq!(memory[0x0400], 0);
+        assert_eq!(memory[0x0401], 1);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_file_names_outside_directory() {
+        let directory = std::env::temp_dir().join(format!("cpm-test-names-{}", std::process::id()));
+        let drive = directory.join("drive");
+        fs::create_dir_all(&drive).unwrap();
+        fs::write(directory.join("SECRET.TXT"), b"secret").unwrap();
+
+        let mut machine = Machine::new();
+        let mut cpm = Cpm::new(&drive);
+        for name in [b"..      ", b"../SECRE", b"/TMP/X  ", b"A\\B     ", b"A B     "] {
+            let mut fcb = [0; 36];
+            fcb[1..9].copy_from_slice(name);
+            fcb[9..12].copy_from_slice(b"TXT");
+            machine.memory_mut().write_slice(DEFAULT_FCB, &fcb).unwrap();
+
+            assert_eq!(cpm.make_file(&mut machine, DEFAULT_FCB).unwrap(), 0xFF);
+            assert_eq!(cpm.open_file(&mut machine, DEFAULT_FCB), 0xFF);
+            assert_eq!(cpm.delete_file(&machine, DEFAULT_FCB), 0xFF);
+            assert_eq!(cpm.read_sequential(&mut machine, DEFAULT_FCB).unwrap(), 0xFF);
+        }
+        assert_eq!(fs::read(directory.join("SECRET.TXT")).unwrap(), b"secret");
+        assert_eq!(fs::read_dir(&drive).unwrap().count(), 0);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_record_count() {
+        let directory = std::env::temp_dir().join(format!("cpm-test-extents-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+
+        let mut machine = Machine::new();
+        let mut cpm = Cpm::new(&directory);
+        cpm.load(&mut machine, &[0xC9], &["big.dat".to_string()]).unwrap();
+        let byte = |machine: &Machine, offset: Address| machine.memory().peek_8(DEFAULT_FCB + offset);
+
+        // Writing the first record of the second extent moves the FCB to it.
+        assert_eq!(cpm.make_file(&mut machine, DEFAULT_FCB).unwrap(), 0);
+        for _ in 0..130 {
+            assert_eq!(cpm.write_sequential(&mut machine, DEFAULT_FCB).unwrap(), 0);
+        }
+        assert_eq!(byte(&machine, FCB_EXTENT), 1);
+        assert_eq!(byte(&machine, FCB_CURRENT_RECORD), 2);
+        assert_eq!(byte(&machine, FCB_RECORD_COUNT), 2);
+        assert_eq!(cpm.close_file(&machine, DEFAULT_FCB).unwrap(), 0);
+
+        // Reading to the end of the first extent gives the record count of the second.
+        machine.memory_mut().write_slice(DEFAULT_FCB + FCB_EXTENT, &[0]).unwrap();
+        machine.memory_mut().write_slice(DEFAULT_FCB + FCB_CURRENT_RECORD, &[0]).unwrap();
+        assert_eq!(cpm.open_file(&mut machine, DEFAULT_FCB), 0);
+        assert_eq!(byte(&machine, FCB_RECORD_COUNT), 128);
+        for _ in 0..128 {
+            assert_eq!(cpm.read_sequential(&mut machine, DEFAULT_FCB).unwrap(), 0);
+        }
+        assert_eq!(byte(&machine, FCB_EXTENT), 1);
+        assert_eq!(byte(&machine, FCB_RECORD_COUNT), 2);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_console_status() {
+        let mut program = bdos(11, 0);
+        // STA 0x0300
+        program.extend([0x32, 0x00, 0x03]);
+        program.extend(bdos(6, 0x00FE));
+        program.extend([0x32, 0x01, 0x03]);
+        program.push(0xC9);
+
+        let mut machine = Machine::new();
+        let mut cpm = Cpm::with_console_input(".", Box::new(&b"x"[..]));
+        cpm.load(&mut machine, &program, &[]).unwrap();
+        run(&mut cpm, &mut machine);
+
+        let memory = machine.memory().as_raw();
+        assert_eq!(memory[0x0300], CONSOLE_STATUS);
+        assert_eq!(memory[0x0301], CONSOLE_STATUS);
+    }
+}
@@ -0,0 +1,68 @@
+use std::io::{self, Write};
+
+use crate::{
+    clock::{ClockSpeed, Throttle},
+    cpm::Cpm,
+    machine::{HaltReason, Machine, MachineState},
+};
+
+/// Why the machine stopped running in headless mode.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum Exit {
+    Halted(HaltReason),
+    /// Halted by a HLT instruction with interrupts enabled. Nothing can raise an interrupt in
+    /// headless mode, so the machine would wait forever.
+    WaitingForInterrupt,
+}
+
+impl Exit {
+    /// Process exit status used when the machine stops in headless mode.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Exit::Halted(halt_reason) => halt_reason.exit_code(),
+            Exit::WaitingForInterrupt => 8,
+        }
+    }
+}
+
+/// Runs the machine until it halts, without the interactive UI. Output written by the program is
+/// streamed to the real stdout as it is produced. With `cpm`, calls to CP/M are handled, and the
+/// program exiting to CP/M counts as halting normally.
+pub fn start(
+    machine: &mut Machine,
+    clock_speed: ClockSpeed,
+    mut cpm: Option<&mut Cpm>,
+) -> anyhow::Result<Exit> {
+    let mut stdout = io::stdout().lock();
+    let mut throttle = Throttle::new(clock_speed);
+
+    loop {
+        if let Some(cpm) = cpm.as_deref_mut() {
+            let exited = cpm.trap(machine)?;
+            stdout.write_all(&machine.stdout)?;
+            stdout.flush()?;
+            machine.stdout.clear();
+            if exited {
+                return Ok(Exit::Halted(HaltReason::HaltInstruction));
+            }
+        }
+
+        let cycles = machine.run_cycle();
+        throttle.wait(cycles);
+
+        if !machine.stdout.is_empty() {
+            stdout.write_all(&machine.stdout)?;
+            stdout.flush()?;
+            machine.stdout.clear();
+        }
+
+        match machine.state() {
+            MachineState::Running => {}
+            MachineState::WaitingForInterrupt if machine.is_finished() => {
+                return Ok(Exit::Halted(HaltReason::HaltInstruction));
+            }
+            MachineState::WaitingForInterrupt => return Ok(Exit::WaitingForInterrupt),
+            MachineState::Halted(halt_reason) => return Ok(Exit::Halted(halt_reason)),
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::{fmt::Display, ops::{Add, Sub}};
+use std::{fmt::Display, ops::{Add, Sub}, str::FromStr};
 
 use parsable::Parsable;
 
@@ -296,6 +296,21 @@ impl TryFrom<u8> for Condition {
     }
 }
 
+impl Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Condition::Carry => "C",
+            Condition::NoCarry => "NC",
+            Condition::Zero => "Z",
+            Condition::NoZero => "NZ",
+            Condition::Positive => "P",
+            Condition::Minus => "M",
+            Condition::ParityEven => "PE",
+            Condition::ParityOdd => "PO",
+        })
+    }
+}
+
 pub type Port = Data8;
 
 #[repr(u8)]
@@ -343,6 +358,56 @@ impl TryFrom<u8> for RestartNumber {
     }
 }
 
+/// The CPU a program is written for. The 8085 runs all 8080 programs, and adds RIM, SIM and the
+/// instructions Intel left undocumented.
+#[derive(Copy, Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub enum Cpu {
+    #[default]
+    Intel8080,
+    Intel8085,
+}
+
+impl Cpu {
+    pub fn supports(&self, instruction: Instruction) -> bool {
+        match instruction {
+            Instruction::Rim
+            | Instruction::Sim
+            | Instruction::Dsub
+            | Instruction::Arhl
+            | Instruction::Rdel
+            | Instruction::Ldhi(..)
+            | Instruction::Ldsi(..)
+            | Instruction::Shlx
+            | Instruction::Lhlx
+            | Instruction::Jnk(..)
+            | Instruction::Jk(..)
+            | Instruction::Rstv => *self == Cpu::Intel8085,
+            _ => true,
+        }
+    }
+}
+
+impl Display for Cpu {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Cpu::Intel8080 => write!(f, "8080"),
+            Cpu::Intel8085 => write!(f, "8085"),
+        }
+    }
+}
+
+impl FromStr for Cpu {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "8080" | "i8080" => Ok(Cpu::Intel8080),
+            "8085" | "i8085" => Ok(Cpu::Intel8085),
+            _ => Err(format!("Invalid CPU '{}', expected '8080' or '8085'", s)),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
 pub enum InstructionOrData {
     Instruction(Instruction),
@@ -475,6 +540,32 @@ pub enum Instruction {
     Hlt,
     /// No op
     Nop,
+    /// Read interrupt masks (8085)
+    Rim,
+    /// Set interrupt masks (8085)
+    Sim,
+
+    // Undocumented 8085 instructions
+    /// Subtract BC from H and L
+    Dsub,
+    /// Arithmetic shift H and L right
+    Arhl,
+    /// Rotate D and E left through carry
+    Rdel,
+    /// Load D and E with H and L plus immediate
+    Ldhi(Data8),
+    /// Load D and E with SP plus immediate
+    Ldsi(Data8),
+    /// Store H and L indirect through D and E
+    Shlx,
+    /// Load H and L indirect through D and E
+    Lhlx,
+    /// Jump if not K
+    Jnk(Address),
+    /// Jump if K
+    Jk(Address),
+    /// Restart at 40H if V
+    Rstv,
 }
 
 impl Instruction {
@@ -537,6 +628,345 @@ impl Instruction {
             Instruction::Di => 1,
             Instruction::Hlt => 1,
             Instruction::Nop => 1,
+            Instruction::Rim => 1,
+            Instruction::Sim => 1,
+            Instruction::Dsub => 1,
+            Instruction::Arhl => 1,
+            Instruction::Rdel => 1,
+            Instruction::Ldhi(..) => 2,
+            Instruction::Ldsi(..) => 2,
+            Instruction::Shlx => 1,
+            Instruction::Lhlx => 1,
+            Instruction::Jnk(..) => 3,
+            Instruction::Jk(..) => 3,
+            Instruction::Rstv => 1,
+        }
+    }
+
+    /// The number of T-states (clock cycles) the instruction takes on `cpu`. `taken` is whether a
+    /// conditional branch was taken, and is ignored for other instructions.
+    pub fn cycles(&self, cpu: Cpu, taken: bool) -> u32 {
+        match cpu {
+            Cpu::Intel8080 => self.cycles_8080(taken),
+            Cpu::Intel8085 => self.cycles_8085(taken),
+        }
+    }
+
+    fn cycles_8080(&self, taken: bool) -> u32 {
+        match self {
+            Instruction::Mov(Register::M, _) | Instruction::Mov(_, Register::M) => 7,
+            Instruction::Mov(..) => 5,
+            Instruction::Mvi(Register::M, _) => 10,
+            Instruction::Mvi(..) => 7,
+            Instruction::Lxi(..) => 10,
+            Instruction::Lda(..) => 13,
+            Instruction::Sta(..) => 13,
+            Instruction::Lhld(..) => 16,
+            Instruction::Shld(..) => 16,
+            Instruction::Ldax(..) => 7,
+            Instruction::Stax(..) => 7,
+            Instruction::Xchg => 4,
+            Instruction::Add(register)
+            | Instruction::Adc(register)
+            | Instruction::Sub(register)
+            | Instruction::Sbb(register)
+            | Instruction::Ana(register)
+            | Instruction::Xra(register)
+            | Instruction::Ora(register)
+            | Instruction::Cmp(register) => {
+                if *register == Register::M {
+                    7
+                } else {
+                    4
+                }
+            }
+            Instruction::Adi(..) => 7,
+            Instruction::Aci(..) => 7,
+            Instruction::Sui(..) => 7,
+            Instruction::Sbi(..) => 7,
+            Instruction::Ani(..) => 7,
+            Instruction::Xri(..) => 7,
+            Instruction::Ori(..) => 7,
+            Instruction::Cpi(..) => 7,
+            Instruction::Inr(Register::M) | Instruction::Dcr(Register::M) => 10,
+            Instruction::Inr(..) | Instruction::Dcr(..) => 5,
+            Instruction::Inx(..) => 5,
+            Instruction::Dcx(..) => 5,
+            Instruction::Dad(..) => 10,
+            Instruction::Daa => 4,
+            Instruction::Rlc => 4,
+            Instruction::Rrc => 4,
+            Instruction::Ral => 4,
+            Instruction::Rar => 4,
+            Instruction::Cma => 4,
+            Instruction::Cmc => 4,
+            Instruction::Stc => 4,
+            Instruction::Jmp(..) => 10,
+            Instruction::Jcc(..) => 10,
+            Instruction::Call(..) => 17,
+            Instruction::Ccc(..) => {
+                if taken {
+                    17
+                } else {
+                    11
+                }
+            }
+            Instruction::Ret => 10,
+            Instruction::Rcc(..) => {
+                if taken {
+                    11
+                } else {
+                    5
+                }
+            }
+            Instruction::Rst(..) => 11,
+            Instruction::Pchl => 5,
+            Instruction::Push(..) => 11,
+            Instruction::Pop(..) => 10,
+            Instruction::Xthl => 18,
+            Instruction::Sphl => 5,
+            Instruction::In(..) => 10,
+            Instruction::Out(..) => 10,
+            Instruction::Ei => 4,
+            Instruction::Di => 4,
+            Instruction::Hlt => 7,
+            Instruction::Nop => 4,
+            // Not available on the 8080.
+            Instruction::Rim
+            | Instruction::Sim
+            | Instruction::Dsub
+            | Instruction::Arhl
+            | Instruction::Rdel
+            | Instruction::Ldhi(..)
+            | Instruction::Ldsi(..)
+            | Instruction::Shlx
+            | Instruction::Lhlx
+            | Instruction::Jnk(..)
+            | Instruction::Jk(..)
+            | Instruction::Rstv => self.cycles_8085(taken),
+        }
+    }
+
+    fn cycles_8085(&self, taken: bool) -> u32 {
+        match self {
+            Instruction::Mov(Register::M, _) | Instruction::Mov(_, Register::M) => 7,
+            Instruction::Mov(..) => 4,
+            Instruction::Mvi(Register::M, _) => 10,
+            Instruction::Mvi(..) => 7,
+            Instruction::Lxi(..) => 10,
+            Instruction::Lda(..) => 13,
+            Instruction::Sta(..) => 13,
+            Instruction::Lhld(..) => 16,
+            Instruction::Shld(..) => 16,
+            Instruction::Ldax(..) => 7,
+            Instruction::Stax(..) => 7,
+            Instruction::Xchg => 4,
+            Instruction::Add(register)
+            | Instruction::Adc(register)
+            | Instruction::Sub(register)
+            | Instruction::Sbb(register)
+            | Instruction::Ana(register)
+            | Instruction::Xra(register)
+            | Instruction::Ora(register)
+            | Instruction::Cmp(register) => {
+                if *register == Register::M {
+                    7
+                } else {
+                    4
+                }
+            }
+            Instruction::Adi(..) => 7,
+            Instruction::Aci(..) => 7,
+            Instruction::Sui(..) => 7,
+            Instruction::Sbi(..) => 7,
+            Instruction::Ani(..) => 7,
+            Instruction::Xri(..) => 7,
+            Instruction::Ori(..) => 7,
+            Instruction::Cpi(..) => 7,
+            Instruction::Inr(Register::M) | Instruction::Dcr(Register::M) => 10,
+            Instruction::Inr(..) | Instruction::Dcr(..) => 4,
+            Instruction::Inx(..) => 6,
+            Instruction::Dcx(..) => 6,
+            Instruction::Dad(..) => 10,
+            Instruction::Daa => 4,
+            Instruction::Rlc => 4,
+            Instruction::Rrc => 4,
+            Instruction::Ral => 4,
+            Instruction::Rar => 4,
+            Instruction::Cma => 4,
+            Instruction::Cmc => 4,
+            Instruction::Stc => 4,
+            Instruction::Jmp(..) => 10,
+            // Unlike the 8080, the 8085 skips fetching the high byte of the address when a
+            // conditional jump isn't taken.
+            Instruction::Jcc(..) | Instruction::Jnk(..) | Instruction::Jk(..) => {
+                if taken {
+                    10
+                } else {
+                    7
+                }
+            }
+            Instruction::Call(..) => 18,
+            Instruction::Ccc(..) => {
+                if taken {
+                    18
+                } else {
+                    9
+                }
+            }
+            Instruction::Ret => 10,
+            Instruction::Rcc(..) | Instruction::Rstv => {
+                if taken {
+                    12
+                } else {
+                    6
+                }
+            }
+            Instruction::Rst(..) => 12,
+            Instruction::Pchl => 6,
+            Instruction::Push(..) => 12,
+            Instruction::Pop(..) => 10,
+            Instruction::Xthl => 16,
+            Instruction::Sphl => 6,
+            Instruction::In(..) => 10,
+            Instruction::Out(..) => 10,
+            Instruction::Ei => 4,
+            Instruction::Di => 4,
+            Instruction::Hlt => 5,
+            Instruction::Nop => 4,
+            Instruction::Rim => 4,
+            Instruction::Sim => 4,
+            Instruction::Dsub => 10,
+            Instruction::Arhl => 7,
+            Instruction::Rdel => 10,
+            Instruction::Ldhi(..) => 10,
+            Instruction::Ldsi(..) => 10,
+            Instruction::Shlx => 10,
+            Instruction::Lhlx => 10,
+        }
+    }
+}
+
+// In assembly, register pairs are named after their first register.
+fn register_pair_operand(register_pair: RegisterPair) -> &'static str {
+    match register_pair {
+        RegisterPair::Bc => "B",
+        RegisterPair::De => "D",
+        RegisterPair::Hl => "H",
+        RegisterPair::Sp => "SP",
+    }
+}
+
+// Hexadecimal numbers must start with a digit in assembly.
+fn hex_operand(value: u16, digits: usize) -> String {
+    let hex = format!("{:0digits$X}H", value, digits = digits);
+    if hex.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        format!("0{}", hex)
+    } else {
+        hex
+    }
+}
+
+/// Formats the instruction as assembly, with numbers in hexadecimal.
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let d8 = |value: Data8| hex_operand(value as u16, 2);
+        let d16 = |value: u16| hex_operand(value, 4);
+        match *self {
+            Instruction::Mov(destination, source) => write!(f, "MOV {}, {}", destination, source),
+            Instruction::Mvi(register, data) => write!(f, "MVI {}, {}", register, d8(data)),
+            Instruction::Lxi(register_pair, data) => write!(
+                f,
+                "LXI {}, {}",
+                register_pair_operand(register_pair),
+                d16(data.value())
+            ),
+            Instruction::Lda(address) => write!(f, "LDA {}", d16(address)),
+            Instruction::Sta(address) => write!(f, "STA {}", d16(address)),
+            Instruction::Lhld(address) => write!(f, "LHLD {}", d16(address)),
+            Instruction::Shld(address) => write!(f, "SHLD {}", d16(address)),
+            Instruction::Ldax(register_pair) => write!(
+                f,
+                "LDAX {}",
+                register_pair_operand(register_pair.to_register_pair())
+            ),
+            Instruction::Stax(register_pair) => write!(
+                f,
+                "STAX {}",
+                register_pair_operand(register_pair.to_register_pair())
+            ),
+            Instruction::Xchg => write!(f, "XCHG"),
+            Instruction::Add(register) => write!(f, "ADD {}", register),
+            Instruction::Adi(data) => write!(f, "ADI {}", d8(data)),
+            Instruction::Adc(register) => write!(f, "ADC {}", register),
+            Instruction::Aci(data) => write!(f, "ACI {}", d8(data)),
+            Instruction::Sub(register) => write!(f, "SUB {}", register),
+            Instruction::Sui(data) => write!(f, "SUI {}", d8(data)),
+            Instruction::Sbb(register) => write!(f, "SBB {}", register),
+            Instruction::Sbi(data) => write!(f, "SBI {}", d8(data)),
+            Instruction::Inr(register) => write!(f, "INR {}", register),
+            Instruction::Dcr(register) => write!(f, "DCR {}", register),
+            Instruction::Inx(register_pair) => {
+                write!(f, "INX {}", register_pair_operand(register_pair))
+            }
+            Instruction::Dcx(register_pair) => {
+                write!(f, "DCX {}", register_pair_operand(register_pair))
+            }
+            Instruction::Dad(register_pair) => {
+                write!(f, "DAD {}", register_pair_operand(register_pair))
+            }
+            Instruction::Daa => write!(f, "DAA"),
+            Instruction::Ana(register) => write!(f, "ANA {}", register),
+            Instruction::Ani(data) => write!(f, "ANI {}", d8(data)),
+            Instruction::Xra(register) => write!(f, "XRA {}", register),
+            Instruction::Xri(data) => write!(f, "XRI {}", d8(data)),
+            Instruction::Ora(register) => write!(f, "ORA {}", register),
+            Instruction::Ori(data) => write!(f, "ORI {}", d8(data)),
+            Instruction::Cmp(register) => write!(f, "CMP {}", register),
+            Instruction::Cpi(data) => write!(f, "CPI {}", d8(data)),
+            Instruction::Rlc => write!(f, "RLC"),
+            Instruction::Rrc => write!(f, "RRC"),
+            Instruction::Ral => write!(f, "RAL"),
+            Instruction::Rar => write!(f, "RAR"),
+            Instruction::Cma => write!(f, "CMA"),
+            Instruction::Cmc => write!(f, "CMC"),
+            Instruction::Stc => write!(f, "STC"),
+            Instruction::Jmp(address) => write!(f, "JMP {}", d16(address)),
+            Instruction::Jcc(condition, address) => write!(f, "J{} {}", condition, d16(address)),
+            Instruction::Call(address) => write!(f, "CALL {}", d16(address)),
+            Instruction::Ccc(condition, address) => write!(f, "C{} {}", condition, d16(address)),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::Rcc(condition) => write!(f, "R{}", condition),
+            Instruction::Rst(restart_number) => write!(f, "RST {}", u16::from(restart_number)),
+            Instruction::Pchl => write!(f, "PCHL"),
+            Instruction::Push(register_pair) => match register_pair.to_register_pair() {
+                Some(register_pair) => write!(f, "PUSH {}", register_pair_operand(register_pair)),
+                None => write!(f, "PUSH PSW"),
+            },
+            Instruction::Pop(register_pair) => match register_pair.to_register_pair() {
+                Some(register_pair) => write!(f, "POP {}", register_pair_operand(register_pair)),
+                None => write!(f, "POP PSW"),
+            },
+            Instruction::Xthl => write!(f, "XTHL"),
+            Instruction::Sphl => write!(f, "SPHL"),
+            Instruction::In(port) => write!(f, "IN {}", d8(port)),
+            Instruction::Out(port) => write!(f, "OUT {}", d8(port)),
+            Instruction::Ei => write!(f, "EI"),
+            Instruction::Di => write!(f, "DI"),
+            Instruction::Hlt => write!(f, "HLT"),
+            Instruction::Nop => write!(f, "NOP"),
+            Instruction::Rim => write!(f, "RIM"),
+            Instruction::Sim => write!(f, "SIM"),
+            Instruction::Dsub => write!(f, "DSUB"),
+            Instruction::Arhl => write!(f, "ARHL"),
+            Instruction::Rdel => write!(f, "RDEL"),
+            Instruction::Ldhi(data) => write!(f, "LDHI {}", d8(data)),
+            Instruction::Ldsi(data) => write!(f, "LDSI {}", d8(data)),
+            Instruction::Shlx => write!(f, "SHLX"),
+            Instruction::Lhlx => write!(f, "LHLX"),
+            Instruction::Jnk(address) => write!(f, "JNK {}", d16(address)),
+            Instruction::Jk(address) => write!(f, "JK {}", d16(address)),
+            Instruction::Rstv => write!(f, "RSTV"),
         }
     }
 }
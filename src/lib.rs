@@ -1,6 +1,20 @@
-mod assembler;
-mod coding;
-mod instruction;
+//! An Intel 8080 (and 8085) assembler and emulator.
+//!
+//! The library can be used without the terminal UI:
+//!
+//! - `assembler::assemble` turns assembly source into a `Program`, reporting errors with their
+//!   line and column.
+//! - `coding::encode` and `coding::decode` convert single `instruction::Instruction`s to and from
+//!   machine code.
+//! - `machine::Machine::with_program` loads a program, and `Machine::run` executes it with a
+//!   budget of instructions.
+
+pub mod assembler;
+pub mod coding;
+pub mod instruction;
 pub mod machine;
 pub mod ui;
 pub mod cli;
+pub mod headless;
+pub mod clock;
+pub mod cpm;
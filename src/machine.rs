@@ -1,54 +1,34 @@
-use std::{fmt::Display, io::{self, Read}};
-
-use rand::Rng;
+use std::fmt::Display;
 
 use crate::{
-    coding::{self, reader::Reader},
+    coding::{
+        self,
+        table::{self, Opcode},
+    },
     instruction::{
-        Address, Condition, Data8, Data16, Instruction, Register, RegisterPair,
+        Address, Condition, Cpu, Data8, Data16, Instruction, Register, RegisterPair,
         RegisterPairOrStatus,
     },
+    machine::{
+        history::History,
+        hooks::MachineHooks,
+        i8085::InterruptLines,
+        io::{IoBus, IoContext},
+        memory::{Memory, MemoryFault},
+        random::Random,
+        trace::TraceSink,
+    },
 };
 
-static MEMORY_SIZE_BYTES: usize = 2 << 16;
-pub struct Memory([u8; MEMORY_SIZE_BYTES]);
-
-impl Memory {
-    pub fn new() -> Self {
-        Self([0; MEMORY_SIZE_BYTES])
-    }
-
-    pub fn read_8(&self, address: Address) -> Data8 {
-        self.0[address as usize]
-    }
-    pub fn read_16(&self, address: Address) -> Option<Data16> {
-        let low = self.0[address as usize];
-        let high = *self.0.get(address as usize + 1)?;
-        Some(Data16::new(low, high))
-    }
-
-    pub fn write_8(&mut self, address: Address, value: Data8) {
-        self.0[address as usize] = value;
-    }
-    #[must_use]
-    pub fn write_16(&mut self, address: Address, value: Data16) -> Option<()> {
-        self.0[address as usize] = value.low;
-        *self.0.get_mut(address as usize + 1)? = value.high;
-
-        Some(())
-    }
-
-    pub fn write_slice(&mut self, address: Address, value: &[u8]) -> Option<()> {
-        let range = (address as usize)..((address as usize) + value.len());
-        self.0
-            .get_mut(range)
-            .map(|dest| dest.copy_from_slice(value))
-    }
-
-    pub fn as_raw(&self) -> &[u8; MEMORY_SIZE_BYTES] {
-        &self.0
-    }
-}
+mod cache;
+pub mod history;
+pub mod hooks;
+pub mod i8085;
+pub mod io;
+pub mod memory;
+pub mod random;
+pub mod snapshot;
+pub mod trace;
 
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
 pub enum ConditionRegister {
@@ -57,15 +37,21 @@ pub enum ConditionRegister {
     Sign,
     Zero,
     Parity,
+    /// V, set on signed overflow. Undocumented, and only visible on the 8085.
+    Overflow,
+    /// K (also called X5 or UI), the sign of the exact result of an arithmetic instruction.
+    /// Undocumented, and only visible on the 8085.
+    UnderflowIndicator,
 }
 
+#[derive(Clone)]
 pub struct ConditionRegisters {
-    flags: [bool; 5],
+    flags: [bool; 7],
 }
 
 impl ConditionRegisters {
     pub fn new() -> Self {
-        Self { flags: [false; 5] }
+        Self { flags: [false; 7] }
     }
     fn condition_index(condition: ConditionRegister) -> usize {
         match condition {
@@ -74,6 +60,8 @@ impl ConditionRegisters {
             ConditionRegister::Zero => 2,
             ConditionRegister::Sign => 3,
             ConditionRegister::Parity => 4,
+            ConditionRegister::Overflow => 5,
+            ConditionRegister::UnderflowIndicator => 6,
         }
     }
 
@@ -87,6 +75,7 @@ impl ConditionRegisters {
 }
 
 // Struct containing program addressable registers.
+#[derive(Clone)]
 pub struct RegisterMap {
     a: Data8,
     b: Data8,
@@ -208,6 +197,8 @@ pub enum HaltReason {
     StackOverflow,
     StackUnderflow,
     MemoryOverflow,
+    RomWrite,
+    UnmappedMemory,
 }
 
 impl Display for HaltReason {
@@ -218,13 +209,80 @@ impl Display for HaltReason {
             HaltReason::StackOverflow => write!(f, "Stack overflowed"),
             HaltReason::StackUnderflow => write!(f, "Stack underflowed"),
             HaltReason::MemoryOverflow => write!(f, "Encountered invalid memory address"),
+            HaltReason::RomWrite => write!(f, "Attempted to write to ROM"),
+            HaltReason::UnmappedMemory => write!(f, "Accessed unmapped memory"),
         }
     }
 }
 
+impl HaltReason {
+    /// Process exit status used when a program halts in headless mode. Only a halt instruction is
+    /// considered a successful exit.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            HaltReason::HaltInstruction => 0,
+            HaltReason::InvalidInstruction => 2,
+            HaltReason::StackOverflow => 3,
+            HaltReason::StackUnderflow => 4,
+            HaltReason::MemoryOverflow => 5,
+            HaltReason::RomWrite => 6,
+            HaltReason::UnmappedMemory => 7,
+        }
+    }
+}
+
+impl From<MemoryFault> for HaltReason {
+    fn from(fault: MemoryFault) -> Self {
+        match fault {
+            MemoryFault::RomWrite(_) => HaltReason::RomWrite,
+            MemoryFault::Unmapped(_) => HaltReason::UnmappedMemory,
+        }
+    }
+}
+
+/// Where the machine was and what it was executing when it halted.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct HaltDiagnostics {
+    pub reason: HaltReason,
+    /// The address of the instruction which halted the machine. For an instruction supplied by an
+    /// interrupt, the PC when the interrupt was accepted.
+    pub pc: Address,
+    /// The machine code of the instruction. Only the opcode if it couldn't be decoded.
+    pub bytes: Vec<u8>,
+    /// The instruction, `None` if the machine halted before executing it.
+    pub instruction: Option<Instruction>,
+    /// SP before the instruction was executed.
+    pub sp: Address,
+    /// The memory address which was accessed, for stack and memory faults.
+    pub address: Option<Address>,
+}
+
+impl Display for HaltDiagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bytes = self
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<_>>()
+            .join(" ");
+        write!(f, "{} at PC={:04X}H: {}", self.reason, self.pc, bytes)?;
+        if let Some(instruction) = self.instruction {
+            write!(f, " ({})", instruction)?;
+        }
+        write!(f, ", SP={:04X}H", self.sp)?;
+        if let Some(address) = self.address {
+            write!(f, ", address {:04X}H", address)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
 pub enum MachineState {
     Running,
+    // Halted by a HLT instruction with interrupts enabled, or on the 8085, where TRAP can't be
+    // disabled. Execution resumes when an interrupt is raised.
+    WaitingForInterrupt,
     Halted(HaltReason),
 }
 
@@ -233,22 +291,74 @@ pub enum ExecutionResult {
     Running,
     ControlTransfer,
     Halt,
-    StackOverflow,
+    WaitForInterrupt,
+    // The overflow results are only generated in sanitizer mode, normally addresses wrap around.
+    // Each carries the address the instruction would have accessed.
+    StackOverflow(Address),
     // Is generated when the stack is popped too many times.
-    StackUnderflow,
-    // When an instruction attempts to write a 16-bit value to the very last byte of memory
-    MemoryOverflow,
+    StackUnderflow(Address),
+    // When an instruction attempts to access a 16-bit value at the very last byte of memory
+    MemoryOverflow(Address),
 }
 
 pub struct Machine {
+    cpu: Cpu,
     state: MachineState,
+    // Set when the machine halts.
+    halt_diagnostics: Option<HaltDiagnostics>,
     memory: Box<Memory>,
     registers: RegisterMap,
     conditions: ConditionRegisters,
     pc: Data16,
+    interrupts_enabled: bool,
+    // EI only enables interrupts after the instruction following it has been executed.
+    interrupt_enable_delay: bool,
+    pending_interrupt: Option<Instruction>,
+    interrupt_lines: InterruptLines,
+    io: IoBus,
+    random: Random,
+    // Report SP and 16-bit accesses wrapping around the address space instead of wrapping.
+    sanitize: bool,
+    // Halt on undocumented 8080 opcodes instead of executing them like the real CPU.
+    strict_opcodes: bool,
+    // T-states executed since the machine was created.
+    cycles: u64,
+    // Instructions executed since the machine was created, including interrupts.
+    steps: u64,
+    history: History,
+    trace: Option<Box<dyn TraceSink>>,
+    hooks: Option<Box<dyn MachineHooks>>,
     pub stdout: Vec<u8>,
 }
 
+// The outcome of a single `run_cycle`.
+struct Step {
+    state: MachineState,
+    cycles: u32,
+    // The instruction which was executed, `None` if the machine halted before executing anything.
+    instruction: Option<Instruction>,
+    // The memory address which made the machine halt, for stack and memory faults.
+    fault_address: Option<Address>,
+    // The bytes of the instruction read from memory, padded with zeros. Zeros for interrupts,
+    // whose instruction isn't read from memory.
+    bytes: [u8; 3],
+}
+
+impl Step {
+    fn halted(halt_reason: HaltReason, fault_address: Option<Address>) -> Self {
+        Self {
+            state: MachineState::Halted(halt_reason),
+            cycles: 0,
+            instruction: None,
+            fault_address,
+            bytes: [0; 3],
+        }
+    }
+}
+
+// T-states counted for each cycle spent waiting for an interrupt after HLT.
+static WAITING_CYCLES: u32 = 4;
+
 fn is_even(value: u32) -> bool {
     value % 2 == 0
 }
@@ -256,19 +366,84 @@ fn is_even(value: u32) -> bool {
 impl Machine {
     pub fn new() -> Self {
         Self {
+            cpu: Cpu::Intel8080,
             state: MachineState::Running,
+            halt_diagnostics: None,
             memory: Box::new(Memory::new()),
             registers: RegisterMap::new(),
             conditions: ConditionRegisters::new(),
             pc: Data16::ZERO,
+            interrupts_enabled: false,
+            interrupt_enable_delay: false,
+            pending_interrupt: None,
+            interrupt_lines: InterruptLines::default(),
+            io: IoBus::with_default_devices(),
+            random: Random::from_entropy(),
+            sanitize: false,
+            strict_opcodes: false,
+            cycles: 0,
+            steps: 0,
+            history: History::new(0),
+            trace: None,
+            hooks: None,
             stdout: Vec::new(),
         }
     }
 
+    /// Creates a machine with `program` loaded into RAM at `origin`, and the PC pointing at its
+    /// first byte. Returns `None` if the program doesn't fit below the end of memory.
+    pub fn with_program(origin: Address, program: &[u8]) -> Option<Self> {
+        let mut machine = Self::new();
+        machine.memory.write_slice(origin, program)?;
+        machine.pc = origin.into();
+        Some(machine)
+    }
+
+    /// Runs at most `step_budget` instructions, stopping early if the machine halts or starts
+    /// waiting for an interrupt. Returns the state it stopped in, which is `Running` if the budget
+    /// ran out. Use `is_finished` to tell whether the program has ended.
+    pub fn run(&mut self, step_budget: u64) -> MachineState {
+        for _ in 0..step_budget {
+            self.run_cycle();
+            if self.state != MachineState::Running {
+                break;
+            }
+        }
+        self.state
+    }
+
     pub fn state(&self) -> MachineState {
         self.state
     }
 
+    /// Whether the program has ended: the machine is halted, or it's an 8085 waiting after `HLT`
+    /// with interrupts disabled, which only TRAP can wake it from.
+    pub fn is_finished(&self) -> bool {
+        match self.state {
+            MachineState::Running => false,
+            MachineState::WaitingForInterrupt => !self.interrupts_enabled && !self.interrupt_ready(),
+            MachineState::Halted(_) => true,
+        }
+    }
+
+    /// Details about why the machine halted, `None` unless it's halted. Also `None` for a halted
+    /// machine restored from a snapshot, which doesn't contain them.
+    pub fn halt_diagnostics(&self) -> Option<&HaltDiagnostics> {
+        self.halt_diagnostics.as_ref()
+    }
+
+    pub fn cpu(&self) -> Cpu {
+        self.cpu
+    }
+
+    /// Selects the CPU to emulate, which decides the instruction set and timings. Defaults to the
+    /// 8080.
+    pub fn set_cpu(&mut self, cpu: Cpu) {
+        self.cpu = cpu;
+        // Cached instructions were decoded for the previous CPU.
+        self.memory.clear_instruction_cache();
+    }
+
     pub fn registers(&self) -> &RegisterMap {
         &self.registers
     }
@@ -285,6 +460,47 @@ impl Machine {
         &mut self.memory
     }
 
+    pub fn io(&self) -> &IoBus {
+        &self.io
+    }
+
+    pub fn io_mut(&mut self) -> &mut IoBus {
+        &mut self.io
+    }
+
+    /// Reseeds the random number generator read by devices, such as `IN 1`. Machines start with
+    /// a random seed.
+    pub fn set_random_seed(&mut self, seed: u64) {
+        self.random = Random::from_seed(seed);
+    }
+
+    pub fn random(&self) -> &Random {
+        &self.random
+    }
+
+    pub fn sanitizer_enabled(&self) -> bool {
+        self.sanitize
+    }
+
+    /// In sanitizer mode, the machine halts when the stack pointer or a 16-bit memory access wraps
+    /// around the end of the address space, which is usually a bug in the program.
+    pub fn set_sanitizer(&mut self, enabled: bool) {
+        self.sanitize = enabled;
+    }
+
+    pub fn strict_opcodes_enabled(&self) -> bool {
+        self.strict_opcodes
+    }
+
+    /// In strict mode, the undocumented 8080 opcodes halt the machine with
+    /// `HaltReason::InvalidInstruction`. Otherwise they're executed as the NOP, JMP, RET or CALL
+    /// they're aliases of.
+    pub fn set_strict_opcodes(&mut self, enabled: bool) {
+        self.strict_opcodes = enabled;
+        // Cached instructions may be aliases which strict mode rejects.
+        self.memory.clear_instruction_cache();
+    }
+
     pub fn register_8(&self, register: Register) -> Data8 {
         self.registers().get_8(register, self.memory())
     }
@@ -293,45 +509,140 @@ impl Machine {
         self.registers().get_16(register)
     }
 
+    /// Sets a register. Setting `M` writes to the memory HL points to.
+    pub fn set_register_8(&mut self, register: Register, value: Data8) {
+        self.registers.set_8(register, value, &mut self.memory);
+    }
+
+    pub fn set_register_16(&mut self, register: RegisterPair, value: Data16) {
+        self.registers.set_16(register, value);
+    }
+
     pub fn pc(&self) -> Data16 {
         self.pc
     }
 
+    /// Sets the address of the next instruction, e.g. to start a program which isn't loaded at
+    /// `0x0000`.
+    pub fn set_pc(&mut self, pc: Data16) {
+        self.pc = pc;
+    }
+
+    /// The total number of T-states (clock cycles) executed.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// The number of instructions executed, including instructions supplied by interrupts.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn interrupts_enabled(&self) -> bool {
+        self.interrupts_enabled
+    }
+
+    pub fn pending_interrupt(&self) -> Option<Instruction> {
+        self.pending_interrupt
+    }
+
+    /// Raises an interrupt which supplies `instruction` to the CPU, normally an `RST n`. The
+    /// interrupt stays pending until interrupts are enabled, and replaces any interrupt which is
+    /// already pending.
+    pub fn interrupt(&mut self, instruction: Instruction) {
+        self.pending_interrupt = Some(instruction);
+    }
+
+    /// Pushes `data` to the stack. Only fails in sanitizer mode, if SP would wrap below `0x0000`.
     #[must_use]
     pub fn stack_push(&mut self, data: Data16) -> Option<()> {
-        let new_sp = self.register_16(RegisterPair::Sp).checked_sub(2)?;
+        let sp = self.register_16(RegisterPair::Sp);
+        let new_sp = if self.sanitize {
+            sp.checked_sub(2)?
+        } else {
+            sp.value().wrapping_sub(2).into()
+        };
 
-        self.memory.write_16(new_sp.value(), data)?;
+        self.memory.write_16(new_sp.value(), data);
         self.registers.set_16(RegisterPair::Sp, new_sp);
 
         Some(())
     }
 
+    /// Pops a word from the stack. Only fails in sanitizer mode, if SP would wrap above `0xFFFF`.
     pub fn stack_pop(&mut self) -> Option<Data16> {
-        let value = self
-            .memory
-            .read_16(self.register_16(RegisterPair::Sp).value())?;
-        self.registers.set_16(
-            RegisterPair::Sp,
-            self.register_16(RegisterPair::Sp).checked_add(2)?,
-        );
+        let sp = self.register_16(RegisterPair::Sp);
+        let new_sp = if self.sanitize {
+            sp.checked_add(2)?
+        } else {
+            sp.value().wrapping_add(2).into()
+        };
+
+        let value = self.memory.read_16(sp.value());
+        self.registers.set_16(RegisterPair::Sp, new_sp);
 
         Some(value)
     }
 
+    // The result of a push which failed because SP would wrap below `0x0000`.
+    fn stack_overflow(&self) -> ExecutionResult {
+        let sp = self.register_16(RegisterPair::Sp);
+        ExecutionResult::StackOverflow(sp.value().wrapping_sub(2))
+    }
+
+    // The result of a pop which failed because SP would wrap above `0xFFFF`.
+    fn stack_underflow(&self) -> ExecutionResult {
+        ExecutionResult::StackUnderflow(self.register_16(RegisterPair::Sp).value())
+    }
+
+    // Whether the sanitizer should report a 16-bit access at `address`, which would wrap around.
+    fn word_access_wraps(&self, address: Address) -> bool {
+        self.sanitize && address == Address::MAX
+    }
+
+    // Sets the undocumented flags V and K after adding `term` to `a`. Subtractions add the one's
+    // complement. K is the sign of the exact result, which is the sign flag unless the result
+    // overflowed. Only the 8085 has them.
+    fn set_overflow_flags(&mut self, a: u8, term: u8, result: u8) {
+        if self.cpu != Cpu::Intel8085 {
+            return;
+        }
+        let v_flag = (a ^ result) & (term ^ result) & 0b1000_0000 != 0;
+        let k_flag = v_flag != (result & 0b1000_0000 != 0);
+        self.conditions.set(ConditionRegister::Overflow, v_flag);
+        self.conditions.set(ConditionRegister::UnderflowIndicator, k_flag);
+    }
+
+    // AC after ANA and ANI. The 8080 sets it to the OR of bit 3 of the operands, the 8085 always
+    // sets it.
+    fn and_auxiliary_carry(&self, a: u8, value: u8) -> bool {
+        match self.cpu {
+            Cpu::Intel8080 => (a | value) & 0b0000_1000 != 0,
+            Cpu::Intel8085 => true,
+        }
+    }
+
     fn get_status_word(&self) -> Data16 {
         let cy_flag = self.conditions.get(ConditionRegister::Carry) as u8;
         let p_flag = self.conditions.get(ConditionRegister::Parity) as u8;
         let ac_flag = self.conditions.get(ConditionRegister::AuxiliaryCarry) as u8;
         let z_flag = self.conditions.get(ConditionRegister::Zero) as u8;
         let s_flag = self.conditions.get(ConditionRegister::Sign) as u8;
+        // The 8080 has fixed values in the unused bits, where the 8085 stores V and K.
+        let (v_flag, k_flag) = match self.cpu {
+            Cpu::Intel8080 => (1, 0),
+            Cpu::Intel8085 => (
+                self.conditions.get(ConditionRegister::Overflow) as u8,
+                self.conditions.get(ConditionRegister::UnderflowIndicator) as u8,
+            ),
+        };
         let low = 0b0000_0000
             | cy_flag
-            | (1 << 1)
+            | (v_flag << 1)
             | (p_flag << 2)
             | (0 << 3)
             | (ac_flag << 4)
-            | (0 << 5)
+            | (k_flag << 5)
             | (z_flag << 6)
             | (s_flag << 7);
         let high = self.registers.get_8(Register::A, &self.memory);
@@ -351,45 +662,249 @@ impl Machine {
         self.conditions.set(ConditionRegister::AuxiliaryCarry, ac_flag == 1);
         self.conditions.set(ConditionRegister::Zero, z_flag == 1);
         self.conditions.set(ConditionRegister::Sign, s_flag == 1);
+        // The 8080 has no V and K flags, and ignores the bits they're stored in.
+        let (v_flag, k_flag) = match self.cpu {
+            Cpu::Intel8080 => (0, 0),
+            Cpu::Intel8085 => ((low >> 1) & 0b0000_0001, (low >> 5) & 0b0000_0001),
+        };
+        self.conditions.set(ConditionRegister::Overflow, v_flag == 1);
+        self.conditions.set(ConditionRegister::UnderflowIndicator, k_flag == 1);
 
         self.registers.set_8(Register::A, high, &mut self.memory);
     }
 
-    pub fn run_cycle(&mut self) {
+    /// Executes one instruction, or services a pending interrupt. Returns the number of T-states
+    /// this took.
+    pub fn run_cycle(&mut self) -> u32 {
         match self.state {
-            MachineState::Halted(_) => {}
-            MachineState::Running => {
-                self.state = self.load_execute();
+            MachineState::Halted(_) => return 0,
+            // The CPU keeps clocking while it waits, so time passes for timed devices.
+            MachineState::WaitingForInterrupt if !self.interrupt_ready() => {
+                self.cycles += WAITING_CYCLES as u64;
+                return WAITING_CYCLES;
             }
+            _ => {}
         }
+
+        let undo = self.begin_undo_record();
+        let trace = self.begin_trace();
+        let journaling = undo.is_some() || trace.is_some() || self.hooks.is_some();
+        if journaling {
+            self.memory.start_journal();
+        }
+        if self.hooks.is_some() {
+            self.memory.start_read_journal();
+        }
+
+        let pc = self.pc.value();
+        let sp = self.registers.get_16(RegisterPair::Sp).value();
+        let (step, interrupt) = match self.service_interrupt() {
+            Some(step) => (step, true),
+            None => (self.load_execute(), false),
+        };
+        self.state = step.state;
+        if let MachineState::Halted(reason) = step.state {
+            self.halt_diagnostics = Some(self.diagnose_halt(reason, &step, pc, sp, interrupt));
+        }
+        self.cycles += step.cycles as u64;
+        self.steps += 1;
+
+        let writes = if journaling {
+            self.memory.take_journal()
+        } else {
+            Vec::new()
+        };
+        if let (Some(trace), Some(instruction)) = (trace, step.instruction) {
+            self.finish_trace(trace, instruction, step.cycles, &step.bytes, &writes);
+        }
+        if self.hooks.is_some() {
+            let reads = self.memory.take_read_journal();
+            self.call_hooks(|hooks, machine| {
+                for &(address, value) in &reads {
+                    hooks.memory_read(address, value);
+                }
+                for write in &writes {
+                    hooks.memory_write(write.address, write.value);
+                }
+                if let Some(instruction) = step.instruction {
+                    hooks.after_instruction(machine, instruction, step.cycles);
+                }
+                // Only set when this step halted the machine.
+                if let Some(diagnostics) = &machine.halt_diagnostics {
+                    hooks.halt(machine, diagnostics);
+                }
+                // A waiting machine doesn't get here, so this step executed the HLT.
+                if step.state == MachineState::WaitingForInterrupt {
+                    hooks.wait_for_interrupt(machine);
+                }
+            });
+        }
+        if let Some(undo) = undo {
+            self.finish_undo_record(undo, writes);
+        }
+        step.cycles
     }
 
-    fn load_execute(&mut self) -> MachineState {
-        let mut stream = Reader::new(&self.memory().0[self.pc().value() as usize..]);
+    // Describes the step which halted the machine. `pc` and `sp` are the values before the step.
+    fn diagnose_halt(
+        &self,
+        reason: HaltReason,
+        step: &Step,
+        pc: Address,
+        sp: Address,
+        interrupt: bool,
+    ) -> HaltDiagnostics {
+        let bytes = match step.instruction {
+            Some(instruction) if interrupt => {
+                let mut bytes = Vec::new();
+                coding::encode(&mut bytes, instruction).expect("writing to Vec can't error");
+                bytes
+            }
+            Some(instruction) => (0..instruction.byte_length())
+                .map(|offset| self.memory.peek_8(pc.wrapping_add(offset)))
+                .collect(),
+            None => vec![self.memory.peek_8(pc)],
+        };
+        HaltDiagnostics {
+            reason,
+            pc,
+            bytes,
+            instruction: step.instruction,
+            sp,
+            address: step.fault_address,
+        }
+    }
 
-        let Some(instruction) = coding::decode(&mut stream) else {
-            return MachineState::Halted(HaltReason::InvalidInstruction);
+    fn interrupt_ready(&self) -> bool {
+        self.vectored_interrupt().is_some()
+            || (self.interrupts_enabled
+                && !self.interrupt_enable_delay
+                && self.pending_interrupt.is_some())
+    }
+
+    // Executes the pending interrupt instruction if interrupts are enabled, after any 8085
+    // interrupt inputs, which have priority. The PC isn't advanced, so an RST pushes the address
+    // of the instruction that would otherwise have been executed.
+    fn service_interrupt(&mut self) -> Option<Step> {
+        if let Some(input) = self.vectored_interrupt() {
+            return Some(self.service_vectored_interrupt(input));
+        }
+        if !self.interrupt_ready() {
+            return None;
+        }
+        let instruction = self.pending_interrupt.take()?;
+        self.call_hooks(|hooks, machine| hooks.before_instruction(machine, instruction));
+        self.interrupts_enabled = false;
+
+        self.memory.take_fault();
+        let result = self.execute(instruction);
+        let cycles = instruction.cycles(self.cpu, result == ExecutionResult::ControlTransfer);
+        Some(self.state_after(instruction, cycles, result))
+    }
+
+    // The bytes at the PC, read without going through the memory bus. Enough for the longest
+    // instruction.
+    fn peek_instruction_bytes(&self) -> [u8; 3] {
+        let pc = self.pc.value();
+        [0, 1, 2].map(|offset| self.memory.peek_8(pc.wrapping_add(offset)))
+    }
+
+    fn load_execute(&mut self) -> Step {
+        // Faults can be latched by reads from outside the machine, such as the UI displaying M.
+        self.memory.take_fault();
+
+        let pc = self.pc.value();
+        let mut bytes = [0; 3];
+        let (opcode, instruction) = match self.memory.cached_instruction(pc) {
+            // Only instructions in RAM and ROM are cached, and reading them can't fault or have
+            // side effects.
+            Some(cached) => {
+                for offset in 0..cached.opcode.length {
+                    bytes[offset as usize] = self.memory.peek_8(pc.wrapping_add(offset));
+                }
+                (cached.opcode, cached.instruction)
+            }
+            None => {
+                // Each byte is fetched once through the memory bus, so that a device sees a single
+                // read, and executing unmapped memory is reported.
+                bytes[0] = self.memory.fetch_8(pc);
+                if let Some(fault) = self.memory.take_fault() {
+                    return Step::halted(fault.into(), Some(fault.address()));
+                }
+                let Some(opcode) = self.opcode(bytes[0]) else {
+                    return Step::halted(HaltReason::InvalidInstruction, None);
+                };
+                for offset in 1..opcode.length {
+                    bytes[offset as usize] = self.memory.fetch_8(pc.wrapping_add(offset));
+                }
+                if let Some(fault) = self.memory.take_fault() {
+                    return Step::halted(fault.into(), Some(fault.address()));
+                }
+                let instruction = opcode.decode(&bytes);
+
+                self.memory.cache_instruction(pc, opcode, instruction);
+                (opcode, instruction)
+            }
         };
-        let instruction_len = stream.read_amount_bytes() as u16;
+
+        self.call_hooks(|hooks, machine| hooks.before_instruction(machine, instruction));
+
+        // Like the real CPU, the PC points to the next instruction while executing.
+        self.pc = pc.wrapping_add(opcode.length).into();
+        self.interrupt_enable_delay = false;
 
         let result = self.execute(instruction);
-        if matches!(result, ExecutionResult::Running) {
-            self.pc = self.pc.value().wrapping_add(instruction_len).into();
+        let cycles = opcode.cycles(result == ExecutionResult::ControlTransfer);
+        Step {
+            bytes,
+            ..self.state_after(instruction, cycles, result)
         }
+    }
 
-        match result {
-            ExecutionResult::Running => MachineState::Running,
-            ExecutionResult::ControlTransfer => MachineState::Running,
-            ExecutionResult::Halt => MachineState::Halted(HaltReason::HaltInstruction),
-            ExecutionResult::StackOverflow => MachineState::Halted(HaltReason::StackOverflow),
-            ExecutionResult::StackUnderflow => MachineState::Halted(HaltReason::StackUnderflow),
-            ExecutionResult::MemoryOverflow => MachineState::Halted(HaltReason::MemoryOverflow),
+    fn state_after(&self, instruction: Instruction, cycles: u32, result: ExecutionResult) -> Step {
+        if let Some(fault) = self.memory.take_fault() {
+            return Step {
+                state: MachineState::Halted(fault.into()),
+                cycles,
+                instruction: Some(instruction),
+                fault_address: Some(fault.address()),
+                bytes: [0; 3],
+            };
+        }
+
+        let (state, fault_address) = match result {
+            ExecutionResult::Running => (MachineState::Running, None),
+            ExecutionResult::ControlTransfer => (MachineState::Running, None),
+            ExecutionResult::Halt => (MachineState::Halted(HaltReason::HaltInstruction), None),
+            ExecutionResult::WaitForInterrupt => (MachineState::WaitingForInterrupt, None),
+            ExecutionResult::StackOverflow(address) => {
+                (MachineState::Halted(HaltReason::StackOverflow), Some(address))
+            }
+            ExecutionResult::StackUnderflow(address) => {
+                (MachineState::Halted(HaltReason::StackUnderflow), Some(address))
+            }
+            ExecutionResult::MemoryOverflow(address) => {
+                (MachineState::Halted(HaltReason::MemoryOverflow), Some(address))
+            }
+        };
+        Step {
+            state,
+            cycles,
+            instruction: Some(instruction),
+            fault_address,
+            bytes: [0; 3],
         }
     }
     
+    // The opcode `byte` decodes to on the emulated CPU, `None` if it's invalid.
+    fn opcode(&self, byte: u8) -> Option<&'static Opcode> {
+        table::opcode(self.cpu, byte)
+            .filter(|opcode| !(self.strict_opcodes && opcode.alias_of.is_some()))
+    }
+
     pub fn load(&self) -> Option<Instruction> {
-        let mut stream = Reader::new(&self.memory().0[self.pc().value() as usize..]);
-        coding::decode(&mut stream)
+        let bytes = self.peek_instruction_bytes();
+        self.opcode(bytes[0]).map(|opcode| opcode.decode(&bytes))
     }
 
     fn execute(&mut self, instruction: Instruction) -> ExecutionResult {
@@ -421,16 +936,19 @@ impl Machine {
                 ExecutionResult::Running
             },
             Instruction::Lhld(address) => {
-                let Some(mem) = self.memory.read_16(address) else {
-                    return ExecutionResult::MemoryOverflow;
-                };
+                if self.word_access_wraps(address) {
+                    return ExecutionResult::MemoryOverflow(address);
+                }
+                let mem = self.memory.read_16(address);
                 self.registers.set_16(RegisterPair::Hl, mem);
                 ExecutionResult::Running
             },
             Instruction::Shld(address) => {
+                if self.word_access_wraps(address) {
+                    return ExecutionResult::MemoryOverflow(address);
+                }
                 let hl = self.registers.get_16(RegisterPair::Hl);
-                let res = self.memory.write_16(address, hl);
-                if matches!(res, None) { return ExecutionResult::MemoryOverflow }
+                self.memory.write_16(address, hl);
                 ExecutionResult::Running
             },
             Instruction::Ldax(register_pair_indirect) => {
@@ -462,7 +980,7 @@ impl Machine {
                 let cy_flag = (result >> 8) & 0b1 == 1;
                 let result = result as u8;
                 let z_flag = result == 0;
-                let s_flag = result & 0b1000_0000 == 1;
+                let s_flag = result & 0b1000_0000 != 0;
                 let p_flag = is_even(result.count_ones());
 
                 self.registers.set_8(Register::A, result, &mut self.memory);
@@ -471,6 +989,7 @@ impl Machine {
                 self.conditions.set(ConditionRegister::Parity, p_flag);
                 self.conditions.set(ConditionRegister::Carry, cy_flag);
                 self.conditions.set(ConditionRegister::AuxiliaryCarry, ac_flag);
+                self.set_overflow_flags(a, term, result);
                 ExecutionResult::Running
             }
             Instruction::Adi(term) => {
@@ -482,7 +1001,7 @@ impl Machine {
                 let cy_flag = (result >> 8) & 0b1 == 1;
                 let result = result as u8;
                 let z_flag = result == 0;
-                let s_flag = result & 0b1000_0000 == 1;
+                let s_flag = result & 0b1000_0000 != 0;
                 let p_flag = is_even(result.count_ones());
 
                 self.registers.set_8(Register::A, result, &mut self.memory);
@@ -491,6 +1010,7 @@ impl Machine {
                 self.conditions.set(ConditionRegister::Parity, p_flag);
                 self.conditions.set(ConditionRegister::Carry, cy_flag);
                 self.conditions.set(ConditionRegister::AuxiliaryCarry, ac_flag);
+                self.set_overflow_flags(a, term, result);
                 ExecutionResult::Running
             }
             Instruction::Adc(register) => {
@@ -504,7 +1024,7 @@ impl Machine {
                 let cy_flag = (result >> 8) & 0b1 == 1;
                 let result = result as u8;
                 let z_flag = result == 0;
-                let s_flag = result & 0b1000_0000 == 1;
+                let s_flag = result & 0b1000_0000 != 0;
                 let p_flag = is_even(result.count_ones());
 
                 self.registers.set_8(Register::A, result, &mut self.memory);
@@ -513,6 +1033,7 @@ impl Machine {
                 self.conditions.set(ConditionRegister::Parity, p_flag);
                 self.conditions.set(ConditionRegister::Carry, cy_flag);
                 self.conditions.set(ConditionRegister::AuxiliaryCarry, ac_flag);
+                self.set_overflow_flags(a, term, result);
                 ExecutionResult::Running
             }
             Instruction::Aci(term) => {
@@ -525,7 +1046,7 @@ impl Machine {
                 let cy_flag = (result >> 8) & 0b1 == 1;
                 let result = result as u8;
                 let z_flag = result == 0;
-                let s_flag = result & 0b1000_0000 == 1;
+                let s_flag = result & 0b1000_0000 != 0;
                 let p_flag = is_even(result.count_ones());
 
                 self.registers.set_8(Register::A, result, &mut self.memory);
@@ -534,21 +1055,22 @@ impl Machine {
                 self.conditions.set(ConditionRegister::Parity, p_flag);
                 self.conditions.set(ConditionRegister::Carry, cy_flag);
                 self.conditions.set(ConditionRegister::AuxiliaryCarry, ac_flag);
+                self.set_overflow_flags(a, term, result);
                 ExecutionResult::Running
             }
             Instruction::Sub(register) => {
                 let a = self.registers.get_8(Register::A, &self.memory);
                 let term = self.registers.get_8(register, &self.memory);
 
-                let term_complement = (!term).wrapping_add(1);
-                
-                let result = (a as u16) + (term_complement as u16);
+                // Subtraction is addition of the one's complement plus one. The carry out of
+                // that addition is the inverse of the borrow.
+                let result = (a as u16) + (!term as u16) + 1;
 
-                let ac_flag = calc_ac_flag_add(a, term_complement, false);
+                let ac_flag = calc_ac_flag_add(a, !term, true);
                 let cy_flag = (result >> 8) & 0b1 != 1;
                 let result = result as u8;
                 let z_flag = result == 0;
-                let s_flag = result & 0b1000_0000 == 1;
+                let s_flag = result & 0b1000_0000 != 0;
                 let p_flag = is_even(result.count_ones());
 
                 self.registers.set_8(Register::A, result, &mut self.memory);
@@ -557,20 +1079,21 @@ impl Machine {
                 self.conditions.set(ConditionRegister::Parity, p_flag);
                 self.conditions.set(ConditionRegister::Carry, cy_flag);
                 self.conditions.set(ConditionRegister::AuxiliaryCarry, ac_flag);
+                self.set_overflow_flags(a, !term, result);
                 ExecutionResult::Running
             }
             Instruction::Sui(term) => {
                 let a = self.registers.get_8(Register::A, &self.memory);
 
-                let term_complement = (!term).wrapping_add(1);
-                
-                let result = (a as u16) + (term_complement as u16);
+                // Subtraction is addition of the one's complement plus one. The carry out of
+                // that addition is the inverse of the borrow.
+                let result = (a as u16) + (!term as u16) + 1;
 
-                let ac_flag = calc_ac_flag_add(a, term_complement, false);
+                let ac_flag = calc_ac_flag_add(a, !term, true);
                 let cy_flag = (result >> 8) & 0b1 != 1;
                 let result = result as u8;
                 let z_flag = result == 0;
-                let s_flag = result & 0b1000_0000 == 1;
+                let s_flag = result & 0b1000_0000 != 0;
                 let p_flag = is_even(result.count_ones());
 
                 self.registers.set_8(Register::A, result, &mut self.memory);
@@ -579,23 +1102,22 @@ impl Machine {
                 self.conditions.set(ConditionRegister::Parity, p_flag);
                 self.conditions.set(ConditionRegister::Carry, cy_flag);
                 self.conditions.set(ConditionRegister::AuxiliaryCarry, ac_flag);
+                self.set_overflow_flags(a, !term, result);
                 ExecutionResult::Running
             }
             Instruction::Sbb(register) => {
                 let a = self.registers.get_8(Register::A, &self.memory);
                 let term = self.registers.get_8(register, &self.memory);
 
-                let cy_flag = self.conditions.get(ConditionRegister::Carry);
-                let (term, borrow) = term.overflowing_add(cy_flag as u8);
-                let term_complement = (!term).wrapping_add(1);
-                
-                let result = (a as u16) + (term_complement as u16);
+                // Like SUB, except that the carry in is the inverse of the borrow.
+                let borrow = self.conditions.get(ConditionRegister::Carry);
+                let result = (a as u16) + (!term as u16) + (!borrow as u16);
 
-                let ac_flag = calc_ac_flag_add(a, term_complement, false);
-                let cy_flag = (result >> 8) & 0b1 != 1 || borrow;
+                let ac_flag = calc_ac_flag_add(a, !term, !borrow);
+                let cy_flag = (result >> 8) & 0b1 != 1;
                 let result = result as u8;
                 let z_flag = result == 0;
-                let s_flag = result & 0b1000_0000 == 1;
+                let s_flag = result & 0b1000_0000 != 0;
                 let p_flag = is_even(result.count_ones());
 
                 self.registers.set_8(Register::A, result, &mut self.memory);
@@ -604,22 +1126,21 @@ impl Machine {
                 self.conditions.set(ConditionRegister::Parity, p_flag);
                 self.conditions.set(ConditionRegister::Carry, cy_flag);
                 self.conditions.set(ConditionRegister::AuxiliaryCarry, ac_flag);
+                self.set_overflow_flags(a, !term, result);
                 ExecutionResult::Running
             }
             Instruction::Sbi(term) => {
                 let a = self.registers.get_8(Register::A, &self.memory);
 
-                let cy_flag = self.conditions.get(ConditionRegister::Carry);
-                let (term, borrow) = term.overflowing_add(cy_flag as u8);
-                let term_complement = (!term).wrapping_add(1);
-                
-                let result = (a as u16) + (term_complement as u16);
+                // Like SUB, except that the carry in is the inverse of the borrow.
+                let borrow = self.conditions.get(ConditionRegister::Carry);
+                let result = (a as u16) + (!term as u16) + (!borrow as u16);
 
-                let ac_flag = calc_ac_flag_add(a, term_complement, false);
-                let cy_flag = (result >> 8) & 0b1 != 1 || !borrow;
+                let ac_flag = calc_ac_flag_add(a, !term, !borrow);
+                let cy_flag = (result >> 8) & 0b1 != 1;
                 let result = result as u8;
                 let z_flag = result == 0;
-                let s_flag = result & 0b1000_0000 == 1;
+                let s_flag = result & 0b1000_0000 != 0;
                 let p_flag = is_even(result.count_ones());
 
                 self.registers.set_8(Register::A, result, &mut self.memory);
@@ -628,6 +1149,7 @@ impl Machine {
                 self.conditions.set(ConditionRegister::Parity, p_flag);
                 self.conditions.set(ConditionRegister::Carry, cy_flag);
                 self.conditions.set(ConditionRegister::AuxiliaryCarry, ac_flag);
+                self.set_overflow_flags(a, !term, result);
                 ExecutionResult::Running
             }
             Instruction::Inr(register) => {
@@ -636,7 +1158,7 @@ impl Machine {
                 let result = value.wrapping_add(1);
                 let ac_flag = calc_ac_flag_add(value, 1, false);
                 let z_flag = result == 0;
-                let s_flag = result & 0b1000_0000 == 1;
+                let s_flag = result & 0b1000_0000 != 0;
                 let p_flag = is_even(result.count_ones());
                 
                 self.registers.set_8(register, result, &mut self.memory);
@@ -644,6 +1166,7 @@ impl Machine {
                 self.conditions.set(ConditionRegister::Sign, s_flag);
                 self.conditions.set(ConditionRegister::Parity, p_flag);
                 self.conditions.set(ConditionRegister::AuxiliaryCarry, ac_flag);
+                self.set_overflow_flags(value, 1, result);
                 ExecutionResult::Running
             }
             Instruction::Dcr(register) => {
@@ -652,7 +1175,7 @@ impl Machine {
                 let result = value.wrapping_sub(1);
                 let ac_flag = calc_ac_flag_add(value, 0b1111_1111, false);
                 let z_flag = result == 0;
-                let s_flag = result & 0b1000_0000 == 1;
+                let s_flag = result & 0b1000_0000 != 0;
                 let p_flag = is_even(result.count_ones());
                 
                 self.registers.set_8(register, result, &mut self.memory);
@@ -660,6 +1183,7 @@ impl Machine {
                 self.conditions.set(ConditionRegister::Sign, s_flag);
                 self.conditions.set(ConditionRegister::Parity, p_flag);
                 self.conditions.set(ConditionRegister::AuxiliaryCarry, ac_flag);
+                self.set_overflow_flags(value, 0b1111_1111, result);
                 ExecutionResult::Running
             }
             Instruction::Inx(register_pair) => {
@@ -668,6 +1192,10 @@ impl Machine {
                 let result = value.wrapping_add(1);
                 
                 self.registers.set_16(register_pair, result.into());
+                // Only the 8085 sets K, when the register pair overflows.
+                if self.cpu == Cpu::Intel8085 {
+                    self.conditions.set(ConditionRegister::UnderflowIndicator, result == 0x0000);
+                }
                 ExecutionResult::Running
             }
             Instruction::Dcx(register_pair) => {
@@ -676,6 +1204,10 @@ impl Machine {
                 let result = value.wrapping_sub(1);
                 
                 self.registers.set_16(register_pair, result.into());
+                // Only the 8085 sets K, when the register pair underflows.
+                if self.cpu == Cpu::Intel8085 {
+                    self.conditions.set(ConditionRegister::UnderflowIndicator, result == 0xFFFF);
+                }
                 ExecutionResult::Running
             }
             Instruction::Dad(register_pair) => {
@@ -705,34 +1237,29 @@ impl Machine {
                 //    flag is set, 6 is added to the most significant 4
                 //    bits of the accumulator
                 //
-                let mut ac_flag = self.conditions.get(ConditionRegister::AuxiliaryCarry);
-                let cy_flag = self.conditions.get(ConditionRegister::Carry);
-                let mut wrapped = false;
-                let mut a = self.registers.get_8(Register::A, &self.memory);
+                // Both corrections are added at once, and CY is only ever set, never cleared.
+                // The high digit is corrected if it's going to exceed 9 after correcting the low
+                // digit, which is the case for all values above 0x99.
+                let ac_flag = self.conditions.get(ConditionRegister::AuxiliaryCarry);
+                let mut cy_flag = self.conditions.get(ConditionRegister::Carry);
+                let a = self.registers.get_8(Register::A, &self.memory);
+
+                let mut correction = 0;
                 // 1.
-                let lsb = a & 0b0000_1111;
-                if lsb > 9 || ac_flag {
-                    ac_flag = lsb > 9;
-                    if a > 0b1111_1111 - 6 {
-                        wrapped = true;
-                    }
-                    a = a.wrapping_add(6);
-                } else {
-                    ac_flag = false;
+                if a & 0b0000_1111 > 9 || ac_flag {
+                    correction |= 0x06;
                 }
                 // 2.
-                let msb = (a >> 4) & 0b0000_1111;
-                if msb > 9 || cy_flag {
-                    if a > 0b1111_1111 - (6 << 4) {
-                        wrapped = true;
-                    }
-                    a = a.wrapping_add(6 << 4);
+                if a > 0x99 || cy_flag {
+                    correction |= 0x60;
+                    cy_flag = true;
                 }
+                let ac_flag = calc_ac_flag_add(a, correction, false);
+                let a = a.wrapping_add(correction);
 
                 let z_flag = a == 0;
-                let s_flag = a & 0b1000_0000 == 1;
+                let s_flag = a & 0b1000_0000 != 0;
                 let p_flag = is_even(a.count_ones());
-                let cy_flag = wrapped;
 
                 self.registers.set_8(Register::A, a, &mut self.memory);
                 self.conditions.set(ConditionRegister::Zero, z_flag);
@@ -748,14 +1275,16 @@ impl Machine {
                 
                 let result = a & value;
                 let z_flag = result == 0;
-                let s_flag = result & 0b1000_0000 == 1;
+                let s_flag = result & 0b1000_0000 != 0;
                 let p_flag = is_even(result.count_ones());
+                let ac_flag = self.and_auxiliary_carry(a, value);
 
                 self.registers.set_8(Register::A, result, &mut self.memory);
                 self.conditions.set(ConditionRegister::Zero, z_flag);
                 self.conditions.set(ConditionRegister::Sign, s_flag);
                 self.conditions.set(ConditionRegister::Parity, p_flag);
                 self.conditions.set(ConditionRegister::Carry, false);
+                self.conditions.set(ConditionRegister::AuxiliaryCarry, ac_flag);
                 ExecutionResult::Running
             }
             Instruction::Ani(value) => {
@@ -763,14 +1292,16 @@ impl Machine {
                 
                 let result = a & value;
                 let z_flag = result == 0;
-                let s_flag = result & 0b1000_0000 == 1;
+                let s_flag = result & 0b1000_0000 != 0;
                 let p_flag = is_even(result.count_ones());
+                let ac_flag = self.and_auxiliary_carry(a, value);
 
                 self.registers.set_8(Register::A, result, &mut self.memory);
                 self.conditions.set(ConditionRegister::Zero, z_flag);
                 self.conditions.set(ConditionRegister::Sign, s_flag);
                 self.conditions.set(ConditionRegister::Parity, p_flag);
                 self.conditions.set(ConditionRegister::Carry, false);
+                self.conditions.set(ConditionRegister::AuxiliaryCarry, ac_flag);
                 ExecutionResult::Running
             }
             Instruction::Xra(register) => {
@@ -779,14 +1310,16 @@ impl Machine {
                 
                 let result = a ^ value;
                 let z_flag = result == 0;
-                let s_flag = result & 0b1000_0000 == 1;
+                let s_flag = result & 0b1000_0000 != 0;
                 let p_flag = is_even(result.count_ones());
+                let ac_flag = false;
 
                 self.registers.set_8(Register::A, result, &mut self.memory);
                 self.conditions.set(ConditionRegister::Zero, z_flag);
                 self.conditions.set(ConditionRegister::Sign, s_flag);
                 self.conditions.set(ConditionRegister::Parity, p_flag);
                 self.conditions.set(ConditionRegister::Carry, false);
+                self.conditions.set(ConditionRegister::AuxiliaryCarry, ac_flag);
                 ExecutionResult::Running
             }
             Instruction::Xri(value) => {
@@ -794,14 +1327,16 @@ impl Machine {
                 
                 let result = a ^ value;
                 let z_flag = result == 0;
-                let s_flag = result & 0b1000_0000 == 1;
+                let s_flag = result & 0b1000_0000 != 0;
                 let p_flag = is_even(result.count_ones());
+                let ac_flag = false;
 
                 self.registers.set_8(Register::A, result, &mut self.memory);
                 self.conditions.set(ConditionRegister::Zero, z_flag);
                 self.conditions.set(ConditionRegister::Sign, s_flag);
                 self.conditions.set(ConditionRegister::Parity, p_flag);
                 self.conditions.set(ConditionRegister::Carry, false);
+                self.conditions.set(ConditionRegister::AuxiliaryCarry, ac_flag);
                 ExecutionResult::Running
             }
             Instruction::Ora(register) => {
@@ -810,14 +1345,16 @@ impl Machine {
                 
                 let result = a | value;
                 let z_flag = result == 0;
-                let s_flag = result & 0b1000_0000 == 1;
+                let s_flag = result & 0b1000_0000 != 0;
                 let p_flag = is_even(result.count_ones());
+                let ac_flag = false;
 
                 self.registers.set_8(Register::A, result, &mut self.memory);
                 self.conditions.set(ConditionRegister::Zero, z_flag);
                 self.conditions.set(ConditionRegister::Sign, s_flag);
                 self.conditions.set(ConditionRegister::Parity, p_flag);
                 self.conditions.set(ConditionRegister::Carry, false);
+                self.conditions.set(ConditionRegister::AuxiliaryCarry, ac_flag);
                 ExecutionResult::Running
             }
             Instruction::Ori(value) => {
@@ -825,29 +1362,31 @@ impl Machine {
                 
                 let result = a | value;
                 let z_flag = result == 0;
-                let s_flag = result & 0b1000_0000 == 1;
+                let s_flag = result & 0b1000_0000 != 0;
                 let p_flag = is_even(result.count_ones());
+                let ac_flag = false;
 
                 self.registers.set_8(Register::A, result, &mut self.memory);
                 self.conditions.set(ConditionRegister::Zero, z_flag);
                 self.conditions.set(ConditionRegister::Sign, s_flag);
                 self.conditions.set(ConditionRegister::Parity, p_flag);
                 self.conditions.set(ConditionRegister::Carry, false);
+                self.conditions.set(ConditionRegister::AuxiliaryCarry, ac_flag);
                 ExecutionResult::Running
             }
             Instruction::Cmp(register) => {
                 let a = self.registers.get_8(Register::A, &self.memory);
                 let term = self.registers.get_8(register, &self.memory);
 
-                let term_complement = (!term).wrapping_add(1);
-                
-                let result = (a as u16) + (term_complement as u16);
+                // Subtraction is addition of the one's complement plus one. The carry out of
+                // that addition is the inverse of the borrow.
+                let result = (a as u16) + (!term as u16) + 1;
 
-                let ac_flag = calc_ac_flag_add(a, term_complement, false);
+                let ac_flag = calc_ac_flag_add(a, !term, true);
                 let cy_flag = (result >> 8) & 0b1 != 1;
                 let result = result as u8;
                 let z_flag = result == 0;
-                let s_flag = result & 0b1000_0000 == 1;
+                let s_flag = result & 0b1000_0000 != 0;
                 let p_flag = is_even(result.count_ones());
 
                 // subtraction without actually storing the value
@@ -856,20 +1395,21 @@ impl Machine {
                 self.conditions.set(ConditionRegister::Parity, p_flag);
                 self.conditions.set(ConditionRegister::Carry, cy_flag);
                 self.conditions.set(ConditionRegister::AuxiliaryCarry, ac_flag);
+                self.set_overflow_flags(a, !term, result);
                 ExecutionResult::Running
             }
             Instruction::Cpi(term) => {
                 let a = self.registers.get_8(Register::A, &self.memory);
 
-                let term_complement = (!term).wrapping_add(1);
-                
-                let result = (a as u16) + (term_complement as u16);
+                // Subtraction is addition of the one's complement plus one. The carry out of
+                // that addition is the inverse of the borrow.
+                let result = (a as u16) + (!term as u16) + 1;
 
-                let ac_flag = calc_ac_flag_add(a, term_complement, false);
+                let ac_flag = calc_ac_flag_add(a, !term, true);
                 let cy_flag = (result >> 8) & 0b1 != 1;
                 let result = result as u8;
                 let z_flag = result == 0;
-                let s_flag = result & 0b1000_0000 == 1;
+                let s_flag = result & 0b1000_0000 != 0;
                 let p_flag = is_even(result.count_ones());
 
                 // subtraction without actually storing the value
@@ -878,17 +1418,18 @@ impl Machine {
                 self.conditions.set(ConditionRegister::Parity, p_flag);
                 self.conditions.set(ConditionRegister::Carry, cy_flag);
                 self.conditions.set(ConditionRegister::AuxiliaryCarry, ac_flag);
+                self.set_overflow_flags(a, !term, result);
                 ExecutionResult::Running
             }
             Instruction::Rlc => {
                 let cy_flag = (self.registers.a >> 7) & 0b1 == 1;
-                self.registers.a = self.registers.a.wrapping_shl(1);
+                self.registers.a = self.registers.a.rotate_left(1);
                 self.conditions.set(ConditionRegister::Carry, cy_flag);
                 ExecutionResult::Running
             },
             Instruction::Rrc => {
                 let cy_flag = self.registers.a & 0b1 == 1;
-                self.registers.a = self.registers.a.wrapping_shr(1);
+                self.registers.a = self.registers.a.rotate_right(1);
                 self.conditions.set(ConditionRegister::Carry, cy_flag);
                 ExecutionResult::Running
             },
@@ -896,7 +1437,7 @@ impl Machine {
                 let cy_flag = self.conditions.get(ConditionRegister::Carry);
                 let new_cy_flag = (self.registers.a >> 7) & 0b1 == 1;
                 self.registers.a = self.registers.a.wrapping_shl(1);
-                self.registers.a &= cy_flag as u8;
+                self.registers.a |= cy_flag as u8;
                 self.conditions.set(ConditionRegister::Carry, new_cy_flag);
                 ExecutionResult::Running
             },
@@ -904,7 +1445,7 @@ impl Machine {
                 let cy_flag = self.conditions.get(ConditionRegister::Carry);
                 let new_cy_flag = self.registers.a & 0b1 == 1;
                 self.registers.a = self.registers.a.wrapping_shr(1);
-                self.registers.a &= (cy_flag as u8) << 7;
+                self.registers.a |= (cy_flag as u8) << 7;
                 self.conditions.set(ConditionRegister::Carry, new_cy_flag);
                 ExecutionResult::Running
             },
@@ -947,12 +1488,11 @@ impl Machine {
                 }
             }
             Instruction::Call(address) => {
-                let next_address = u16::from(self.pc) + instruction.byte_length();
-                if self.stack_push(next_address.into()).is_some() {
+                if self.stack_push(self.pc).is_some() {
                     self.pc = address.into();
                     ExecutionResult::ControlTransfer
                 } else {
-                    ExecutionResult::StackOverflow
+                    self.stack_overflow()
                 }
             }
             Instruction::Ccc(condition, address) => {
@@ -967,12 +1507,11 @@ impl Machine {
                     Condition::ParityOdd => !self.conditions.get(ConditionRegister::Parity),
                 };
                 if should_call {
-                    let next_address = u16::from(self.pc) + instruction.byte_length();
-                    if should_call && self.stack_push(next_address.into()).is_some() {
+                    if self.stack_push(self.pc).is_some() {
                         self.pc = address.into();
                         ExecutionResult::ControlTransfer
                     } else {
-                        ExecutionResult::StackOverflow
+                        self.stack_overflow()
                     }
                 } else {
                     ExecutionResult::Running
@@ -983,7 +1522,7 @@ impl Machine {
                     self.pc = address;
                     ExecutionResult::ControlTransfer
                 }
-                None => ExecutionResult::StackUnderflow,
+                None => self.stack_underflow(),
             },
             Instruction::Rcc(condition) => {
                 let should_return = match condition {
@@ -1003,7 +1542,7 @@ impl Machine {
                             self.pc = address;
                             ExecutionResult::ControlTransfer
                         }
-                        None => ExecutionResult::StackUnderflow,
+                        None => self.stack_underflow(),
                     }
                 } else {
                     ExecutionResult::Running
@@ -1014,7 +1553,7 @@ impl Machine {
                     self.pc = (u16::from(restart_number) << 3).into();
                     ExecutionResult::ControlTransfer
                 } else {
-                    ExecutionResult::StackOverflow
+                    self.stack_overflow()
                 }
             },
             Instruction::Pchl => {
@@ -1029,7 +1568,7 @@ impl Machine {
                 };
                 match self.stack_push(data) {
                     Some(()) => ExecutionResult::Running,
-                    None => ExecutionResult::StackOverflow,
+                    None => self.stack_overflow(),
                 }
             }
             Instruction::Pop(register) => {
@@ -1042,19 +1581,18 @@ impl Machine {
                         }
                         ExecutionResult::Running
                     }
-                    None => ExecutionResult::StackOverflow,
+                    None => self.stack_underflow(),
                 }
             }
             Instruction::Xthl => {
                 let hl = self.registers.get_16(RegisterPair::Hl);
                 let sp = self.registers.get_16(RegisterPair::Sp);
-                let Some(stack_top) = self.memory.read_16(sp.into()) else {
-                    return ExecutionResult::StackOverflow;
-                };
-                self.registers.set_16(RegisterPair::Hl, stack_top);
-                if matches!(self.memory.write_16(sp.into(), hl), None) {
-                    return ExecutionResult::StackOverflow;
+                if self.word_access_wraps(sp.into()) {
+                    return ExecutionResult::StackOverflow(sp.into());
                 }
+                let stack_top = self.memory.read_16(sp.into());
+                self.registers.set_16(RegisterPair::Hl, stack_top);
+                self.memory.write_16(sp.into(), hl);
                 ExecutionResult::Running
             },
             Instruction::Sphl => {
@@ -1063,52 +1601,160 @@ impl Machine {
                 ExecutionResult::Running
             },
             Instruction::In(port) => {
-                let byte = match port {
-                    0 => {
-                        match io::stdin()
-                            .bytes()
-                            .next()
-                            .map(|res| res.expect("surely io doesn't error"))
-                        {
-                            Some(byte) => byte,
-                            None => return ExecutionResult::Halt,
-                        }
-                    }
-                    1 => {
-                        rand::rng().random()
-                    }
-                    _ => 0,
+                let mut context = IoContext {
+                    registers: &self.registers,
+                    stdout: &mut self.stdout,
+                    random: &mut self.random,
                 };
-                
+                let Some(byte) = self.io.input(port, &mut context) else {
+                    return ExecutionResult::Halt;
+                };
+                self.call_hooks(|hooks, _| hooks.port_in(port, byte));
+
                 self.registers.set_8(Register::A, byte, &mut self.memory);
 
                 ExecutionResult::Running
             }
             Instruction::Out(port) => {
-                match port {
-                    0 => {
-                        let byte = self.register_8(Register::A);
-                        self.stdout.push(byte);
-                        ExecutionResult::Running
-                    }
-                    1 => {
-                        let number = self.register_8(Register::A);
-                        self.stdout.extend_from_slice(format!("{}", number).as_bytes());
-                        ExecutionResult::Running
-                    }
-                    2 => {
-                        let number = self.register_16(RegisterPair::Hl).value();
-                        self.stdout.extend_from_slice(format!("{}", number).as_bytes());
-                        ExecutionResult::Running
-                    }
-                    _ => ExecutionResult::Running,
-                }
+                let value = self.register_8(Register::A);
+                let mut context = IoContext {
+                    registers: &self.registers,
+                    stdout: &mut self.stdout,
+                    random: &mut self.random,
+                };
+                self.io.output(port, value, &mut context);
+                self.call_hooks(|hooks, _| hooks.port_out(port, value));
+
+                ExecutionResult::Running
             },
-            // We don't support interrupts, equate EI and DI to NOP
-            Instruction::Ei => ExecutionResult::Running,
-            Instruction::Di => ExecutionResult::Running,
-            Instruction::Hlt => ExecutionResult::Halt,
+            Instruction::Ei => {
+                self.interrupts_enabled = true;
+                self.interrupt_enable_delay = true;
+                ExecutionResult::Running
+            }
+            Instruction::Di => {
+                self.interrupts_enabled = false;
+                self.interrupt_enable_delay = false;
+                ExecutionResult::Running
+            }
+            Instruction::Hlt => {
+                // TRAP can't be disabled, so the 8085 always waits for it.
+                if self.interrupts_enabled || self.cpu == Cpu::Intel8085 {
+                    ExecutionResult::WaitForInterrupt
+                } else {
+                    ExecutionResult::Halt
+                }
+            }
             Instruction::Nop => ExecutionResult::Running,
+            Instruction::Rim => {
+                let value = self.read_interrupt_masks();
+                self.registers.set_8(Register::A, value, &mut self.memory);
+                ExecutionResult::Running
+            }
+            Instruction::Sim => {
+                let a = self.registers.get_8(Register::A, &self.memory);
+                self.set_interrupt_masks(a);
+                ExecutionResult::Running
+            }
+            Instruction::Dsub => {
+                let hl = self.registers.get_16(RegisterPair::Hl);
+                let bc = self.registers.get_16(RegisterPair::Bc);
+
+                // Subtracts a byte at a time like SUB followed by SBB, so the flags are those of
+                // the high byte, except for Z which covers the whole result.
+                let low = (hl.low as u16) + (!bc.low as u16) + 1;
+                let borrow = (low >> 8) & 0b1 != 1;
+                let high = (hl.high as u16) + (!bc.high as u16) + (!borrow as u16);
+
+                let ac_flag = calc_ac_flag_add(hl.high, !bc.high, !borrow);
+                let cy_flag = (high >> 8) & 0b1 != 1;
+                let result = Data16::new(low as u8, high as u8);
+                let z_flag = result.value() == 0;
+                let s_flag = result.high & 0b1000_0000 != 0;
+                let p_flag = is_even(result.high.count_ones());
+
+                self.registers.set_16(RegisterPair::Hl, result);
+                self.conditions.set(ConditionRegister::Zero, z_flag);
+                self.conditions.set(ConditionRegister::Sign, s_flag);
+                self.conditions.set(ConditionRegister::Parity, p_flag);
+                self.conditions.set(ConditionRegister::Carry, cy_flag);
+                self.conditions.set(ConditionRegister::AuxiliaryCarry, ac_flag);
+                self.set_overflow_flags(hl.high, !bc.high, result.high);
+                ExecutionResult::Running
+            }
+            Instruction::Arhl => {
+                let hl = self.registers.get_16(RegisterPair::Hl).value();
+                let cy_flag = hl & 0b1 == 1;
+                // Shifting a signed value keeps the sign bit.
+                let result = ((hl as i16) >> 1) as u16;
+
+                self.registers.set_16(RegisterPair::Hl, result.into());
+                self.conditions.set(ConditionRegister::Carry, cy_flag);
+                ExecutionResult::Running
+            }
+            Instruction::Rdel => {
+                let de = self.registers.get_16(RegisterPair::De).value();
+                let cy_flag = self.conditions.get(ConditionRegister::Carry);
+                let new_cy_flag = (de >> 15) & 0b1 == 1;
+                let result = (de << 1) | cy_flag as u16;
+                // V is set when the sign changes.
+                let v_flag = (de ^ result) & 0x8000 != 0;
+
+                self.registers.set_16(RegisterPair::De, result.into());
+                self.conditions.set(ConditionRegister::Carry, new_cy_flag);
+                self.conditions.set(ConditionRegister::Overflow, v_flag);
+                ExecutionResult::Running
+            }
+            Instruction::Ldhi(offset) => {
+                let hl = self.registers.get_16(RegisterPair::Hl).value();
+                self.registers
+                    .set_16(RegisterPair::De, hl.wrapping_add(offset as u16).into());
+                ExecutionResult::Running
+            }
+            Instruction::Ldsi(offset) => {
+                let sp = self.registers.get_16(RegisterPair::Sp).value();
+                self.registers
+                    .set_16(RegisterPair::De, sp.wrapping_add(offset as u16).into());
+                ExecutionResult::Running
+            }
+            Instruction::Shlx => {
+                let address = self.registers.get_16(RegisterPair::De).value();
+                if self.word_access_wraps(address) {
+                    return ExecutionResult::MemoryOverflow(address);
+                }
+                let hl = self.registers.get_16(RegisterPair::Hl);
+                self.memory.write_16(address, hl);
+                ExecutionResult::Running
+            }
+            Instruction::Lhlx => {
+                let address = self.registers.get_16(RegisterPair::De).value();
+                if self.word_access_wraps(address) {
+                    return ExecutionResult::MemoryOverflow(address);
+                }
+                let mem = self.memory.read_16(address);
+                self.registers.set_16(RegisterPair::Hl, mem);
+                ExecutionResult::Running
+            }
+            Instruction::Jnk(address) | Instruction::Jk(address) => {
+                let k_flag = self.conditions.get(ConditionRegister::UnderflowIndicator);
+                if k_flag == matches!(instruction, Instruction::Jk(_)) {
+                    self.pc = address.into();
+                    ExecutionResult::ControlTransfer
+                } else {
+                    ExecutionResult::Running
+                }
+            }
+            Instruction::Rstv => {
+                if !self.conditions.get(ConditionRegister::Overflow) {
+                    return ExecutionResult::Running;
+                }
+                if self.stack_push(self.pc).is_some() {
+                    self.pc = 0x0040.into();
+                    ExecutionResult::ControlTransfer
+                } else {
+                    self.stack_overflow()
+                }
+            }
         }
     }
 }
@@ -1116,17 +1762,27 @@ impl Machine {
 fn calc_ac_flag_add(a: u8, b: u8, cy_flag: bool) -> bool {
     let a = a & 0b0000_1111;
     let b = b & 0b0000_1111;
-    (a + b + (cy_flag as u8)) & 0b0001_0000 == 1
+    (a + b + (cy_flag as u8)) & 0b0001_0000 != 0
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::time::Instant;
+    use crate::{
+        instruction::{Port, RestartNumber},
+        machine::{
+            io::IoDevice,
+            memory::{MemoryBus, RomWritePolicy, UnmappedPolicy},
+            trace::{TraceFormat, TraceWriter},
+        },
+    };
+    use std::{
+        cell::Cell,
+        sync::{Arc, Mutex},
+    };
 
     #[test]
     fn test_add_register() {
-        let now = Instant::now();
         let mut machine = Machine::new();
 
         machine.conditions.set(ConditionRegister::Carry, true);
@@ -1139,79 +1795,73 @@ mod tests {
             .set_8(Register::B, 0x00, &mut machine.memory);
 
         let result = machine.execute(Instruction::Add(Register::B));
-        let elapsed = now.elapsed();
-
-        // assert_eq!(result, ExecutionResult::Running);
 
-        // assert_eq!(0x6B, machine.register_8(Register::A));
-        println!(
-            "add: Time elapsed: {:?}, A: {:?}, Sign: {:?}",
-            elapsed,
-            machine.register_8(Register::A),
-            machine.conditions.get(ConditionRegister::Sign)
-        );
+        assert_eq!(result, ExecutionResult::Running);
+        assert_eq!(0x80, machine.register_8(Register::A));
+        assert!(machine.conditions.get(ConditionRegister::Sign));
+        assert!(!machine.conditions.get(ConditionRegister::Zero));
+        assert!(!machine.conditions.get(ConditionRegister::Parity));
+        assert!(!machine.conditions.get(ConditionRegister::Carry));
+        assert!(!machine.conditions.get(ConditionRegister::AuxiliaryCarry));
     }
     #[test]
     fn test_sub_register() {
-        let now = Instant::now();
         let mut machine = Machine::new();
 
-        // machine.conditions.set(ConditionRegister::Carry, true);
-
         machine
             .registers
             .set_8(Register::A, 0x20, &mut machine.memory);
         machine
             .registers
             .set_8(Register::B, 0x10, &mut machine.memory);
-        let result = machine.execute(Instruction::Sbi(66));
-        let elapsed = now.elapsed();
+        let result = machine.execute(Instruction::Sub(Register::B));
 
-        // assert_eq!(result, ExecutionResult::Running);
+        assert_eq!(result, ExecutionResult::Running);
+        assert_eq!(0x10, machine.register_8(Register::A));
+        assert!(!machine.conditions.get(ConditionRegister::Carry));
+        // No borrow out of the low nibble, which the 8080 reports as AC set.
+        assert!(machine.conditions.get(ConditionRegister::AuxiliaryCarry));
 
-        // assert_eq!(0x10, machine.register_8(Register::A));
+        let result = machine.execute(Instruction::Sbi(66));
 
-        println!("sub: Time elapsed: {:?}", elapsed);
+        assert_eq!(result, ExecutionResult::Running);
+        assert_eq!(0xCE, machine.register_8(Register::A));
+        assert!(machine.conditions.get(ConditionRegister::Carry));
+        assert!(machine.conditions.get(ConditionRegister::Sign));
     }
 
     #[test]
     fn test_inr_register() {
-        let now = Instant::now();
         let mut machine = Machine::new();
 
         machine
             .registers
             .set_8(Register::B, 0x00, &mut machine.memory);
         let result = machine.execute(Instruction::Inr(Register::B));
-        let elapsed = now.elapsed();
 
         assert_eq!(result, ExecutionResult::Running);
 
         assert_eq!(0x01, machine.register_8(Register::B));
-
-        println!("inr: Time elapsed: {:?}", elapsed);
+        assert!(!machine.conditions.get(ConditionRegister::Zero));
+        assert!(!machine.conditions.get(ConditionRegister::Sign));
+        assert!(!machine.conditions.get(ConditionRegister::Parity));
     }
 
     #[test]
     fn test_inx_register() {
-        let now = Instant::now();
         let mut machine = Machine::new();
 
         machine
             .registers
             .set_16(RegisterPair::Bc, 0xFF00.into());
         let result = machine.execute(Instruction::Inx(RegisterPair::Bc));
-        let elapsed = now.elapsed();
 
         assert_eq!(result, ExecutionResult::Running);
 
         assert_eq!(0xFF01, machine.register_16(RegisterPair::Bc).value());
-
-        println!("inx: Time elapsed: {:?}", elapsed);
     }
     #[test]
     fn test_ana_register() {
-        let now = Instant::now();
         let mut machine = Machine::new();
 
         machine
@@ -1220,15 +1870,666 @@ mod tests {
         machine
             .registers
             .set_8(Register::B, 0x0F, &mut machine.memory);
+        machine.conditions.set(ConditionRegister::Carry, true);
 
         let result = machine.execute(Instruction::Ana(Register::B));
 
-        let elapsed = now.elapsed();
+        assert_eq!(result, ExecutionResult::Running);
+        assert_eq!(0x0C, machine.register_8(Register::A));
+        assert!(!machine.conditions.get(ConditionRegister::Carry));
+        assert!(machine.conditions.get(ConditionRegister::Parity));
+        assert!(machine.conditions.get(ConditionRegister::AuxiliaryCarry));
+
+        machine.execute(Instruction::Xra(Register::A));
+        assert_eq!(0x00, machine.register_8(Register::A));
+        assert!(machine.conditions.get(ConditionRegister::Zero));
+        assert!(!machine.conditions.get(ConditionRegister::AuxiliaryCarry));
+    }
+
+    // Checks the flags of every 8-bit addition and subtraction against the definition of the
+    // 8080, where AC is the carry out of bit 3 and subtraction adds the one's complement.
+    #[test]
+    fn test_arithmetic_flags() {
+        let mut machine = Machine::new();
+
+        for a in 0..=255u8 {
+            for term in 0..=255u8 {
+                for carry in [false, true] {
+                    for (instruction, subtract, uses_carry) in [
+                        (Instruction::Adi(term), false, false),
+                        (Instruction::Aci(term), false, true),
+                        (Instruction::Sui(term), true, false),
+                        (Instruction::Sbi(term), true, true),
+                    ] {
+                        let addend = if subtract { !term } else { term };
+                        let carry_in = match (subtract, uses_carry) {
+                            (false, false) => false,
+                            (false, true) => carry,
+                            (true, false) => true,
+                            (true, true) => !carry,
+                        };
+                        let sum = a as u16 + addend as u16 + carry_in as u16;
+                        let result = sum as u8;
+
+                        machine.registers.a = a;
+                        machine.conditions.set(ConditionRegister::Carry, carry);
+                        machine.execute(instruction);
+
+                        let name = format!("{} with A={:02X} CY={}", instruction, a, carry);
+                        assert_eq!(machine.registers.a, result, "{}", name);
+                        let conditions = &machine.conditions;
+                        assert_eq!(conditions.get(ConditionRegister::Zero), result == 0, "{}", name);
+                        assert_eq!(conditions.get(ConditionRegister::Sign), result >= 0x80, "{}", name);
+                        assert_eq!(
+                            conditions.get(ConditionRegister::Parity),
+                            result.count_ones() % 2 == 0,
+                            "{}",
+                            name
+                        );
+                        assert_eq!(
+                            conditions.get(ConditionRegister::Carry),
+                            (sum > 0xFF) != subtract,
+                            "{}",
+                            name
+                        );
+                        assert_eq!(
+                            conditions.get(ConditionRegister::AuxiliaryCarry),
+                            (a ^ addend ^ result) & 0x10 != 0,
+                            "{}",
+                            name
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_daa() {
+        let mut machine = Machine::new();
+
+        // The example from the 8080 manual.
+        machine.registers.a = 0x9B;
+        machine.execute(Instruction::Daa);
+        assert_eq!(machine.registers.a, 0x01);
+        assert!(machine.conditions.get(ConditionRegister::Carry));
+        assert!(machine.conditions.get(ConditionRegister::AuxiliaryCarry));
+
+        // Adding BCD numbers: 38 + 45 = 83, and 99 + 01 = 100.
+        for (a, term, expected, carry) in [(0x38, 0x45, 0x83, false), (0x99, 0x01, 0x00, true)] {
+            machine.registers.a = a;
+            machine.execute(Instruction::Adi(term));
+            machine.execute(Instruction::Daa);
+            assert_eq!(machine.registers.a, expected);
+            assert_eq!(machine.conditions.get(ConditionRegister::Carry), carry);
+        }
+    }
+
+    #[test]
+    fn test_rotates() {
+        let mut machine = Machine::new();
+
+        machine.registers.a = 0b1000_0001;
+        machine.execute(Instruction::Rlc);
+        assert_eq!(machine.registers.a, 0b0000_0011);
+        assert!(machine.conditions.get(ConditionRegister::Carry));
+
+        machine.execute(Instruction::Rrc);
+        assert_eq!(machine.registers.a, 0b1000_0001);
+        assert!(machine.conditions.get(ConditionRegister::Carry));
+
+        // RAL and RAR rotate through the carry.
+        machine.conditions.set(ConditionRegister::Carry, false);
+        machine.execute(Instruction::Ral);
+        assert_eq!(machine.registers.a, 0b0000_0010);
+        assert!(machine.conditions.get(ConditionRegister::Carry));
+
+        machine.execute(Instruction::Rar);
+        assert_eq!(machine.registers.a, 0b1000_0001);
+        assert!(!machine.conditions.get(ConditionRegister::Carry));
+    }
+
+    #[test]
+    fn test_interrupt_after_ei() {
+        let mut machine = Machine::new();
+
+        // EI, NOP, NOP
+        machine.memory_mut().write_slice(0, &[0xFB, 0x00, 0x00]).unwrap();
+        machine.registers.set_16(RegisterPair::Sp, 0x1000.into());
+        machine.interrupt(Instruction::Rst(RestartNumber::R1));
+
+        machine.run_cycle();
+        assert_eq!(machine.pc().value(), 1);
+        assert!(machine.interrupts_enabled());
+
+        // The instruction following EI is executed before the interrupt is accepted.
+        machine.run_cycle();
+        assert_eq!(machine.pc().value(), 2);
+
+        machine.run_cycle();
+        assert_eq!(machine.pc().value(), 0x08);
+        assert!(!machine.interrupts_enabled());
+        assert_eq!(machine.pending_interrupt(), None);
+        assert_eq!(machine.stack_pop(), Some(Data16::from(2)));
+    }
+
+    #[test]
+    fn test_interrupt_ignored_when_disabled() {
+        let mut machine = Machine::new();
+
+        // NOP, NOP
+        machine.interrupt(Instruction::Rst(RestartNumber::R1));
+        machine.run_cycle();
+        machine.run_cycle();
+
+        assert_eq!(machine.pc().value(), 2);
+        assert_eq!(machine.pending_interrupt(), Some(Instruction::Rst(RestartNumber::R1)));
+    }
+
+    #[test]
+    fn test_hlt_waits_for_interrupt() {
+        let mut machine = Machine::new();
+
+        // EI, HLT
+        machine.memory_mut().write_slice(0, &[0xFB, 0x76]).unwrap();
+        machine.registers.set_16(RegisterPair::Sp, 0x1000.into());
+
+        machine.run_cycle();
+        machine.run_cycle();
+        assert_eq!(machine.state(), MachineState::WaitingForInterrupt);
+
+        machine.run_cycle();
+        assert_eq!(machine.state(), MachineState::WaitingForInterrupt);
+
+        machine.interrupt(Instruction::Rst(RestartNumber::R7));
+        machine.run_cycle();
+        assert_eq!(machine.state(), MachineState::Running);
+        assert_eq!(machine.pc().value(), 0x38);
+        assert_eq!(machine.stack_pop(), Some(Data16::from(2)));
+    }
+
+    #[test]
+    fn test_custom_io_device() {
+        struct Loopback(Vec<(Port, Data8)>);
+
+        impl IoDevice for Loopback {
+            fn input(&mut self, port: Port, _context: &mut IoContext) -> Option<Data8> {
+                Some(port.wrapping_mul(2))
+            }
+
+            fn output(&mut self, port: Port, value: Data8, _context: &mut IoContext) {
+                self.0.push((port, value));
+            }
+        }
+
+        let device = Arc::new(Mutex::new(Loopback(Vec::new())));
+        let mut machine = Machine::new();
+        machine.io_mut().attach(0x20, Box::new(device.clone()));
+        machine.io_mut().attach(0x21, Box::new(device.clone()));
+
+        let result = machine.execute(Instruction::In(0x21));
+        assert_eq!(result, ExecutionResult::Running);
+        assert_eq!(machine.register_8(Register::A), 0x42);
+
+        machine.execute(Instruction::Out(0x20));
+        machine.execute(Instruction::Out(0x33));
+        assert_eq!(device.lock().unwrap().0, vec![(0x20, 0x42)]);
+        assert!(machine.stdout.is_empty());
+    }
+
+    #[test]
+    fn test_hlt_halts_when_interrupts_disabled() {
+        let mut machine = Machine::new();
+
+        // HLT
+        machine.memory_mut().write_slice(0, &[0x76]).unwrap();
+        machine.run_cycle();
+
+        assert_eq!(machine.state(), MachineState::Halted(HaltReason::HaltInstruction));
+    }
+
+    #[test]
+    fn test_rom_write() {
+        let mut machine = Machine::new();
+
+        // MVI A, 0x42; STA 0x0010; HLT
+        machine
+            .memory_mut()
+            .write_slice(0, &[0x3E, 0x42, 0x32, 0x10, 0x00, 0x76])
+            .unwrap();
+        machine.memory_mut().map_rom(0x0000..=0x00FF);
+
+        machine.run_cycle();
+        machine.run_cycle();
+        assert_eq!(machine.state(), MachineState::Running);
+        assert_eq!(machine.memory().read_8(0x0010), 0x00);
+
+        machine.memory_mut().set_rom_write_policy(RomWritePolicy::Halt);
+        machine.pc = 0x0002.into();
+        machine.run_cycle();
+        assert_eq!(machine.state(), MachineState::Halted(HaltReason::RomWrite));
+    }
+
+    #[test]
+    fn test_unmapped_memory() {
+        let mut machine = Machine::new();
+        machine.memory_mut().map_unmapped(0x8000..=0xFFFF);
+
+        machine.registers.set_16(RegisterPair::Hl, 0x9000.into());
+        machine.execute(Instruction::Mov(Register::A, Register::M));
+        assert_eq!(machine.register_8(Register::A), 0xFF);
+
+        // Executing unmapped memory halts before anything is executed.
+        machine.memory_mut().set_unmapped_policy(UnmappedPolicy::Halt);
+        machine.pc = 0x8000.into();
+        machine.run_cycle();
+        assert_eq!(machine.state(), MachineState::Halted(HaltReason::UnmappedMemory));
+        assert_eq!(machine.pc().value(), 0x8000);
+    }
+
+    #[test]
+    fn test_memory_mapped_device() {
+        struct Screen(Vec<u8>);
+
+        impl MemoryBus for Screen {
+            fn read_8(&self, address: Address) -> Result<Data8, MemoryFault> {
+                Ok(self.0[address as usize])
+            }
+
+            fn write_8(&mut self, address: Address, value: Data8) -> Result<(), MemoryFault> {
+                self.0[address as usize] = value;
+                Ok(())
+            }
+        }
+
+        let screen = Arc::new(Mutex::new(Screen(vec![0; 0x100])));
+        let mut machine = Machine::new();
+        machine
+            .memory_mut()
+            .map_device(0xF000..=0xF0FF, Box::new(screen.clone()));
+
+        machine.registers.set_8(Register::A, b'x', &mut machine.memory);
+        machine.execute(Instruction::Sta(0xF003));
+        assert_eq!(screen.lock().unwrap().0[3], b'x');
+        assert_eq!(machine.memory().as_raw()[0xF003], 0x00);
+
+        screen.lock().unwrap().0[4] = b'y';
+        machine.execute(Instruction::Lda(0xF004));
+        assert_eq!(machine.register_8(Register::A), b'y');
+    }
+
+    #[test]
+    fn test_device_instruction_fetch() {
+        // Counts the reads of each byte, like a device which advances on every read.
+        struct Program {
+            bytes: Vec<u8>,
+            reads: Vec<Cell<usize>>,
+        }
+
+        impl MemoryBus for Program {
+            fn read_8(&self, address: Address) -> Result<Data8, MemoryFault> {
+                let reads = &self.reads[address as usize];
+                reads.set(reads.get() + 1);
+                Ok(self.bytes[address as usize])
+            }
+
+            fn write_8(&mut self, _address: Address, _value: Data8) -> Result<(), MemoryFault> {
+                Ok(())
+            }
+        }
+
+        // MVI A, 42H; NOP
+        let program = Arc::new(Mutex::new(Program {
+            bytes: vec![0x3E, 0x42, 0x00],
+            reads: vec![Cell::new(0); 3],
+        }));
+        let mut machine = Machine::new();
+        machine
+            .memory_mut()
+            .map_device(0x8000..=0x8002, Box::new(program.clone()));
+        // Tracing records the bytes of the instruction without reading them again.
+        machine.set_trace_sink(Some(Box::new(TraceWriter::new(std::io::sink(), TraceFormat::Text))));
+        machine.pc = 0x8000.into();
+        machine.run_cycle();
+
+        assert_eq!(machine.register_8(Register::A), 0x42);
+        let reads: Vec<_> = program.lock().unwrap().reads.iter().map(Cell::get).collect();
+        assert_eq!(reads, vec![1, 1, 0]);
+    }
 
-        println!(
-            "inx: Time elapsed: {:?}\nReturn: {:?}",
-            elapsed,
-            machine.registers.get_8(Register::A, &machine.memory)
+    #[test]
+    fn test_instruction_cache() {
+        let mut machine = Machine::new();
+        machine.memory_mut().set_instruction_cache(true);
+
+        // MVI B, 1; MVI A, 2; STA 0x0001; JMP 0x0000
+        machine
+            .memory_mut()
+            .write_slice(0, &[0x06, 0x01, 0x3E, 0x02, 0x32, 0x01, 0x00, 0xC3, 0x00, 0x00])
+            .unwrap();
+        machine.run_cycle();
+        assert_eq!(machine.register_8(Register::B), 1);
+
+        // The program overwrites the operand of the cached MVI B.
+        for _ in 0..4 {
+            machine.run_cycle();
+        }
+        assert_eq!(machine.register_8(Register::B), 2);
+
+        // Loading a new program also invalidates the cache.
+        machine.pc = 0x0000.into();
+        machine.memory_mut().write_slice(1, &[0x03]).unwrap();
+        machine.run_cycle();
+        assert_eq!(machine.register_8(Register::B), 3);
+
+        // Instructions read from devices aren't cached.
+        struct Program(Data8);
+
+        impl MemoryBus for Program {
+            fn read_8(&self, _address: Address) -> Result<Data8, MemoryFault> {
+                Ok(self.0)
+            }
+
+            fn write_8(&mut self, _address: Address, _value: Data8) -> Result<(), MemoryFault> {
+                Ok(())
+            }
+        }
+
+        let program = Arc::new(Mutex::new(Program(0x04))); // INR B
+        machine
+            .memory_mut()
+            .map_device(0x8000..=0x8000, Box::new(program.clone()));
+        machine.pc = 0x8000.into();
+        machine.run_cycle();
+        assert_eq!(machine.register_8(Register::B), 4);
+
+        program.lock().unwrap().0 = 0x05; // DCR B
+        machine.pc = 0x8000.into();
+        machine.run_cycle();
+        assert_eq!(machine.register_8(Register::B), 3);
+    }
+
+    #[test]
+    fn test_stack_wraps_around() {
+        let mut machine = Machine::new();
+
+        assert_eq!(machine.stack_push(0x1234.into()), Some(()));
+        assert_eq!(machine.register_16(RegisterPair::Sp).value(), 0xFFFE);
+        assert_eq!(machine.memory().read_16(0xFFFE), Data16::from(0x1234));
+
+        assert_eq!(machine.stack_pop(), Some(Data16::from(0x1234)));
+        assert_eq!(machine.register_16(RegisterPair::Sp).value(), 0x0000);
+    }
+
+    #[test]
+    fn test_word_access_wraps_around() {
+        let mut machine = Machine::new();
+        machine.registers.set_16(RegisterPair::Hl, 0xBEEF.into());
+
+        assert_eq!(machine.execute(Instruction::Shld(0xFFFF)), ExecutionResult::Running);
+        assert_eq!(machine.memory().read_8(0xFFFF), 0xEF);
+        assert_eq!(machine.memory().read_8(0x0000), 0xBE);
+    }
+
+    #[test]
+    fn test_sanitizer() {
+        let mut machine = Machine::new();
+        machine.set_sanitizer(true);
+
+        assert_eq!(machine.stack_push(0x1234.into()), None);
+        assert_eq!(machine.register_16(RegisterPair::Sp).value(), 0x0000);
+        assert_eq!(
+            machine.execute(Instruction::Lhld(0xFFFF)),
+            ExecutionResult::MemoryOverflow(0xFFFF)
         );
+
+        machine.registers.set_16(RegisterPair::Sp, 0xFFFE.into());
+        assert_eq!(machine.execute(Instruction::Ret), ExecutionResult::StackUnderflow(0xFFFE));
+    }
+
+    #[test]
+    fn test_run_with_budget() {
+        // MVI A, 1; JMP 0x0102 at 0x0100
+        let mut machine = Machine::with_program(0x100, &[0x3E, 0x01, 0xC3, 0x02, 0x01]).unwrap();
+        assert_eq!(machine.pc().value(), 0x100);
+        assert_eq!(machine.run(10), MachineState::Running);
+        assert_eq!(machine.steps(), 10);
+        assert_eq!(machine.register_8(Register::A), 1);
+
+        // HLT
+        let mut machine = Machine::with_program(0, &[0x76]).unwrap();
+        assert_eq!(machine.run(10), MachineState::Halted(HaltReason::HaltInstruction));
+        assert_eq!(machine.steps(), 1);
+
+        assert!(Machine::with_program(0xFFFF, &[0x00, 0x00]).is_none());
+    }
+
+    #[test]
+    fn test_halt_diagnostics() {
+        // NOP; CALL 0x1234
+        let mut machine = Machine::new();
+        machine.set_sanitizer(true);
+        machine.memory_mut().write_slice(0, &[0x00, 0xCD, 0x34, 0x12]).unwrap();
+        machine.run_cycle();
+        assert_eq!(machine.halt_diagnostics(), None);
+
+        machine.run_cycle();
+        let diagnostics = machine.halt_diagnostics().unwrap();
+        assert_eq!(diagnostics.reason, HaltReason::StackOverflow);
+        assert_eq!((diagnostics.pc, diagnostics.sp), (0x0001, 0x0000));
+        assert_eq!(diagnostics.bytes, [0xCD, 0x34, 0x12]);
+        assert_eq!(diagnostics.address, Some(0xFFFE));
+        assert_eq!(
+            diagnostics.to_string(),
+            "Stack overflowed at PC=0001H: CD 34 12 (CALL 1234H), SP=0000H, address FFFEH"
+        );
+
+        // Executing unmapped memory faults at the instruction's address.
+        let mut machine = Machine::new();
+        machine.memory_mut().map_unmapped(0x8000..=0xFFFF);
+        machine.memory_mut().set_unmapped_policy(UnmappedPolicy::Halt);
+        machine.pc = 0x8000.into();
+        machine.run_cycle();
+        let diagnostics = machine.halt_diagnostics().unwrap();
+        assert_eq!((diagnostics.instruction, diagnostics.address), (None, Some(0x8000)));
+
+        // Stepping back clears them.
+        let mut machine = Machine::new();
+        machine.set_history_capacity(1);
+        machine.set_strict_opcodes(true);
+        machine.memory_mut().write_slice(0, &[0xCB]).unwrap();
+        machine.run_cycle();
+        let diagnostics = machine.halt_diagnostics().unwrap();
+        assert_eq!(diagnostics.to_string(), "Encountered invalid instruction at PC=0000H: CB, SP=0000H");
+        assert!(machine.step_back());
+        assert_eq!(machine.halt_diagnostics(), None);
+    }
+
+    #[test]
+    fn test_cycles() {
+        let mut machine = Machine::new();
+
+        // MVI A, 0; CPI 1; CZ 0x0000; RNZ; HLT
+        machine
+            .memory_mut()
+            .write_slice(0, &[0x3E, 0x00, 0xFE, 0x01, 0xCC, 0x00, 0x00, 0xC0, 0x76])
+            .unwrap();
+        machine.registers.set_16(RegisterPair::Sp, 0x1000.into());
+        machine.stack_push(0x0008.into()).unwrap();
+
+        assert_eq!(machine.run_cycle(), 7);
+        assert_eq!(machine.run_cycle(), 7);
+        // Not taken
+        assert_eq!(machine.run_cycle(), 11);
+        // Taken
+        assert_eq!(machine.run_cycle(), 11);
+        assert_eq!(machine.pc().value(), 0x0008);
+        assert_eq!(machine.cycles(), 36);
+
+        assert_eq!(machine.run_cycle(), 7);
+        assert_eq!(machine.run_cycle(), 0);
+        assert_eq!(machine.cycles(), 43);
+    }
+
+    #[test]
+    fn test_overflow_flags() {
+        for cpu in [Cpu::Intel8080, Cpu::Intel8085] {
+            let mut machine = Machine::new();
+            machine.set_cpu(cpu);
+            let is_8085 = cpu == Cpu::Intel8085;
+            machine.registers.set_8(Register::A, 0x7F, &mut machine.memory);
+            machine.registers.set_8(Register::B, 0x01, &mut machine.memory);
+
+            // 0x7F + 1 overflows to 0x80, whose exact result is positive.
+            machine.execute(Instruction::Add(Register::B));
+            assert_eq!(machine.register_8(Register::A), 0x80);
+            assert_eq!(machine.conditions().get(ConditionRegister::Overflow), is_8085);
+            assert!(!machine.conditions().get(ConditionRegister::UnderflowIndicator));
+
+            // 0x80 - 1 overflows to 0x7F, whose exact result is negative.
+            machine.execute(Instruction::Sub(Register::B));
+            assert_eq!(machine.register_8(Register::A), 0x7F);
+            assert_eq!(machine.conditions().get(ConditionRegister::Overflow), is_8085);
+            assert_eq!(machine.conditions().get(ConditionRegister::UnderflowIndicator), is_8085);
+
+            machine.registers.set_16(RegisterPair::Bc, 0xFFFF.into());
+            machine.conditions.set(ConditionRegister::UnderflowIndicator, false);
+            machine.execute(Instruction::Inx(RegisterPair::Bc));
+            assert_eq!(machine.conditions().get(ConditionRegister::UnderflowIndicator), is_8085);
+        }
+    }
+
+    #[test]
+    fn test_8085_instructions() {
+        let mut machine = Machine::new();
+        machine.set_cpu(Cpu::Intel8085);
+        machine.registers.set_16(RegisterPair::Sp, 0x1000.into());
+        machine.registers.set_16(RegisterPair::Hl, 0x8003.into());
+        machine.registers.set_16(RegisterPair::Bc, 0x0004.into());
+
+        // DSUB; ARHL; RDEL; LDHI 0x10; SHLX; LDSI 0x02; LHLX
+        machine
+            .memory_mut()
+            .write_slice(0, &[0x08, 0x10, 0x18, 0x28, 0x10, 0xD9, 0x38, 0x02, 0xED])
+            .unwrap();
+
+        machine.run_cycle();
+        assert_eq!(machine.register_16(RegisterPair::Hl).value(), 0x7FFF);
+        assert!(!machine.conditions().get(ConditionRegister::Carry));
+        assert!(machine.conditions().get(ConditionRegister::Overflow));
+
+        machine.run_cycle();
+        assert_eq!(machine.register_16(RegisterPair::Hl).value(), 0x3FFF);
+        assert!(machine.conditions().get(ConditionRegister::Carry));
+
+        machine.registers.set_16(RegisterPair::De, 0x4001.into());
+        machine.run_cycle();
+        assert_eq!(machine.register_16(RegisterPair::De).value(), 0x8003);
+        assert!(!machine.conditions().get(ConditionRegister::Carry));
+
+        machine.run_cycle();
+        assert_eq!(machine.register_16(RegisterPair::De).value(), 0x400F);
+
+        machine.run_cycle();
+        assert_eq!(machine.memory().read_16(0x400F).value(), 0x3FFF);
+
+        machine.run_cycle();
+        assert_eq!(machine.register_16(RegisterPair::De).value(), 0x1002);
+
+        machine.memory_mut().write_16(0x1002, 0xBEEF.into());
+        machine.run_cycle();
+        assert_eq!(machine.register_16(RegisterPair::Hl).value(), 0xBEEF);
+    }
+
+    #[test]
+    fn test_8085_flags() {
+        let mut machine = Machine::new();
+        machine.set_cpu(Cpu::Intel8085);
+        machine.registers.set_16(RegisterPair::Sp, 0x1000.into());
+        machine.registers.set_16(RegisterPair::De, 0x0001.into());
+
+        // MVI A, 0x7F; INR A; PUSH PSW; RSTV
+        machine
+            .memory_mut()
+            .write_slice(0, &[0x3E, 0x7F, 0x3C, 0xF5, 0xCB])
+            .unwrap();
+        for _ in 0..3 {
+            machine.run_cycle();
+        }
+        // S, AC and V are set. K isn't, since the exact result 128 is positive.
+        assert_eq!(machine.memory().read_16(0x0FFE), Data16::new(0b1001_0010, 0x80));
+        assert_eq!(machine.run_cycle(), 12);
+        assert_eq!(machine.pc().value(), 0x40);
+
+        // DCX D; JK 0; DCX D; JK 0x1234
+        machine
+            .memory_mut()
+            .write_slice(0x40, &[0x1B, 0xFD, 0x00, 0x00, 0x1B, 0xFD, 0x34, 0x12])
+            .unwrap();
+        machine.run_cycle();
+        assert_eq!(machine.run_cycle(), 7);
+        // DE underflows, which sets K.
+        machine.run_cycle();
+        assert_eq!(machine.run_cycle(), 10);
+        assert_eq!(machine.pc().value(), 0x1234);
+    }
+
+    #[test]
+    fn test_8085_differences() {
+        // MVI A, 0x0F; ANI 0xF0; PUSH PSW; HLT
+        let program = [0x3E, 0x0F, 0xE6, 0xF0, 0xF5, 0x76];
+        let mut cycles = Vec::new();
+        for cpu in [Cpu::Intel8080, Cpu::Intel8085] {
+            let mut machine = Machine::new();
+            machine.set_cpu(cpu);
+            machine.registers.set_16(RegisterPair::Sp, 0x1000.into());
+            machine.memory_mut().write_slice(0, &program).unwrap();
+            for _ in 0..4 {
+                machine.run_cycle();
+            }
+            cycles.push(machine.cycles());
+
+            let flags = machine.stack_pop().unwrap().low;
+            match cpu {
+                // Bit 1 is always set, and AC is the OR of bit 3 of the operands.
+                Cpu::Intel8080 => assert_eq!(flags, 0b0101_0110),
+                // AC is always set.
+                Cpu::Intel8085 => assert_eq!(flags, 0b0101_0100),
+            }
+        }
+        assert_eq!(cycles, [7 + 7 + 11 + 7, 7 + 7 + 12 + 5]);
+
+        // The 8085 instructions are NOP on the 8080.
+        let mut machine = Machine::new();
+        machine.memory_mut().write_slice(0, &[0x20]).unwrap();
+        machine.run_cycle();
+        assert_eq!(machine.state(), MachineState::Running);
+        assert_eq!(machine.pc().value(), 1);
+    }
+
+    #[test]
+    fn test_undocumented_8080_opcodes() {
+        // 0x08 (NOP); 0xFD (CALL 0x0008); 0x00; 0xCB (JMP 0x0010) at 0x0008; 0xD9 (RET) at 0x0010
+        let mut machine = Machine::new();
+        machine.registers.set_16(RegisterPair::Sp, 0x1000.into());
+        machine.memory_mut().write_slice(0, &[0x08, 0xFD, 0x08, 0x00]).unwrap();
+        machine.memory_mut().write_slice(0x08, &[0xCB, 0x10, 0x00]).unwrap();
+        machine.memory_mut().write_slice(0x10, &[0xD9]).unwrap();
+
+        assert_eq!(machine.run_cycle(), 4);
+        assert_eq!(machine.run_cycle(), 17);
+        assert_eq!(machine.pc().value(), 0x08);
+        assert_eq!(machine.run_cycle(), 10);
+        assert_eq!(machine.pc().value(), 0x10);
+        assert_eq!(machine.run_cycle(), 10);
+        assert_eq!(machine.pc().value(), 0x04);
+
+        // Strict mode halts instead.
+        let mut machine = Machine::new();
+        machine.set_strict_opcodes(true);
+        machine.memory_mut().write_slice(0, &[0xCB, 0x10, 0x00]).unwrap();
+        assert_eq!(machine.load(), None);
+        machine.run_cycle();
+        assert_eq!(machine.state(), MachineState::Halted(HaltReason::InvalidInstruction));
     }
 }
@@ -0,0 +1,46 @@
+use crate::{
+    coding::table::Opcode,
+    instruction::{Address, Instruction},
+};
+
+/// An instruction which has already been decoded.
+#[derive(Copy, Clone, Debug)]
+pub(super) struct CachedInstruction {
+    pub(super) opcode: &'static Opcode,
+    pub(super) instruction: Instruction,
+}
+
+/// Decoded instructions, keyed by the address of their first byte. Owned by the memory, so that
+/// every write can invalidate the instructions it overwrites.
+pub(super) struct InstructionCache {
+    entries: Box<[Option<CachedInstruction>]>,
+}
+
+impl InstructionCache {
+    pub(super) fn new() -> Self {
+        Self {
+            entries: vec![None; 1 << 16].into_boxed_slice(),
+        }
+    }
+
+    pub(super) fn get(&self, address: Address) -> Option<CachedInstruction> {
+        self.entries[address as usize]
+    }
+
+    pub(super) fn insert(&mut self, address: Address, cached: CachedInstruction) {
+        self.entries[address as usize] = Some(cached);
+    }
+
+    /// Removes every instruction which contains the byte at `address`.
+    pub(super) fn invalidate(&mut self, address: Address) {
+        // Instructions are at most 3 bytes long, so only the 2 preceding addresses can start an
+        // instruction covering `address`.
+        for offset in 0..3 {
+            self.entries[address.wrapping_sub(offset) as usize] = None;
+        }
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.entries.fill(None);
+    }
+}
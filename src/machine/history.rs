@@ -0,0 +1,210 @@
+use std::collections::VecDeque;
+
+use crate::{
+    instruction::{Data16, Instruction},
+    machine::{
+        ConditionRegisters, Machine, MachineState, RegisterMap, i8085::InterruptLines,
+        memory::MemoryWrite, random::Random,
+    },
+};
+
+/// The machine state before an instruction was executed, and the memory writes it made.
+pub(super) struct UndoRecord {
+    registers: RegisterMap,
+    conditions: ConditionRegisters,
+    pc: Data16,
+    state: MachineState,
+    interrupts_enabled: bool,
+    interrupt_enable_delay: bool,
+    pending_interrupt: Option<Instruction>,
+    interrupt_lines: InterruptLines,
+    random: Random,
+    cycles: u64,
+    stdout_len: usize,
+    memory: Vec<MemoryWrite>,
+}
+
+/// The undo records of the most recently executed instructions, oldest first.
+pub(super) struct History {
+    records: VecDeque<UndoRecord>,
+    capacity: usize,
+}
+
+impl History {
+    pub(super) fn new(capacity: usize) -> Self {
+        Self {
+            records: VecDeque::new(),
+            capacity,
+        }
+    }
+}
+
+impl Machine {
+    /// Sets how many executed instructions are recorded so that they can be undone with
+    /// `step_back`. `0` disables recording, which is the default.
+    pub fn set_history_capacity(&mut self, capacity: usize) {
+        self.history.capacity = capacity;
+        while self.history.records.len() > capacity {
+            self.history.records.pop_front();
+        }
+    }
+
+    pub fn history_capacity(&self) -> usize {
+        self.history.capacity
+    }
+
+    /// The earliest step that the machine can be rewound to.
+    pub fn earliest_step(&self) -> u64 {
+        self.steps - self.history.records.len() as u64
+    }
+
+    pub fn clear_history(&mut self) {
+        self.history.records.clear();
+    }
+
+    pub(super) fn begin_undo_record(&mut self) -> Option<UndoRecord> {
+        if self.history.capacity == 0 {
+            return None;
+        }
+
+        Some(UndoRecord {
+            registers: self.registers.clone(),
+            conditions: self.conditions.clone(),
+            pc: self.pc,
+            state: self.state,
+            interrupts_enabled: self.interrupts_enabled,
+            interrupt_enable_delay: self.interrupt_enable_delay,
+            pending_interrupt: self.pending_interrupt,
+            interrupt_lines: self.interrupt_lines,
+            random: self.random,
+            cycles: self.cycles,
+            stdout_len: self.stdout.len(),
+            memory: Vec::new(),
+        })
+    }
+
+    pub(super) fn finish_undo_record(&mut self, mut record: UndoRecord, writes: Vec<MemoryWrite>) {
+        record.memory = writes;
+
+        if self.history.records.len() == self.history.capacity {
+            self.history.records.pop_front();
+        }
+        self.history.records.push_back(record);
+    }
+
+    /// Undoes the most recently executed instruction. Returns `false` if there is no recorded
+    /// instruction to undo.
+    ///
+    /// Input read from devices and writes to memory-mapped devices can't be undone, but output
+    /// written to `stdout` is removed.
+    pub fn step_back(&mut self) -> bool {
+        let Some(record) = self.history.records.pop_back() else {
+            return false;
+        };
+
+        for write in record.memory.into_iter().rev() {
+            if let Some(previous) = write.previous {
+                self.memory.restore_8(write.address, previous);
+            }
+        }
+        self.registers = record.registers;
+        self.conditions = record.conditions;
+        self.pc = record.pc;
+        self.state = record.state;
+        self.halt_diagnostics = None;
+        self.interrupts_enabled = record.interrupts_enabled;
+        self.interrupt_enable_delay = record.interrupt_enable_delay;
+        self.pending_interrupt = record.pending_interrupt;
+        self.interrupt_lines = record.interrupt_lines;
+        self.random = record.random;
+        self.cycles = record.cycles;
+        self.stdout
+            .truncate(record.stdout_len.min(self.stdout.len()));
+        self.steps -= 1;
+
+        true
+    }
+
+    /// Steps back until `step` instructions have been executed. Returns `false` without changing
+    /// the machine if `step` is in the future or older than the recorded history.
+    pub fn rewind_to(&mut self, step: u64) -> bool {
+        if step > self.steps || step < self.earliest_step() {
+            return false;
+        }
+        while self.steps > step {
+            self.step_back();
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        instruction::{Register, RegisterPair},
+        machine::{ConditionRegister, HaltReason, Machine, MachineState},
+    };
+
+    #[test]
+    fn test_step_back() {
+        let mut machine = Machine::new();
+        machine.set_history_capacity(100);
+
+        // LXI SP, 0x1000; MVI A, 0x41; STC; PUSH PSW; OUT 0; HLT
+        machine
+            .memory_mut()
+            .write_slice(0, &[0x31, 0x00, 0x10, 0x3E, 0x41, 0x37, 0xF5, 0xD3, 0x00, 0x76])
+            .unwrap();
+        for _ in 0..6 {
+            machine.run_cycle();
+        }
+        assert_eq!(machine.state(), MachineState::Halted(HaltReason::HaltInstruction));
+        assert_eq!(machine.steps(), 6);
+        assert_eq!(machine.stdout, b"A");
+
+        assert!(machine.step_back());
+        assert_eq!(machine.state(), MachineState::Running);
+        assert_eq!(machine.pc().value(), 9);
+
+        assert!(machine.step_back());
+        assert!(machine.stdout.is_empty());
+
+        assert!(machine.step_back());
+        assert_eq!(machine.register_16(RegisterPair::Sp).value(), 0x1000);
+        assert_eq!(machine.memory().read_16(0x0FFE).value(), 0x0000);
+
+        assert!(machine.step_back());
+        assert!(!machine.conditions().get(ConditionRegister::Carry));
+        assert_eq!(machine.steps(), 2);
+        assert_eq!(machine.cycles(), 17);
+
+        // Running forward again gives the same result.
+        for _ in 0..4 {
+            machine.run_cycle();
+        }
+        assert_eq!(machine.state(), MachineState::Halted(HaltReason::HaltInstruction));
+        assert_eq!(machine.memory().read_16(0x0FFE).value(), 0x4103);
+    }
+
+    #[test]
+    fn test_rewind_to() {
+        let mut machine = Machine::new();
+        machine.set_history_capacity(3);
+
+        // INR B, repeated
+        machine.memory_mut().write_slice(0, &[0x04; 10]).unwrap();
+        for _ in 0..10 {
+            machine.run_cycle();
+        }
+        assert_eq!(machine.earliest_step(), 7);
+
+        assert!(!machine.rewind_to(6));
+        assert!(!machine.rewind_to(11));
+        assert_eq!(machine.register_8(Register::B), 10);
+
+        assert!(machine.rewind_to(7));
+        assert_eq!(machine.register_8(Register::B), 7);
+        assert_eq!(machine.pc().value(), 7);
+        assert!(!machine.step_back());
+    }
+}
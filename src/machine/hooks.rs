@@ -0,0 +1,191 @@
+//! Callbacks which let embedders observe the machine as it executes, for debuggers, profilers and
+//! similar tools.
+
+use crate::{
+    instruction::{Address, Data8, Instruction, Port},
+    machine::{HaltDiagnostics, Machine},
+};
+
+/// Receives callbacks from `Machine::run_cycle`. Every method does nothing by default, so an
+/// implementation only needs to override the events it's interested in.
+///
+/// For each instruction the callbacks are made in this order: `before_instruction`, `port_in` or
+/// `port_out` while the instruction executes, `memory_read` and `memory_write` for the accesses it
+/// made, `after_instruction`, and finally `halt` if the instruction halted the machine, or
+/// `wait_for_interrupt` if it was a `HLT` which left the machine waiting for an interrupt.
+pub trait MachineHooks: Send {
+    /// Called before an instruction is executed, including an instruction supplied by an
+    /// interrupt. The machine hasn't changed yet, so `machine.pc()` is the address of the
+    /// instruction, or of the next instruction for an interrupt.
+    fn before_instruction(&mut self, _machine: &Machine, _instruction: Instruction) {}
+
+    /// Called after an instruction was executed, with the number of T-states it took.
+    fn after_instruction(&mut self, _machine: &Machine, _instruction: Instruction, _cycles: u32) {}
+
+    /// A byte read by the instruction, not including fetching the instruction itself.
+    fn memory_read(&mut self, _address: Address, _value: Data8) {}
+
+    /// A byte written by the instruction, whether or not the write reached RAM.
+    fn memory_write(&mut self, _address: Address, _value: Data8) {}
+
+    /// A byte read by `IN` from the device attached to `port`.
+    fn port_in(&mut self, _port: Port, _value: Data8) {}
+
+    /// A byte written by `OUT` to `port`.
+    fn port_out(&mut self, _port: Port, _value: Data8) {}
+
+    /// Called when the machine halts, for any reason.
+    fn halt(&mut self, _machine: &Machine, _diagnostics: &HaltDiagnostics) {}
+
+    /// Called when `HLT` leaves the machine waiting for an interrupt, which is how every `HLT` on
+    /// the 8085 and an `HLT` with interrupts enabled on the 8080 stop. Use
+    /// `Machine::is_finished` to tell whether an interrupt can still wake the machine.
+    fn wait_for_interrupt(&mut self, _machine: &Machine) {}
+}
+
+impl Machine {
+    /// Sets the hooks which are called as the machine executes, returning the previous hooks.
+    pub fn set_hooks(
+        &mut self,
+        hooks: Option<Box<dyn MachineHooks>>,
+    ) -> Option<Box<dyn MachineHooks>> {
+        std::mem::replace(&mut self.hooks, hooks)
+    }
+
+    // Calls `f` with the hooks, if any. The hooks are taken out of the machine during the call,
+    // so that they can inspect it.
+    pub(super) fn call_hooks(&mut self, f: impl FnOnce(&mut dyn MachineHooks, &Machine)) {
+        if let Some(mut hooks) = self.hooks.take() {
+            f(hooks.as_mut(), self);
+            self.hooks = Some(hooks);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{
+        instruction::{Cpu, Register, RegisterPair},
+        machine::{HaltReason, MachineState},
+    };
+
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl MachineHooks for Recorder {
+        fn before_instruction(&mut self, machine: &Machine, instruction: Instruction) {
+            let pc = machine.pc().value();
+            self.0.lock().unwrap().push(format!("{:04X} {}", pc, instruction));
+        }
+
+        fn after_instruction(&mut self, machine: &Machine, _: Instruction, cycles: u32) {
+            let a = machine.register_8(Register::A);
+            self.0.lock().unwrap().push(format!("A={:02X} T={}", a, cycles));
+        }
+
+        fn memory_read(&mut self, address: Address, value: Data8) {
+            self.0.lock().unwrap().push(format!("read {:04X}={:02X}", address, value));
+        }
+
+        fn memory_write(&mut self, address: Address, value: Data8) {
+            self.0.lock().unwrap().push(format!("write {:04X}={:02X}", address, value));
+        }
+
+        fn port_out(&mut self, port: Port, value: Data8) {
+            self.0.lock().unwrap().push(format!("out {:02X}={:02X}", port, value));
+        }
+
+        fn halt(&mut self, _: &Machine, diagnostics: &HaltDiagnostics) {
+            self.0.lock().unwrap().push(format!("halt {:?}", diagnostics.reason));
+        }
+
+        fn wait_for_interrupt(&mut self, machine: &Machine) {
+            let pc = machine.pc().value();
+            self.0.lock().unwrap().push(format!("wait {:04X} {}", pc, machine.is_finished()));
+        }
+    }
+
+    #[test]
+    fn test_hooks() {
+        // LDA 0x0010; INR A; STA 0x0011; OUT 0x33; HLT
+        let program = [0x3A, 0x10, 0x00, 0x3C, 0x32, 0x11, 0x00, 0xD3, 0x33, 0x76];
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut machine = Machine::new();
+        machine.memory_mut().write_slice(0, &program).unwrap();
+        machine.memory_mut().write_slice(0x10, &[0x41]).unwrap();
+        machine.set_hooks(Some(Box::new(Recorder(events.clone()))));
+        for _ in 0..5 {
+            machine.run_cycle();
+        }
+        assert_eq!(machine.state(), MachineState::Halted(HaltReason::HaltInstruction));
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                "0000 LDA 0010H",
+                "read 0010=41",
+                "A=41 T=13",
+                "0003 INR A",
+                "A=42 T=5",
+                "0004 STA 0011H",
+                "write 0011=42",
+                "A=42 T=13",
+                "0007 OUT 33H",
+                "out 33=42",
+                "A=42 T=10",
+                "0009 HLT",
+                "A=42 T=7",
+                "halt HaltInstruction",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hooks_see_stack_accesses() {
+        // LXI SP, 0x0100; PUSH B; POP D
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut machine = Machine::new();
+        machine.memory_mut().write_slice(0, &[0x31, 0x00, 0x01, 0xC5, 0xD1]).unwrap();
+        machine.set_register_16(RegisterPair::Bc, 0x1234.into());
+        machine.set_hooks(Some(Box::new(Recorder(events.clone()))));
+        for _ in 0..3 {
+            machine.run_cycle();
+        }
+
+        let events = events.lock().unwrap();
+        assert_eq!(events[3..6], ["write 00FE=34", "write 00FF=12", "A=00 T=11"]);
+        assert_eq!(events[7..9], ["read 00FE=34", "read 00FF=12"]);
+    }
+
+    #[test]
+    fn test_hooks_wait_for_interrupt() {
+        // DI; HLT
+        for (cpu, event) in [
+            (Cpu::Intel8080, "halt HaltInstruction"),
+            (Cpu::Intel8085, "wait 0002 true"),
+        ] {
+            let events = Arc::new(Mutex::new(Vec::new()));
+            let mut machine = Machine::new();
+            machine.set_cpu(cpu);
+            machine.memory_mut().write_slice(0, &[0xF3, 0x76]).unwrap();
+            machine.set_hooks(Some(Box::new(Recorder(events.clone()))));
+            for _ in 0..4 {
+                machine.run_cycle();
+            }
+
+            // Called once on entering the wait, not for every cycle spent waiting.
+            assert_eq!(events.lock().unwrap()[4..], [event]);
+        }
+
+        // EI; HLT
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut machine = Machine::new();
+        machine.memory_mut().write_slice(0, &[0xFB, 0x76]).unwrap();
+        machine.set_hooks(Some(Box::new(Recorder(events.clone()))));
+        machine.run_cycle();
+        machine.run_cycle();
+        assert_eq!(events.lock().unwrap().last().unwrap(), "wait 0002 false");
+    }
+}
@@ -0,0 +1,310 @@
+//! The interrupt inputs and serial lines which the 8085 adds to the 8080.
+
+use crate::{
+    instruction::{Address, Cpu, Data8, Instruction},
+    machine::{Machine, Step},
+};
+
+/// The interrupt inputs of the 8085, besides INTR which is raised with `Machine::interrupt`. Each
+/// one restarts at a fixed address instead of executing an instruction supplied by a device.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum InterruptInput {
+    /// Can't be masked or disabled.
+    Trap,
+    /// Latched when raised, until it's serviced or reset by SIM.
+    Rst75,
+    /// Level triggered, stays raised until the device lowers it.
+    Rst65,
+    /// Level triggered, stays raised until the device lowers it.
+    Rst55,
+}
+
+impl InterruptInput {
+    /// The address the CPU restarts at when servicing the interrupt.
+    pub fn vector(&self) -> Address {
+        match self {
+            InterruptInput::Trap => 0x0024,
+            InterruptInput::Rst75 => 0x003C,
+            InterruptInput::Rst65 => 0x0034,
+            InterruptInput::Rst55 => 0x002C,
+        }
+    }
+}
+
+// Bits of the accumulator used by SIM.
+const SIM_MASKS: u8 = 0b0000_0111;
+const SIM_MASK_SET_ENABLE: u8 = 0b0000_1000;
+const SIM_RESET_RST75: u8 = 0b0001_0000;
+const SIM_SERIAL_OUTPUT_ENABLE: u8 = 0b0100_0000;
+const SIM_SERIAL_OUTPUT: u8 = 0b1000_0000;
+
+// T-states to service TRAP or an RST n.5 interrupt, which is the same as executing RST.
+const VECTORED_INTERRUPT_CYCLES: u32 = 12;
+
+/// The state of the 8085 interrupt inputs and serial lines.
+#[derive(Copy, Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub(super) struct InterruptLines {
+    // RST 5.5, 6.5 and 7.5 masks in bits 0 to 2, as set by SIM.
+    masks: u8,
+    trap: bool,
+    rst75: bool,
+    rst65: bool,
+    rst55: bool,
+    serial_input: bool,
+    serial_output: bool,
+}
+
+impl InterruptLines {
+    fn masked(&self, input: InterruptInput) -> bool {
+        let bit = match input {
+            InterruptInput::Trap => return false,
+            InterruptInput::Rst75 => 2,
+            InterruptInput::Rst65 => 1,
+            InterruptInput::Rst55 => 0,
+        };
+        self.masks & (1 << bit) != 0
+    }
+
+    /// Packs the state into two bytes for snapshots.
+    pub(super) fn to_bytes(self) -> [u8; 2] {
+        let inputs = (self.rst55 as u8)
+            | (self.rst65 as u8) << 1
+            | (self.rst75 as u8) << 2
+            | (self.trap as u8) << 3
+            | (self.serial_input as u8) << 4
+            | (self.serial_output as u8) << 5;
+        [self.masks, inputs]
+    }
+
+    pub(super) fn from_bytes([masks, inputs]: [u8; 2]) -> Option<Self> {
+        if masks & !SIM_MASKS != 0 || inputs & !0b0011_1111 != 0 {
+            return None;
+        }
+        Some(Self {
+            masks,
+            rst55: inputs & 0b0000_0001 != 0,
+            rst65: inputs & 0b0000_0010 != 0,
+            rst75: inputs & 0b0000_0100 != 0,
+            trap: inputs & 0b0000_1000 != 0,
+            serial_input: inputs & 0b0001_0000 != 0,
+            serial_output: inputs & 0b0010_0000 != 0,
+        })
+    }
+}
+
+impl Machine {
+    /// Raises or lowers one of the 8085 interrupt inputs. TRAP and RST 7.5 are latched when
+    /// raised, so lowering them has no effect. The inputs are ignored on the 8080.
+    pub fn set_interrupt_input(&mut self, input: InterruptInput, active: bool) {
+        let lines = &mut self.interrupt_lines;
+        match input {
+            InterruptInput::Trap => lines.trap |= active,
+            InterruptInput::Rst75 => lines.rst75 |= active,
+            InterruptInput::Rst65 => lines.rst65 = active,
+            InterruptInput::Rst55 => lines.rst55 = active,
+        }
+    }
+
+    /// Whether an 8085 interrupt input is raised, or latched for TRAP and RST 7.5.
+    pub fn interrupt_input(&self, input: InterruptInput) -> bool {
+        let lines = &self.interrupt_lines;
+        match input {
+            InterruptInput::Trap => lines.trap,
+            InterruptInput::Rst75 => lines.rst75,
+            InterruptInput::Rst65 => lines.rst65,
+            InterruptInput::Rst55 => lines.rst55,
+        }
+    }
+
+    /// The level of the 8085 SOD line, which is set by SIM.
+    pub fn serial_output(&self) -> bool {
+        self.interrupt_lines.serial_output
+    }
+
+    /// Sets the level of the 8085 SID line, which is read by RIM.
+    pub fn set_serial_input(&mut self, level: bool) {
+        self.interrupt_lines.serial_input = level;
+    }
+
+    // The highest priority 8085 interrupt which can be serviced now. TRAP is serviced even when
+    // interrupts are disabled.
+    pub(super) fn vectored_interrupt(&self) -> Option<InterruptInput> {
+        if self.cpu != Cpu::Intel8085 {
+            return None;
+        }
+        if self.interrupt_lines.trap {
+            return Some(InterruptInput::Trap);
+        }
+        if !self.interrupts_enabled || self.interrupt_enable_delay {
+            return None;
+        }
+        [InterruptInput::Rst75, InterruptInput::Rst65, InterruptInput::Rst55]
+            .into_iter()
+            .find(|&input| self.interrupt_input(input) && !self.interrupt_lines.masked(input))
+    }
+
+    pub(super) fn service_vectored_interrupt(&mut self, input: InterruptInput) -> Step {
+        // The CPU pushes the PC and jumps to the vector, which is executed as a CALL.
+        let instruction = Instruction::Call(input.vector());
+        self.call_hooks(|hooks, machine| hooks.before_instruction(machine, instruction));
+
+        match input {
+            InterruptInput::Trap => self.interrupt_lines.trap = false,
+            InterruptInput::Rst75 => self.interrupt_lines.rst75 = false,
+            InterruptInput::Rst65 | InterruptInput::Rst55 => {}
+        }
+        self.interrupts_enabled = false;
+
+        self.memory.take_fault();
+        let result = self.execute(instruction);
+        self.state_after(instruction, VECTORED_INTERRUPT_CYCLES, result)
+    }
+
+    // The value RIM loads into the accumulator.
+    pub(super) fn read_interrupt_masks(&self) -> Data8 {
+        let lines = &self.interrupt_lines;
+        (lines.serial_input as u8) << 7
+            | (lines.rst75 as u8) << 6
+            | (lines.rst65 as u8) << 5
+            | (lines.rst55 as u8) << 4
+            | (self.interrupts_enabled as u8) << 3
+            | lines.masks
+    }
+
+    // Executes SIM with the accumulator `value`.
+    pub(super) fn set_interrupt_masks(&mut self, value: Data8) {
+        let lines = &mut self.interrupt_lines;
+        if value & SIM_MASK_SET_ENABLE != 0 {
+            lines.masks = value & SIM_MASKS;
+        }
+        if value & SIM_RESET_RST75 != 0 {
+            lines.rst75 = false;
+        }
+        if value & SIM_SERIAL_OUTPUT_ENABLE != 0 {
+            lines.serial_output = value & SIM_SERIAL_OUTPUT != 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        instruction::{Register, RegisterPair, RestartNumber},
+        machine::MachineState,
+    };
+
+    fn machine_8085(program: &[u8]) -> Machine {
+        let mut machine = Machine::new();
+        machine.set_cpu(Cpu::Intel8085);
+        machine.memory_mut().write_slice(0, program).unwrap();
+        machine.registers.set_16(RegisterPair::Sp, 0x1000.into());
+        machine
+    }
+
+    #[test]
+    fn test_rim_sim() {
+        // MVI A, 0x0E; SIM; MVI A, 0xC0; SIM; RIM
+        let mut machine = machine_8085(&[0x3E, 0x0E, 0x30, 0x3E, 0xC0, 0x30, 0x20]);
+        machine.set_serial_input(true);
+        machine.set_interrupt_input(InterruptInput::Rst65, true);
+        for _ in 0..5 {
+            machine.run_cycle();
+        }
+
+        assert!(machine.serial_output());
+        // SID, RST 6.5 pending, masks for RST 7.5 and 6.5.
+        assert_eq!(machine.register_8(Register::A), 0b1010_0110);
+    }
+
+    #[test]
+    fn test_vectored_interrupts() {
+        // EI, NOP, NOP, NOP
+        let mut machine = machine_8085(&[0xFB, 0x00, 0x00, 0x00]);
+        machine.interrupt(Instruction::Rst(RestartNumber::R1));
+        machine.set_interrupt_input(InterruptInput::Rst55, true);
+        machine.set_interrupt_input(InterruptInput::Rst75, true);
+        machine.run_cycle();
+        machine.run_cycle();
+
+        // RST 7.5 has the highest priority of the maskable interrupts, and its latch is cleared.
+        assert_eq!(machine.run_cycle(), 12);
+        assert_eq!(machine.pc().value(), 0x3C);
+        assert!(!machine.interrupt_input(InterruptInput::Rst75));
+        assert!(!machine.interrupts_enabled());
+        assert_eq!(machine.stack_pop(), Some(2.into()));
+
+        // RST 5.5 is level triggered, and still raised.
+        machine.interrupts_enabled = true;
+        machine.run_cycle();
+        assert_eq!(machine.pc().value(), 0x2C);
+
+        machine.set_interrupt_input(InterruptInput::Rst55, false);
+        machine.interrupts_enabled = true;
+        machine.run_cycle();
+        assert_eq!(machine.pc().value(), 0x08);
+    }
+
+    #[test]
+    fn test_masked_interrupts() {
+        // MVI A, 0x09; SIM; EI; NOP; NOP
+        let mut machine = machine_8085(&[0x3E, 0x09, 0x30, 0xFB, 0x00, 0x00]);
+        machine.set_interrupt_input(InterruptInput::Rst55, true);
+        for _ in 0..5 {
+            machine.run_cycle();
+        }
+        assert_eq!(machine.pc().value(), 6);
+
+        // TRAP can't be masked or disabled.
+        machine.interrupts_enabled = false;
+        machine.set_interrupt_input(InterruptInput::Trap, true);
+        machine.run_cycle();
+        assert_eq!(machine.pc().value(), 0x24);
+        assert!(!machine.interrupt_input(InterruptInput::Trap));
+    }
+
+    #[test]
+    fn test_hlt_waits_for_rst75() {
+        // EI, HLT
+        let mut machine = machine_8085(&[0xFB, 0x76]);
+        machine.run_cycle();
+        machine.run_cycle();
+        assert_eq!(machine.state(), MachineState::WaitingForInterrupt);
+        assert!(!machine.is_finished());
+
+        machine.set_interrupt_input(InterruptInput::Rst75, true);
+        machine.run_cycle();
+        assert_eq!(machine.state(), MachineState::Running);
+        assert_eq!(machine.pc().value(), 0x3C);
+    }
+
+    #[test]
+    fn test_trap_wakes_hlt() {
+        // DI, HLT
+        let mut machine = machine_8085(&[0xF3, 0x76]);
+        machine.run_cycle();
+        machine.run_cycle();
+        assert_eq!(machine.state(), MachineState::WaitingForInterrupt);
+        // Nothing but TRAP can wake the CPU, so the program has ended.
+        assert!(machine.is_finished());
+
+        machine.set_interrupt_input(InterruptInput::Rst75, true);
+        machine.run_cycle();
+        assert_eq!(machine.state(), MachineState::WaitingForInterrupt);
+
+        machine.set_interrupt_input(InterruptInput::Trap, true);
+        assert!(!machine.is_finished());
+        machine.run_cycle();
+        assert_eq!(machine.state(), MachineState::Running);
+        assert_eq!(machine.pc().value(), 0x24);
+        assert_eq!(machine.stack_pop(), Some(2.into()));
+    }
+
+    #[test]
+    fn test_ignored_on_8080() {
+        let mut machine = Machine::new();
+        machine.set_interrupt_input(InterruptInput::Trap, true);
+        machine.run_cycle();
+        assert_eq!(machine.pc().value(), 1);
+    }
+}
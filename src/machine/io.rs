@@ -0,0 +1,146 @@
+use std::{
+    io::{self, Read},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    instruction::{Data8, Port, RegisterPair},
+    machine::{RegisterMap, random::Random},
+};
+
+/// Machine state available to a device while it handles an `IN` or `OUT` instruction.
+pub struct IoContext<'a> {
+    pub registers: &'a RegisterMap,
+    pub stdout: &'a mut Vec<u8>,
+    /// The machine's random number generator, which is part of its state so that runs with the
+    /// same seed are reproducible.
+    pub random: &'a mut Random,
+}
+
+/// A peripheral which can be attached to the I/O ports of a machine.
+pub trait IoDevice: Send {
+    /// Handles `IN port`. Returns the byte to load into the accumulator, or `None` to halt the
+    /// machine.
+    fn input(&mut self, port: Port, context: &mut IoContext) -> Option<Data8>;
+
+    /// Handles `OUT port`, where `value` is the contents of the accumulator.
+    fn output(&mut self, port: Port, value: Data8, context: &mut IoContext);
+}
+
+// Allows a device to be attached to several ports, or to be inspected by the embedder while it's
+// attached.
+impl<T: IoDevice> IoDevice for Arc<Mutex<T>> {
+    fn input(&mut self, port: Port, context: &mut IoContext) -> Option<Data8> {
+        self.lock().expect("device mutex poisoned").input(port, context)
+    }
+
+    fn output(&mut self, port: Port, value: Data8, context: &mut IoContext) {
+        self.lock().expect("device mutex poisoned").output(port, value, context)
+    }
+}
+
+/// Maps each of the 256 I/O ports to at most one device. `IN` from an unmapped port reads `0`,
+/// and `OUT` to an unmapped port is ignored.
+pub struct IoBus {
+    ports: Vec<Option<Box<dyn IoDevice>>>,
+}
+
+impl IoBus {
+    /// Creates a bus without any attached devices.
+    pub fn new() -> Self {
+        Self {
+            ports: (0..=Port::MAX).map(|_| None).collect(),
+        }
+    }
+
+    /// Creates a bus with the devices documented in the README attached to ports 0-2.
+    pub fn with_default_devices() -> Self {
+        let mut bus = Self::new();
+        bus.attach(0, Box::new(ConsoleDevice));
+        bus.attach(1, Box::new(NumberDevice));
+        bus.attach(2, Box::new(WordDevice));
+        bus
+    }
+
+    /// Attaches `device` to `port`, returning the device which was previously attached.
+    pub fn attach(&mut self, port: Port, device: Box<dyn IoDevice>) -> Option<Box<dyn IoDevice>> {
+        self.ports[port as usize].replace(device)
+    }
+
+    pub fn detach(&mut self, port: Port) -> Option<Box<dyn IoDevice>> {
+        self.ports[port as usize].take()
+    }
+
+    pub fn is_attached(&self, port: Port) -> bool {
+        self.ports[port as usize].is_some()
+    }
+
+    pub fn input(&mut self, port: Port, context: &mut IoContext) -> Option<Data8> {
+        match &mut self.ports[port as usize] {
+            Some(device) => device.input(port, context),
+            None => Some(0),
+        }
+    }
+
+    pub fn output(&mut self, port: Port, value: Data8, context: &mut IoContext) {
+        if let Some(device) = &mut self.ports[port as usize] {
+            device.output(port, value, context);
+        }
+    }
+}
+
+impl Default for IoBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reads bytes from stdin and writes bytes to the machine's stdout buffer. Reaching the end of
+/// stdin halts the machine.
+pub struct ConsoleDevice;
+
+impl IoDevice for ConsoleDevice {
+    fn input(&mut self, _port: Port, _context: &mut IoContext) -> Option<Data8> {
+        io::stdin()
+            .bytes()
+            .next()
+            .map(|res| res.expect("surely io doesn't error"))
+    }
+
+    fn output(&mut self, _port: Port, value: Data8, context: &mut IoContext) {
+        context.stdout.push(value);
+    }
+}
+
+/// Reads random bytes from the machine's random number generator, and writes bytes to the
+/// machine's stdout buffer formatted as decimal numbers.
+pub struct NumberDevice;
+
+impl IoDevice for NumberDevice {
+    fn input(&mut self, _port: Port, context: &mut IoContext) -> Option<Data8> {
+        Some(context.random.next_u8())
+    }
+
+    fn output(&mut self, _port: Port, value: Data8, context: &mut IoContext) {
+        context
+            .stdout
+            .extend_from_slice(format!("{}", value).as_bytes());
+    }
+}
+
+/// Writes the HL register pair to the machine's stdout buffer formatted as a decimal number,
+/// ignoring the accumulator. Reads `0`.
+pub struct WordDevice;
+
+impl IoDevice for WordDevice {
+    fn input(&mut self, _port: Port, _context: &mut IoContext) -> Option<Data8> {
+        Some(0)
+    }
+
+    fn output(&mut self, _port: Port, _value: Data8, context: &mut IoContext) {
+        let number = context.registers.get_16(RegisterPair::Hl).value();
+        context
+            .stdout
+            .extend_from_slice(format!("{}", number).as_bytes());
+    }
+}
@@ -0,0 +1,392 @@
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Display,
+    ops::RangeInclusive,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    coding::table::Opcode,
+    instruction::{Address, Data8, Data16, Instruction},
+    machine::cache::{CachedInstruction, InstructionCache},
+};
+
+static MEMORY_SIZE_BYTES: usize = 1 << 16;
+
+/// An invalid memory access. Faults are reported to the machine, which halts.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum MemoryFault {
+    RomWrite(Address),
+    Unmapped(Address),
+}
+
+impl MemoryFault {
+    /// The address which was accessed.
+    pub fn address(&self) -> Address {
+        match self {
+            MemoryFault::RomWrite(address) | MemoryFault::Unmapped(address) => *address,
+        }
+    }
+}
+
+impl Display for MemoryFault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MemoryFault::RomWrite(address) => write!(f, "write to ROM at 0x{:04X}", address),
+            MemoryFault::Unmapped(address) => write!(f, "unmapped access at 0x{:04X}", address),
+        }
+    }
+}
+
+/// Something which responds to reads and writes on the memory bus, such as memory-mapped I/O.
+/// Addresses are relative to the start of the region the device is mapped to.
+pub trait MemoryBus: Send {
+    /// Reads take `&self` since memory is also read when displaying the machine. Devices which
+    /// need to react to reads can use interior mutability.
+    fn read_8(&self, address: Address) -> Result<Data8, MemoryFault>;
+
+    fn write_8(&mut self, address: Address, value: Data8) -> Result<(), MemoryFault>;
+}
+
+// Allows a device to be inspected by the embedder while it's mapped.
+impl<T: MemoryBus> MemoryBus for Arc<Mutex<T>> {
+    fn read_8(&self, address: Address) -> Result<Data8, MemoryFault> {
+        self.lock().expect("device mutex poisoned").read_8(address)
+    }
+
+    fn write_8(&mut self, address: Address, value: Data8) -> Result<(), MemoryFault> {
+        self.lock()
+            .expect("device mutex poisoned")
+            .write_8(address, value)
+    }
+}
+
+/// A write made by the program.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct MemoryWrite {
+    pub address: Address,
+    pub value: Data8,
+    /// The value of the RAM byte which was overwritten, or `None` if the write wasn't made to RAM.
+    pub previous: Option<Data8>,
+}
+
+/// What happens when a program writes to ROM.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum RomWritePolicy {
+    Ignore,
+    Halt,
+}
+
+/// What happens when a program accesses an unmapped region.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum UnmappedPolicy {
+    /// Reads return the given value, like a floating data bus, and writes are ignored.
+    Open(Data8),
+    Halt,
+}
+
+pub enum Region {
+    /// Backed by RAM, but can't be written by the program.
+    Rom,
+    Device(Box<dyn MemoryBus>),
+    Unmapped,
+}
+
+struct MappedRegion {
+    range: RangeInclusive<Address>,
+    region: Region,
+}
+
+/// The memory bus of the machine. Addresses are backed by RAM unless they have been mapped to a
+/// different region.
+pub struct Memory {
+    ram: [u8; MEMORY_SIZE_BYTES],
+    // Later regions take precedence over earlier ones where they overlap.
+    regions: Vec<MappedRegion>,
+    rom_write_policy: RomWritePolicy,
+    unmapped_policy: UnmappedPolicy,
+    // Set by accesses which fault, and checked by the machine after each instruction.
+    fault: Cell<Option<MemoryFault>>,
+    // Writes made while journaling, in the order they were made.
+    journal: Option<Vec<MemoryWrite>>,
+    // Reads made while journaling reads, as address and value.
+    read_journal: RefCell<Option<Vec<(Address, Data8)>>>,
+    instruction_cache: Option<InstructionCache>,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self {
+            ram: [0; MEMORY_SIZE_BYTES],
+            regions: Vec::new(),
+            rom_write_policy: RomWritePolicy::Ignore,
+            unmapped_policy: UnmappedPolicy::Open(0xFF),
+            fault: Cell::new(None),
+            journal: None,
+            read_journal: RefCell::new(None),
+            instruction_cache: None,
+        }
+    }
+
+    /// Maps `range` to `region`, replacing whatever was mapped there before.
+    pub fn map(&mut self, range: RangeInclusive<Address>, region: Region) {
+        self.regions.push(MappedRegion { range, region });
+        self.clear_instruction_cache();
+    }
+
+    pub fn map_rom(&mut self, range: RangeInclusive<Address>) {
+        self.map(range, Region::Rom);
+    }
+
+    pub fn map_device(&mut self, range: RangeInclusive<Address>, device: Box<dyn MemoryBus>) {
+        self.map(range, Region::Device(device));
+    }
+
+    pub fn map_unmapped(&mut self, range: RangeInclusive<Address>) {
+        self.map(range, Region::Unmapped);
+    }
+
+    /// Removes all regions, making the whole address space RAM again.
+    pub fn clear_regions(&mut self) {
+        self.regions.clear();
+        self.clear_instruction_cache();
+    }
+
+    pub fn rom_write_policy(&self) -> RomWritePolicy {
+        self.rom_write_policy
+    }
+
+    pub fn set_rom_write_policy(&mut self, policy: RomWritePolicy) {
+        self.rom_write_policy = policy;
+    }
+
+    pub fn unmapped_policy(&self) -> UnmappedPolicy {
+        self.unmapped_policy
+    }
+
+    pub fn set_unmapped_policy(&mut self, policy: UnmappedPolicy) {
+        self.unmapped_policy = policy;
+    }
+
+    fn region_at(&self, address: Address) -> Option<&MappedRegion> {
+        self.regions
+            .iter()
+            .rev()
+            .find(|mapped| mapped.range.contains(&address))
+    }
+
+    fn region_at_mut(&mut self, address: Address) -> Option<&mut MappedRegion> {
+        self.regions
+            .iter_mut()
+            .rev()
+            .find(|mapped| mapped.range.contains(&address))
+    }
+
+    fn report(&self, fault: MemoryFault) {
+        // Keep the first fault of an instruction, it's the one which caused the rest.
+        if self.fault.get().is_none() {
+            self.fault.set(Some(fault));
+        }
+    }
+
+    /// Returns and clears the fault raised since the last call, if any.
+    pub(crate) fn take_fault(&self) -> Option<MemoryFault> {
+        self.fault.take()
+    }
+
+    /// Reads a byte without reporting faults. Used when the read isn't made by the program.
+    pub fn peek_8(&self, address: Address) -> Data8 {
+        self.try_read_8(address)
+            .unwrap_or_else(|_| self.open_bus_value())
+    }
+
+    fn try_read_8(&self, address: Address) -> Result<Data8, MemoryFault> {
+        let Some(mapped) = self.region_at(address) else {
+            return Ok(self.ram[address as usize]);
+        };
+        match &mapped.region {
+            Region::Rom => Ok(self.ram[address as usize]),
+            Region::Device(device) => device.read_8(address - mapped.range.start()),
+            Region::Unmapped => match self.unmapped_policy {
+                UnmappedPolicy::Open(value) => Ok(value),
+                UnmappedPolicy::Halt => Err(MemoryFault::Unmapped(address)),
+            },
+        }
+    }
+
+    fn open_bus_value(&self) -> Data8 {
+        match self.unmapped_policy {
+            UnmappedPolicy::Open(value) => value,
+            UnmappedPolicy::Halt => 0xFF,
+        }
+    }
+
+    pub fn read_8(&self, address: Address) -> Data8 {
+        let value = self.fetch_8(address);
+        if let Some(journal) = self.read_journal.borrow_mut().as_mut() {
+            journal.push((address, value));
+        }
+        value
+    }
+
+    /// Reads a byte of an instruction. Faults like `read_8`, but isn't recorded in the read
+    /// journal.
+    pub(crate) fn fetch_8(&self, address: Address) -> Data8 {
+        self.try_read_8(address).unwrap_or_else(|fault| {
+            self.report(fault);
+            self.open_bus_value()
+        })
+    }
+    /// Reads a little-endian word. Like on the real CPU, the high byte of a word at `0xFFFF` is
+    /// read from `0x0000`.
+    pub fn read_16(&self, address: Address) -> Data16 {
+        let low = self.read_8(address);
+        let high = self.read_8(address.wrapping_add(1));
+        Data16::new(low, high)
+    }
+
+    pub fn write_8(&mut self, address: Address, value: Data8) {
+        let rom_write_policy = self.rom_write_policy;
+        let unmapped_policy = self.unmapped_policy;
+
+        let mut previous = None;
+        let result = match self.region_at_mut(address) {
+            None => {
+                previous = Some(self.ram[address as usize]);
+                self.ram[address as usize] = value;
+                Ok(())
+            }
+            Some(mapped) => match &mut mapped.region {
+                Region::Rom => match rom_write_policy {
+                    RomWritePolicy::Ignore => Ok(()),
+                    RomWritePolicy::Halt => Err(MemoryFault::RomWrite(address)),
+                },
+                Region::Device(device) => device.write_8(address - mapped.range.start(), value),
+                Region::Unmapped => match unmapped_policy {
+                    UnmappedPolicy::Open(_) => Ok(()),
+                    UnmappedPolicy::Halt => Err(MemoryFault::Unmapped(address)),
+                },
+            },
+        };
+        if let Err(fault) = result {
+            self.report(fault);
+        }
+        if let Some(cache) = &mut self.instruction_cache {
+            cache.invalidate(address);
+        }
+        if let Some(journal) = &mut self.journal {
+            journal.push(MemoryWrite {
+                address,
+                value,
+                previous,
+            });
+        }
+    }
+    /// Writes a little-endian word, wrapping around the end of memory like `read_16`.
+    pub fn write_16(&mut self, address: Address, value: Data16) {
+        self.write_8(address, value.low);
+        self.write_8(address.wrapping_add(1), value.high);
+    }
+
+    /// Starts recording the writes made by the program.
+    pub(crate) fn start_journal(&mut self) {
+        self.journal = Some(Vec::new());
+    }
+
+    /// Stops recording writes and returns the recorded writes.
+    pub(crate) fn take_journal(&mut self) -> Vec<MemoryWrite> {
+        self.journal.take().unwrap_or_default()
+    }
+
+    /// Starts recording the reads made by the program.
+    pub(crate) fn start_read_journal(&self) {
+        self.read_journal.replace(Some(Vec::new()));
+    }
+
+    /// Stops recording reads and returns the recorded reads.
+    pub(crate) fn take_read_journal(&self) -> Vec<(Address, Data8)> {
+        self.read_journal.take().unwrap_or_default()
+    }
+
+    /// Writes a byte directly into RAM, ignoring regions. Used to undo writes.
+    pub(crate) fn restore_8(&mut self, address: Address, value: Data8) {
+        self.ram[address as usize] = value;
+        if let Some(cache) = &mut self.instruction_cache {
+            cache.invalidate(address);
+        }
+    }
+
+    /// Copies `value` directly into RAM, ignoring regions. This is how programs and ROM images are
+    /// loaded.
+    pub fn write_slice(&mut self, address: Address, value: &[u8]) -> Option<()> {
+        let range = (address as usize)..((address as usize) + value.len());
+        self.ram
+            .get_mut(range)
+            .map(|dest| dest.copy_from_slice(value))?;
+        if let Some(cache) = &mut self.instruction_cache {
+            for offset in 0..value.len() {
+                cache.invalidate(address.wrapping_add(offset as Address));
+            }
+        }
+        Some(())
+    }
+
+    /// Enables or disables caching of decoded instructions. Only instructions in RAM and ROM are
+    /// cached, and writes invalidate the instructions they overwrite, so self-modifying code
+    /// behaves the same with the cache enabled.
+    pub fn set_instruction_cache(&mut self, enabled: bool) {
+        if enabled != self.instruction_cache.is_some() {
+            self.instruction_cache = enabled.then(InstructionCache::new);
+        }
+    }
+
+    pub fn instruction_cache_enabled(&self) -> bool {
+        self.instruction_cache.is_some()
+    }
+
+    pub(super) fn clear_instruction_cache(&mut self) {
+        if let Some(cache) = &mut self.instruction_cache {
+            cache.clear();
+        }
+    }
+
+    pub(super) fn cached_instruction(&self, address: Address) -> Option<CachedInstruction> {
+        self.instruction_cache.as_ref()?.get(address)
+    }
+
+    /// Caches an instruction which was decoded from `address`, unless some of its bytes are read
+    /// from a device or unmapped memory, which could return different bytes next time.
+    pub(super) fn cache_instruction(
+        &mut self,
+        address: Address,
+        opcode: &'static Opcode,
+        instruction: Instruction,
+    ) {
+        let cacheable = (0..opcode.length).all(|offset| {
+            match self.region_at(address.wrapping_add(offset)) {
+                None => true,
+                Some(mapped) => matches!(mapped.region, Region::Rom),
+            }
+        });
+        if let (true, Some(cache)) = (cacheable, &mut self.instruction_cache) {
+            cache.insert(
+                address,
+                CachedInstruction {
+                    opcode,
+                    instruction,
+                },
+            );
+        }
+    }
+
+    /// The contents of RAM, including the contents of ROM regions.
+    pub fn as_raw(&self) -> &[u8; MEMORY_SIZE_BYTES] {
+        &self.ram
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,56 @@
+//! The random number generator which devices such as the number device on port 1 read from.
+
+use rand::Rng;
+
+/// A SplitMix64 generator. Its whole state is a single `u64`, so it can be saved in snapshots and
+/// undo records, and restoring it continues the same sequence.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    /// A generator which always produces the same sequence for the same seed.
+    pub fn from_seed(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// A generator seeded by the operating system, for runs which don't need to be reproducible.
+    pub fn from_entropy() -> Self {
+        Self::from_seed(rand::rng().random())
+    }
+
+    /// The current state, which can be passed to `from_seed` to continue the sequence from here.
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        // The high bits are the best mixed.
+        (self.next_u64() >> 56) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_is_reproducible() {
+        // The first outputs of SplitMix64 seeded with 0.
+        let mut random = Random::from_seed(0);
+        assert_eq!(random.next_u64(), 0xE220_A839_7B1D_CDAF);
+        assert_eq!(random.next_u64(), 0x6E78_9E6A_A1B9_65F4);
+
+        let mut resumed = Random::from_seed(random.state());
+        assert_eq!(resumed.next_u8(), random.next_u8());
+    }
+}
@@ -0,0 +1,419 @@
+//! Saving and restoring the complete state of a machine.
+//!
+//! A snapshot is a binary file with all multi-byte values stored little-endian:
+//!
+//! | Size | Contents |
+//! | --- | --- |
+//! | 8 | Magic bytes `I8080SNP` |
+//! | 2 | Format version, currently `1` |
+//! | 1 | CPU: `0` 8080, `1` 8085 |
+//! | 7 | Registers A, B, C, D, E, H and L |
+//! | 2 | SP |
+//! | 2 | PC |
+//! | 1 | Flags, in the same layout as the low byte of `PUSH PSW` |
+//! | 1 | State: `0` running, `1` waiting for an interrupt, `2` halted |
+//! | 1 | Halt reason when halted (see `halt_reason_code`), otherwise `0` |
+//! | 1 | Interrupts enabled (`0` or `1`) |
+//! | 1 | Interrupt enable delay after `EI` (`0` or `1`) |
+//! | 1 | Length `n` of the pending interrupt instruction, `0` if none |
+//! | n | Machine code of the pending interrupt instruction |
+//! | 2 | 8085 interrupt masks and inputs |
+//! | 8 | Total T-states executed |
+//! | 8 | Total instructions executed, including interrupts |
+//! | 1 | Sanitizer mode (`0` or `1`) |
+//! | 1 | Strict opcodes (`0` or `1`) |
+//! | 8 | State of the random number generator |
+//! | 65536 | Contents of RAM |
+//! | 4 | Length `m` of the stdout buffer |
+//! | m | Contents of the stdout buffer |
+//!
+//! The memory map and attached I/O devices are configuration rather than state, and aren't part of
+//! the snapshot.
+
+use std::io::{self, Read, Write};
+
+use crate::{
+    coding::{self, reader::Reader},
+    instruction::{Cpu, Data16},
+    machine::{HaltReason, Machine, MachineState, i8085::InterruptLines, random::Random},
+};
+
+pub const SNAPSHOT_MAGIC: &[u8; 8] = b"I8080SNP";
+pub const SNAPSHOT_VERSION: u16 = 1;
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn halt_reason_code(halt_reason: HaltReason) -> u8 {
+    match halt_reason {
+        HaltReason::HaltInstruction => 0,
+        HaltReason::InvalidInstruction => 1,
+        HaltReason::StackOverflow => 2,
+        HaltReason::StackUnderflow => 3,
+        HaltReason::MemoryOverflow => 4,
+        HaltReason::RomWrite => 5,
+        HaltReason::UnmappedMemory => 6,
+    }
+}
+
+fn halt_reason_from_code(code: u8) -> io::Result<HaltReason> {
+    match code {
+        0 => Ok(HaltReason::HaltInstruction),
+        1 => Ok(HaltReason::InvalidInstruction),
+        2 => Ok(HaltReason::StackOverflow),
+        3 => Ok(HaltReason::StackUnderflow),
+        4 => Ok(HaltReason::MemoryOverflow),
+        5 => Ok(HaltReason::RomWrite),
+        6 => Ok(HaltReason::UnmappedMemory),
+        _ => Err(invalid_data("invalid halt reason")),
+    }
+}
+
+fn cpu_code(cpu: Cpu) -> u8 {
+    match cpu {
+        Cpu::Intel8080 => 0,
+        Cpu::Intel8085 => 1,
+    }
+}
+
+fn cpu_from_code(code: u8) -> io::Result<Cpu> {
+    match code {
+        0 => Ok(Cpu::Intel8080),
+        1 => Ok(Cpu::Intel8085),
+        _ => Err(invalid_data("invalid CPU")),
+    }
+}
+
+fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16(reader: &mut impl Read) -> io::Result<u16> {
+    let mut buf = [0; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_bool(reader: &mut impl Read) -> io::Result<bool> {
+    match read_u8(reader)? {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err(invalid_data("invalid boolean")),
+    }
+}
+
+impl Machine {
+    /// Writes a snapshot of the machine state in the format described in the `snapshot` module.
+    pub fn save_snapshot(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(SNAPSHOT_MAGIC)?;
+        writer.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+        writer.write_all(&[cpu_code(self.cpu)])?;
+
+        let registers = &self.registers;
+        writer.write_all(&[
+            registers.a,
+            registers.b,
+            registers.c,
+            registers.d,
+            registers.e,
+            registers.h,
+            registers.l,
+        ])?;
+        writer.write_all(&registers.sp.value().to_le_bytes())?;
+        writer.write_all(&self.pc.value().to_le_bytes())?;
+        writer.write_all(&[self.get_status_word().low])?;
+
+        let (state, halt_reason) = match self.state {
+            MachineState::Running => (0, 0),
+            MachineState::WaitingForInterrupt => (1, 0),
+            MachineState::Halted(halt_reason) => (2, halt_reason_code(halt_reason)),
+        };
+        writer.write_all(&[
+            state,
+            halt_reason,
+            self.interrupts_enabled as u8,
+            self.interrupt_enable_delay as u8,
+        ])?;
+
+        let mut pending_interrupt = Vec::new();
+        if let Some(instruction) = self.pending_interrupt {
+            coding::encode(&mut pending_interrupt, instruction)?;
+        }
+        writer.write_all(&[pending_interrupt.len() as u8])?;
+        writer.write_all(&pending_interrupt)?;
+        writer.write_all(&self.interrupt_lines.to_bytes())?;
+
+        writer.write_all(&self.cycles.to_le_bytes())?;
+        writer.write_all(&self.steps.to_le_bytes())?;
+        writer.write_all(&[self.sanitize as u8, self.strict_opcodes as u8])?;
+        writer.write_all(&self.random.state().to_le_bytes())?;
+
+        writer.write_all(self.memory.as_raw())?;
+
+        writer.write_all(&(self.stdout.len() as u32).to_le_bytes())?;
+        writer.write_all(&self.stdout)?;
+
+        Ok(())
+    }
+
+    /// Restores the machine state from a snapshot written by `save_snapshot`. The memory map and
+    /// I/O devices of the machine are kept. The machine is left unchanged if the snapshot is
+    /// invalid.
+    pub fn load_snapshot(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != SNAPSHOT_MAGIC {
+            return Err(invalid_data("not a machine snapshot"));
+        }
+        let version = read_u16(reader)?;
+        if version != SNAPSHOT_VERSION {
+            return Err(invalid_data(&format!(
+                "unsupported snapshot version {}",
+                version
+            )));
+        }
+        let cpu = cpu_from_code(read_u8(reader)?)?;
+
+        let mut registers = [0; 7];
+        reader.read_exact(&mut registers)?;
+        let [a, b, c, d, e, h, l] = registers;
+        let sp = read_u16(reader)?;
+        let pc = read_u16(reader)?;
+        let flags = read_u8(reader)?;
+
+        let state = read_u8(reader)?;
+        let halt_reason = read_u8(reader)?;
+        let state = match state {
+            0 => MachineState::Running,
+            1 => MachineState::WaitingForInterrupt,
+            2 => MachineState::Halted(halt_reason_from_code(halt_reason)?),
+            _ => return Err(invalid_data("invalid machine state")),
+        };
+        let interrupts_enabled = read_bool(reader)?;
+        let interrupt_enable_delay = read_bool(reader)?;
+
+        let pending_interrupt_len = read_u8(reader)?;
+        let pending_interrupt = if pending_interrupt_len == 0 {
+            None
+        } else {
+            let mut bytes = vec![0; pending_interrupt_len as usize];
+            reader.read_exact(&mut bytes)?;
+            let (instruction, _) = coding::decode(&mut Reader::new(&bytes), cpu)
+                .ok_or_else(|| invalid_data("invalid pending interrupt instruction"))?;
+            Some(instruction)
+        };
+        let mut interrupt_lines = [0; 2];
+        reader.read_exact(&mut interrupt_lines)?;
+        let interrupt_lines = InterruptLines::from_bytes(interrupt_lines)
+            .ok_or_else(|| invalid_data("invalid 8085 interrupt state"))?;
+
+        let cycles = read_u64(reader)?;
+        let steps = read_u64(reader)?;
+        let sanitize = read_bool(reader)?;
+        let strict_opcodes = read_bool(reader)?;
+        let random = Random::from_seed(read_u64(reader)?);
+
+        let mut memory = vec![0; self.memory.as_raw().len()];
+        reader.read_exact(&mut memory)?;
+
+        let stdout_len = read_u32(reader)?;
+        let mut stdout = Vec::new();
+        reader
+            .take(stdout_len as u64)
+            .read_to_end(&mut stdout)?;
+        if stdout.len() != stdout_len as usize {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        // Everything has been read successfully, so the machine can be modified.
+        self.set_cpu(cpu);
+        self.registers.a = a;
+        self.registers.b = b;
+        self.registers.c = c;
+        self.registers.d = d;
+        self.registers.e = e;
+        self.registers.h = h;
+        self.registers.l = l;
+        self.registers.sp = sp.into();
+        self.pc = pc.into();
+        self.set_status_word(Data16::new(flags, a));
+        self.state = state;
+        self.halt_diagnostics = None;
+        self.interrupts_enabled = interrupts_enabled;
+        self.interrupt_enable_delay = interrupt_enable_delay;
+        self.pending_interrupt = pending_interrupt;
+        self.interrupt_lines = interrupt_lines;
+        self.cycles = cycles;
+        self.steps = steps;
+        self.sanitize = sanitize;
+        self.set_strict_opcodes(strict_opcodes);
+        self.random = random;
+        self.memory
+            .write_slice(0, &memory)
+            .expect("snapshot memory has the size of the machine memory");
+        self.stdout = stdout;
+        // The history leads up to the previous state, which has been replaced.
+        self.clear_history();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        instruction::{Instruction, Register, RegisterPair, RestartNumber},
+        machine::{ConditionRegister, i8085::InterruptInput},
+    };
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut machine = Machine::new();
+        // EI, MVI B, 0x42; STC; HLT
+        machine
+            .memory_mut()
+            .write_slice(0x100, &[0xFB, 0x06, 0x42, 0x37, 0x76])
+            .unwrap();
+        machine.pc = 0x100.into();
+        machine.registers.set_16(RegisterPair::Sp, 0x2000.into());
+        machine.stdout.extend_from_slice(b"hello");
+        for _ in 0..4 {
+            machine.run_cycle();
+        }
+        machine.interrupt(Instruction::Rst(RestartNumber::R3));
+
+        let mut snapshot = Vec::new();
+        machine.save_snapshot(&mut snapshot).unwrap();
+
+        let mut restored = Machine::new();
+        restored.load_snapshot(&mut snapshot.as_slice()).unwrap();
+
+        assert_eq!(restored.state(), MachineState::WaitingForInterrupt);
+        assert_eq!(restored.pc(), machine.pc());
+        assert_eq!(restored.register_8(Register::B), 0x42);
+        assert_eq!(restored.register_16(RegisterPair::Sp).value(), 0x2000);
+        assert!(restored.conditions().get(ConditionRegister::Carry));
+        assert!(restored.interrupts_enabled());
+        assert_eq!(restored.pending_interrupt(), Some(Instruction::Rst(RestartNumber::R3)));
+        assert_eq!(restored.cycles(), machine.cycles());
+        assert_eq!(restored.memory().as_raw(), machine.memory().as_raw());
+        assert_eq!(restored.stdout, b"hello");
+
+        let mut resaved = Vec::new();
+        restored.save_snapshot(&mut resaved).unwrap();
+        assert_eq!(snapshot, resaved);
+    }
+
+    #[test]
+    fn test_snapshot_8085() {
+        let mut machine = Machine::new();
+        machine.set_cpu(Cpu::Intel8085);
+        // MVI A, 0x1D; SIM; MVI A, 0x7F; ADI 1
+        machine
+            .memory_mut()
+            .write_slice(0, &[0x3E, 0x1D, 0x30, 0x3E, 0x7F, 0xC6, 0x01])
+            .unwrap();
+        for _ in 0..4 {
+            machine.run_cycle();
+        }
+        machine.set_interrupt_input(InterruptInput::Rst65, true);
+
+        let mut snapshot = Vec::new();
+        machine.save_snapshot(&mut snapshot).unwrap();
+
+        let mut restored = Machine::new();
+        restored.load_snapshot(&mut snapshot.as_slice()).unwrap();
+
+        assert_eq!(restored.cpu(), Cpu::Intel8085);
+        assert!(restored.conditions().get(ConditionRegister::Overflow));
+        assert!(restored.interrupt_input(InterruptInput::Rst65));
+        assert_eq!(restored.read_interrupt_masks(), machine.read_interrupt_masks());
+
+        // The 8080 has no V and K flags, so loading an 8080 snapshot clears them.
+        let mut snapshot_8080 = Vec::new();
+        Machine::new().save_snapshot(&mut snapshot_8080).unwrap();
+        restored.load_snapshot(&mut snapshot_8080.as_slice()).unwrap();
+        assert_eq!(restored.cpu(), Cpu::Intel8080);
+        assert!(!restored.conditions().get(ConditionRegister::Overflow));
+    }
+
+    #[test]
+    fn test_snapshot_steps() {
+        // NOP; NOP; NOP
+        let mut machine = Machine::with_program(0, &[0x00, 0x00, 0x00]).unwrap();
+        machine.set_strict_opcodes(true);
+        machine.run(3);
+
+        let mut snapshot = Vec::new();
+        machine.save_snapshot(&mut snapshot).unwrap();
+
+        let mut restored = Machine::new();
+        restored.set_history_capacity(10);
+        restored.run(5);
+        restored.load_snapshot(&mut snapshot.as_slice()).unwrap();
+        assert_eq!(restored.steps(), 3);
+        assert_eq!(restored.earliest_step(), 3);
+        assert!(restored.strict_opcodes_enabled());
+    }
+
+    #[test]
+    fn test_snapshot_random() {
+        // IN 1; MOV B, A; IN 1
+        let program = [0xDB, 0x01, 0x47, 0xDB, 0x01];
+        let mut machine = Machine::with_program(0, &program).unwrap();
+        machine.set_random_seed(1234);
+        machine.run_cycle();
+
+        let mut snapshot = Vec::new();
+        machine.save_snapshot(&mut snapshot).unwrap();
+        machine.run(2);
+
+        let mut restored = Machine::new();
+        restored.load_snapshot(&mut snapshot.as_slice()).unwrap();
+        restored.run(2);
+        assert_eq!(restored.register_8(Register::A), machine.register_8(Register::A));
+
+        // The same seed gives the same numbers.
+        let mut rerun = Machine::with_program(0, &program).unwrap();
+        rerun.set_random_seed(1234);
+        rerun.run(3);
+        assert_eq!(rerun.register_8(Register::B), machine.register_8(Register::B));
+        assert_eq!(rerun.register_8(Register::A), machine.register_8(Register::A));
+    }
+
+    #[test]
+    fn test_invalid_snapshot() {
+        let mut machine = Machine::new();
+        machine.registers.set_16(RegisterPair::Hl, 0x1234.into());
+
+        let mut snapshot = Vec::new();
+        Machine::new().save_snapshot(&mut snapshot).unwrap();
+        snapshot.truncate(snapshot.len() - 1);
+
+        assert!(machine.load_snapshot(&mut snapshot.as_slice()).is_err());
+        assert!(machine.load_snapshot(&mut &b"not a snapshot"[..]).is_err());
+
+        // Only the current version is loaded.
+        let mut old = Vec::new();
+        Machine::new().save_snapshot(&mut old).unwrap();
+        old[8..10].copy_from_slice(&(SNAPSHOT_VERSION - 1).to_le_bytes());
+        assert!(machine.load_snapshot(&mut old.as_slice()).is_err());
+        assert_eq!(machine.register_16(RegisterPair::Hl).value(), 0x1234);
+    }
+}
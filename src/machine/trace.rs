@@ -0,0 +1,375 @@
+use std::{
+    fmt::Display,
+    io::{self, Write},
+    str::FromStr,
+};
+
+use crate::{
+    coding::{self, table},
+    instruction::{Data16, Instruction, RegisterPair},
+    machine::{ConditionRegister, ConditionRegisters, Machine, MachineState, memory::MemoryWrite},
+};
+
+/// What the machine did when executing a single instruction. Registers and flags are the values
+/// after execution.
+pub struct TraceEntry<'a> {
+    /// The number of instructions executed, including this one.
+    pub step: u64,
+    /// The address of the instruction. For an instruction supplied by an interrupt, the PC when
+    /// the interrupt was accepted.
+    pub pc: Data16,
+    pub bytes: &'a [u8],
+    pub instruction: Instruction,
+    pub interrupt: bool,
+    /// For an undocumented 8080 opcode, the documented opcode it was executed as.
+    pub alias_of: Option<u8>,
+    pub cycles: u32,
+    pub next_pc: Data16,
+    /// The accumulator and the flags, in the layout used by `PUSH PSW`.
+    pub psw: Data16,
+    pub bc: Data16,
+    pub de: Data16,
+    pub hl: Data16,
+    pub sp: Data16,
+    pub conditions: &'a ConditionRegisters,
+    pub writes: &'a [MemoryWrite],
+    pub state: MachineState,
+}
+
+/// Receives a trace entry for every instruction the machine executes.
+pub trait TraceSink: Send {
+    fn record(&mut self, entry: &TraceEntry);
+
+    /// Called when tracing stops. Flushes buffered output, and reports the first error which
+    /// occurred while recording.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// The machine state before an instruction is executed, which is needed for its trace entry.
+pub(super) struct TraceStart {
+    pc: Data16,
+    interrupt: bool,
+}
+
+impl Machine {
+    /// Sets the sink which receives a trace of executed instructions, returning the previous
+    /// sink. The previous sink isn't finished.
+    pub fn set_trace_sink(
+        &mut self,
+        sink: Option<Box<dyn TraceSink>>,
+    ) -> Option<Box<dyn TraceSink>> {
+        std::mem::replace(&mut self.trace, sink)
+    }
+
+    pub(super) fn begin_trace(&self) -> Option<TraceStart> {
+        self.trace.as_ref()?;
+
+        Some(TraceStart {
+            pc: self.pc,
+            interrupt: self.interrupt_ready(),
+        })
+    }
+
+    pub(super) fn finish_trace(
+        &mut self,
+        start: TraceStart,
+        instruction: Instruction,
+        cycles: u32,
+        fetched: &[u8; 3],
+        writes: &[MemoryWrite],
+    ) {
+        let mut interrupt_bytes = Vec::new();
+        let bytes = if start.interrupt {
+            coding::encode(&mut interrupt_bytes, instruction).expect("writing to Vec can't error");
+            &interrupt_bytes[..]
+        } else {
+            &fetched[..instruction.byte_length() as usize]
+        };
+
+        let alias_of = match start.interrupt {
+            true => None,
+            false => table::opcode(self.cpu, fetched[0]).and_then(|opcode| opcode.alias_of),
+        };
+
+        let entry = TraceEntry {
+            step: self.steps,
+            pc: start.pc,
+            bytes,
+            instruction,
+            interrupt: start.interrupt,
+            alias_of,
+            cycles,
+            next_pc: self.pc,
+            psw: self.get_status_word(),
+            bc: self.registers.get_16(RegisterPair::Bc),
+            de: self.registers.get_16(RegisterPair::De),
+            hl: self.registers.get_16(RegisterPair::Hl),
+            sp: self.registers.get_16(RegisterPair::Sp),
+            conditions: &self.conditions,
+            writes,
+            state: self.state,
+        };
+        if let Some(sink) = &mut self.trace {
+            sink.record(&entry);
+        }
+    }
+}
+
+/// The format used by `TraceWriter`.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// One human readable line per instruction.
+    Text,
+    /// One JSON object per line.
+    JsonLines,
+}
+
+impl Display for TraceFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TraceFormat::Text => write!(f, "text"),
+            TraceFormat::JsonLines => write!(f, "json"),
+        }
+    }
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "text" => Ok(TraceFormat::Text),
+            "json" | "jsonl" => Ok(TraceFormat::JsonLines),
+            _ => Err(format!("Invalid trace format '{}', expected 'text' or 'json'", s)),
+        }
+    }
+}
+
+/// Writes trace entries to `writer` in a `TraceFormat`. Writing stops at the first error, which is
+/// reported by `finish`.
+pub struct TraceWriter<W: Write + Send> {
+    writer: W,
+    format: TraceFormat,
+    error: Option<io::Error>,
+}
+
+impl<W: Write + Send> TraceWriter<W> {
+    pub fn new(writer: W, format: TraceFormat) -> Self {
+        Self {
+            writer,
+            format,
+            error: None,
+        }
+    }
+
+    fn write_text(&mut self, entry: &TraceEntry) -> io::Result<()> {
+        let bytes = entry
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<_>>()
+            .join(" ");
+        write!(
+            self.writer,
+            "{:>8} {:04X}  {:<8}  {:<16} PSW={:04X} BC={:04X} DE={:04X} HL={:04X} SP={:04X} F={} T={}",
+            entry.step,
+            entry.pc.value(),
+            bytes,
+            entry.instruction.to_string(),
+            entry.psw.value(),
+            entry.bc.value(),
+            entry.de.value(),
+            entry.hl.value(),
+            entry.sp.value(),
+            flags_string(entry.conditions),
+            entry.cycles,
+        )?;
+        if entry.interrupt {
+            write!(self.writer, " INT")?;
+        }
+        if let Some(documented) = entry.alias_of {
+            write!(self.writer, " ALIAS={:02X}", documented)?;
+        }
+        for write in entry.writes {
+            write!(self.writer, " [{:04X}]={:02X}", write.address, write.value)?;
+        }
+        writeln!(self.writer)
+    }
+
+    fn write_json(&mut self, entry: &TraceEntry) -> io::Result<()> {
+        let bytes = entry
+            .bytes
+            .iter()
+            .map(|byte| byte.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        let writes = entry
+            .writes
+            .iter()
+            .map(|write| format!("{{\"address\":{},\"value\":{}}}", write.address, write.value))
+            .collect::<Vec<_>>()
+            .join(",");
+        let flag = |condition| entry.conditions.get(condition);
+        writeln!(
+            self.writer,
+            "{{\"step\":{},\"pc\":{},\"bytes\":[{}],\"instruction\":{},\"interrupt\":{},\"cycles\":{},\"next_pc\":{},\"a\":{},\"psw\":{},\"bc\":{},\"de\":{},\"hl\":{},\"sp\":{},\"flags\":{{\"s\":{},\"z\":{},\"ac\":{},\"p\":{},\"cy\":{}}},\"writes\":[{}],\"alias_of\":{},\"state\":{}}}",
+            entry.step,
+            entry.pc.value(),
+            bytes,
+            json_string(&entry.instruction.to_string()),
+            entry.interrupt,
+            entry.cycles,
+            entry.next_pc.value(),
+            entry.psw.high,
+            entry.psw.value(),
+            entry.bc.value(),
+            entry.de.value(),
+            entry.hl.value(),
+            entry.sp.value(),
+            flag(ConditionRegister::Sign),
+            flag(ConditionRegister::Zero),
+            flag(ConditionRegister::AuxiliaryCarry),
+            flag(ConditionRegister::Parity),
+            flag(ConditionRegister::Carry),
+            writes,
+            entry.alias_of.map_or("null".to_string(), |documented| documented.to_string()),
+            json_string(&state_string(entry.state)),
+        )
+    }
+}
+
+impl<W: Write + Send> TraceSink for TraceWriter<W> {
+    fn record(&mut self, entry: &TraceEntry) {
+        if self.error.is_some() {
+            return;
+        }
+        let result = match self.format {
+            TraceFormat::Text => self.write_text(entry),
+            TraceFormat::JsonLines => self.write_json(entry),
+        };
+        if let Err(err) = result {
+            self.error = Some(err);
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.writer.flush()
+    }
+}
+
+// Set flags are shown by their letter, clear flags by `-`.
+fn flags_string(conditions: &ConditionRegisters) -> String {
+    [
+        (ConditionRegister::Sign, 'S'),
+        (ConditionRegister::Zero, 'Z'),
+        (ConditionRegister::AuxiliaryCarry, 'A'),
+        (ConditionRegister::Parity, 'P'),
+        (ConditionRegister::Carry, 'C'),
+    ]
+    .into_iter()
+    .map(|(condition, letter)| if conditions.get(condition) { letter } else { '-' })
+    .collect()
+}
+
+fn state_string(state: MachineState) -> String {
+    match state {
+        MachineState::Running => "running".to_string(),
+        MachineState::WaitingForInterrupt => "waiting".to_string(),
+        MachineState::Halted(halt_reason) => format!("halted: {}", halt_reason),
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    // A writer whose output can still be read after it has been given to the machine.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn trace_program(program: &[u8], cycles: usize, format: TraceFormat) -> String {
+        let buffer = SharedBuffer::default();
+        let mut machine = Machine::new();
+        machine.memory_mut().write_slice(0, program).unwrap();
+        machine.set_trace_sink(Some(Box::new(TraceWriter::new(buffer.clone(), format))));
+        for _ in 0..cycles {
+            machine.run_cycle();
+        }
+        machine.set_trace_sink(None).unwrap().finish().unwrap();
+
+        let bytes = buffer.0.lock().unwrap().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_text_trace() {
+        // LXI SP, 0x1000; MVI A, 0xA5; PUSH PSW; HLT
+        let trace = trace_program(&[0x31, 0x00, 0x10, 0x3E, 0xA5, 0xF5, 0x76], 4, TraceFormat::Text);
+        let lines: Vec<_> = trace.lines().collect();
+
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("       1 0000  31 00 10  LXI SP, 1000H"));
+        assert!(lines[1].contains("MVI A, 0A5H"));
+        assert!(lines[1].contains("PSW=A502"));
+        assert!(lines[2].ends_with("[0FFE]=02 [0FFF]=A5"));
+        assert!(lines[3].contains("HLT"));
+    }
+
+    #[test]
+    fn test_trace_alias() {
+        // 0x08 (NOP); NOP
+        let trace = trace_program(&[0x08, 0x00], 2, TraceFormat::Text);
+        let lines: Vec<_> = trace.lines().collect();
+
+        assert!(lines[0].contains("NOP"));
+        assert!(lines[0].ends_with(" ALIAS=00"));
+        assert!(!lines[1].contains("ALIAS"));
+    }
+
+    #[test]
+    fn test_json_lines_trace() {
+        // MVI B, 0x42; STA 0x0010
+        let trace = trace_program(&[0x06, 0x42, 0x32, 0x10, 0x00], 2, TraceFormat::JsonLines);
+        let lines: Vec<_> = trace.lines().collect();
+
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with(
+            "{\"step\":1,\"pc\":0,\"bytes\":[6,66],\"instruction\":\"MVI B, 42H\",\"interrupt\":false,\"cycles\":7,\"next_pc\":2,"
+        ));
+        assert!(lines[0].contains("\"bc\":16896,"));
+        assert!(lines[1].contains("\"writes\":[{\"address\":16,\"value\":0}],\"alias_of\":null,"));
+        assert!(lines[1].ends_with("\"state\":\"running\"}"));
+    }
+}
@@ -1,5 +1,7 @@
+use std::process::ExitCode;
+
 use rsoderh_jonsh_leben_emulator::{self, cli};
 
-fn main() -> anyhow::Result<()> {
+fn main() -> anyhow::Result<ExitCode> {
     cli::start()
 }
@@ -1,6 +1,7 @@
 use std::{
     fmt::Display,
     io,
+    path::PathBuf,
     sync::{LazyLock, mpsc::{self, TryRecvError}},
     time::{Duration, Instant},
 };
@@ -21,9 +22,11 @@ use tui::{
 };
 
 use crate::{
+    cli,
+    clock::{ClockSpeed, Throttle},
     coding,
-    instruction::{Register, RegisterPair},
-    machine::{ConditionRegister, Machine, MachineState},
+    instruction::{Instruction, Register, RegisterPair, RestartNumber},
+    machine::{ConditionRegister, HaltReason, Machine, MachineState},
     ui::memory_view::MemoryView,
 };
 
@@ -122,13 +125,19 @@ struct Ui {
     input_receiver: mpsc::Receiver<KeyEvent>,
     quit_sender: mpsc::Sender<Option<String>>,
     state: UiState,
+    throttle: Throttle,
+    snapshot_path: Option<PathBuf>,
+    // Result of the last action, shown next to the keys.
+    status: Option<String>,
 }
 
 impl Ui {
     fn new(
         machine: Machine,
         input_receiver: mpsc::Receiver<KeyEvent>,
-        quit_sender: mpsc::Sender<Option<String>>) 
+        quit_sender: mpsc::Sender<Option<String>>,
+        clock_speed: ClockSpeed,
+        snapshot_path: Option<PathBuf>) 
         -> Self 
     {
         Self {
@@ -136,6 +145,9 @@ impl Ui {
             input_receiver,
             quit_sender,
             state: UiState::Paused,
+            throttle: Throttle::new(clock_speed),
+            snapshot_path,
+            status: None,
         }
     }
 
@@ -149,19 +161,38 @@ impl Ui {
         }
         match self.state {
             UiState::Running => {
-                self.machine.run_cycle();
+                let cycles = self.machine.run_cycle();
+                self.throttle.wait(cycles);
+                self.pause_if_finished();
             }
             UiState::Paused => {}
         }
-        match self.machine.state() {
-            MachineState::Running => {}
-            MachineState::Halted(halt_reason) => {
-                self.quit_sender.send(Some(format!("State machine halted: {}", halt_reason)));
-            }
-        }
         Ok(())
     }
 
+    // Pauses when the program has ended and shows why, so that it can still be stepped back from.
+    fn pause_if_finished(&mut self) {
+        if let Some(message) = self.halt_message() {
+            self.state = UiState::Paused;
+            self.status = Some(message);
+        }
+    }
+
+    fn halt_message(&self) -> Option<String> {
+        if !self.machine.is_finished() {
+            return None;
+        }
+        // An 8085 waiting for TRAP after `HLT` has stopped at a halt instruction too.
+        let halt_reason = match self.machine.state() {
+            MachineState::Halted(halt_reason) => halt_reason,
+            _ => HaltReason::HaltInstruction,
+        };
+        Some(match self.machine.halt_diagnostics() {
+            Some(diagnostics) => format!("State machine halted: {}", diagnostics),
+            None => format!("State machine halted: {}", halt_reason),
+        })
+    }
+
     fn draw(&self, terminal: &mut Terminal<CrosstermBackend<io::Stdout>>) -> anyhow::Result<()> {
         terminal.draw(|f| {
             static REGISTERS_HEIGHT: u16 = 5 + 2;
@@ -354,6 +385,14 @@ impl Ui {
                 Span::styled("PC", *STYLE_LABEL),
                 Span::raw(": "),
                 Span::styled(format!("0x{:04x}", value.value()), *STYLE_PC),
+                Span::raw("  "),
+                Span::styled("T-states", *STYLE_LABEL),
+                Span::raw(": "),
+                Span::styled(format!("{}", self.machine.cycles()), *STYLE_VALUE),
+                Span::raw("  "),
+                Span::styled("Step", *STYLE_LABEL),
+                Span::raw(": "),
+                Span::styled(format!("{}", self.machine.steps()), *STYLE_VALUE),
             ]));
             f.render_widget(pc, block_area);
         }
@@ -394,8 +433,21 @@ impl Ui {
             Span::styled("P", *STYLE_BLOCK_LABEL),
             Span::styled("  step instruction: ", *STYLE_BLOCK_BORDER),
             Span::styled("Space", *STYLE_BLOCK_LABEL),
+            Span::styled("  step back: ", *STYLE_BLOCK_BORDER),
+            Span::styled("B", *STYLE_BLOCK_LABEL),
+            Span::styled("  interrupt (RST 7): ", *STYLE_BLOCK_BORDER),
+            Span::styled("I", *STYLE_BLOCK_LABEL),
+            Span::styled("  save snapshot: ", *STYLE_BLOCK_BORDER),
+            Span::styled("S", *STYLE_BLOCK_LABEL),
             Span::styled("  quit: ", *STYLE_BLOCK_BORDER),
             Span::styled("Q", *STYLE_BLOCK_LABEL),
+            Span::styled(
+                self.status
+                    .as_ref()
+                    .map(|status| format!("  {}", status))
+                    .unwrap_or_default(),
+                *STYLE_DATA,
+            ),
         ]));
         f.render_widget(par, area);
     }
@@ -420,18 +472,42 @@ impl Ui {
     fn input(&mut self, event: event::KeyEvent) -> anyhow::Result<()> {
         match event.code {
             KeyCode::Char('q') => {
-                self.quit_sender.send(None)?;
+                self.quit_sender.send(self.halt_message())?;
             }
             KeyCode::Char(' ') => match self.state {
                 UiState::Paused => {
                     self.machine.run_cycle();
+                    self.pause_if_finished();
                 }
                 _ => {}
             },
+            KeyCode::Char('b') if self.state == UiState::Paused => {
+                let stepped_back = self.machine.step_back();
+                self.status = if stepped_back {
+                    None
+                } else {
+                    Some("no more history to step back".to_string())
+                };
+            }
+            KeyCode::Char('s') => {
+                self.status = Some(match &self.snapshot_path {
+                    Some(path) => match cli::save_snapshot(&self.machine, path) {
+                        Ok(()) => format!("saved snapshot to {}", path.display()),
+                        Err(err) => format!("couldn't save snapshot: {}", err),
+                    },
+                    None => "no snapshot file, start with --save-snapshot <FILE>".to_string(),
+                });
+            }
+            KeyCode::Char('i') => {
+                self.machine.interrupt(Instruction::Rst(RestartNumber::R7));
+            }
             KeyCode::Char('p') => {
-                if self.machine.state() == MachineState::Running {
+                if !self.machine.is_finished() {
                     self.state = match self.state {
-                        UiState::Paused => UiState::Running,
+                        UiState::Paused => {
+                            self.throttle.reset();
+                            UiState::Running
+                        }
                         UiState::Running => UiState::Paused,
                     }
                 }
@@ -442,7 +518,11 @@ impl Ui {
     }
 }
 
-pub fn start(machine: Machine) -> anyhow::Result<()> {
+pub fn start(
+    machine: Machine,
+    clock_speed: ClockSpeed,
+    snapshot_path: Option<PathBuf>,
+) -> anyhow::Result<()> {
     let backend = CrosstermBackend::new(io::stdout());
     let mut terminal = Terminal::new(backend)?;
     terminal.clear()?;
@@ -451,7 +531,13 @@ pub fn start(machine: Machine) -> anyhow::Result<()> {
 
     let (input_sender, input_receiver) = mpsc::channel::<KeyEvent>();
     let (quit_sender, quit_receiver) = mpsc::channel::<Option<String>>();
-    let mut ui = Ui::new(machine, input_receiver, quit_sender.clone());
+    let mut ui = Ui::new(
+        machine,
+        input_receiver,
+        quit_sender.clone(),
+        clock_speed,
+        snapshot_path,
+    );
 
     std::thread::spawn(move || -> Result<(), anyhow::Error> {
         let mut last_draw_time = Instant::now();
@@ -0,0 +1,91 @@
+//! Runs the standard 8080 CPU exercisers, which are CP/M programs, and checks that they report
+//! success. The binaries aren't part of the repository, see `tests/cpu_exercisers/README.md`.
+//! The test of an exerciser which hasn't been added is skipped.
+
+use std::{fs, io, path::PathBuf};
+
+use rsoderh_jonsh_leben_emulator::{
+    cpm::Cpm,
+    machine::{Machine, MachineState},
+};
+
+/// Runs the exerciser `name` under CP/M until it exits, and returns its console output. Returns
+/// `None` if the exerciser hasn't been added.
+fn run_exerciser(name: &str) -> Option<String> {
+    let directory = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/cpu_exercisers");
+    let path = directory.join(name);
+    let program = match fs::read(&path) {
+        Ok(program) => program,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            eprintln!(
+                "Skipping {}: {} doesn't exist. See tests/cpu_exercisers/README.md.",
+                name,
+                path.display()
+            );
+            return None;
+        }
+        Err(err) => panic!("Couldn't read {}: {}", path.display(), err),
+    };
+
+    let mut machine = Machine::new();
+    machine.memory_mut().set_instruction_cache(true);
+    let mut cpm = Cpm::with_console_input(directory, Box::new(io::empty()));
+    cpm.load(&mut machine, &program, &[]).unwrap();
+
+    let mut output = Vec::new();
+    loop {
+        let exited = cpm.trap(&mut machine).unwrap();
+        // Print as the exerciser runs, since some take minutes.
+        print!("{}", String::from_utf8_lossy(&machine.stdout));
+        output.append(&mut machine.stdout);
+        if exited {
+            break;
+        }
+
+        machine.run_cycle();
+        assert_eq!(
+            machine.state(),
+            MachineState::Running,
+            "{} should exit to CP/M. Output:\n{}",
+            name,
+            String::from_utf8_lossy(&output)
+        );
+    }
+    println!();
+
+    Some(String::from_utf8_lossy(&output).into_owned())
+}
+
+#[test]
+fn test_8080pre() {
+    let Some(output) = run_exerciser("8080PRE.COM") else {
+        return;
+    };
+    assert!(output.contains("8080 Preliminary tests complete"), "{}", output);
+}
+
+#[test]
+fn test_tst8080() {
+    let Some(output) = run_exerciser("TST8080.COM") else {
+        return;
+    };
+    assert!(output.contains("CPU IS OPERATIONAL"), "{}", output);
+}
+
+#[test]
+fn test_cputest() {
+    let Some(output) = run_exerciser("CPUTEST.COM") else {
+        return;
+    };
+    assert!(output.contains("CPU TESTS OK"), "{}", output);
+}
+
+// Takes several minutes, best run with `--release`.
+#[test]
+fn test_8080exm() {
+    let Some(output) = run_exerciser("8080EXM.COM") else {
+        return;
+    };
+    assert!(!output.contains("ERROR"), "{}", output);
+    assert!(output.contains("Tests complete"), "{}", output);
+}
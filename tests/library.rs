@@ -0,0 +1,111 @@
+//! Uses the emulator through the public library API only, the way an embedder would.
+
+use rsoderh_jonsh_leben_emulator::{
+    assembler,
+    coding::{self, reader::Reader},
+    instruction::{Cpu, Instruction, Register},
+    machine::{HaltReason, Machine, MachineState},
+};
+
+#[test]
+fn test_assemble_and_run() {
+    let source = b"
+            ORG 100H
+            MVI A, 48H      ; 'H'
+            OUT 0
+            MVI A, 49H      ; 'I'
+            OUT 0
+            HLT
+            END
+    ";
+
+    let program = assembler::assemble(source, Cpu::Intel8080).unwrap();
+    let mut machine = Machine::with_program(program.origin, &program.bytes).unwrap();
+    assert_eq!(machine.run(1000), MachineState::Halted(HaltReason::HaltInstruction));
+    assert_eq!(machine.stdout, b"HI");
+}
+
+#[test]
+fn test_assemble_file_with_include() {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/include.8080");
+
+    let program = assembler::assemble_file(&path, Cpu::Intel8080).unwrap();
+    let mut machine = Machine::with_program(program.origin, &program.bytes).unwrap();
+    assert_eq!(machine.run(1000), MachineState::Halted(HaltReason::HaltInstruction));
+    assert_eq!(machine.stdout, b"Hello, World!");
+}
+
+#[test]
+fn test_include_errors() {
+    let directory = std::env::temp_dir().join(format!("include-test-{}", std::process::id()));
+    std::fs::create_dir_all(directory.join("lib")).unwrap();
+    let main = directory.join("main.8080");
+    std::fs::write(&main, b"
+DEBUG   EQU 0
+        IF DEBUG
+        INCLUDE 'lib/missing.8080'
+        ENDIF
+        INCLUDE 'lib/math.8080'
+        END
+").unwrap();
+    std::fs::write(directory.join("lib/math.8080"), b"        NOP\n        MOV A,\n").unwrap();
+
+    // Files in blocks which aren't assembled aren't included, and errors are reported in the file
+    // they're in.
+    let error = assembler::assemble_file(&main, Cpu::Intel8080).unwrap_err();
+    let position = error.position.unwrap();
+    assert_eq!(position.file, Some(directory.join("lib/math.8080")));
+    assert_eq!((position.line, position.column), (2, 9));
+
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn test_step_budget() {
+    let source = b"
+    LOOP:   JMP LOOP
+            END
+    ";
+
+    let program = assembler::assemble(source, Cpu::Intel8080).unwrap();
+    let mut machine = Machine::with_program(program.origin, &program.bytes).unwrap();
+    assert_eq!(machine.run(1000), MachineState::Running);
+    assert_eq!(machine.steps(), 1000);
+}
+
+#[test]
+fn test_assembly_error() {
+    let source = b"
+            MOV A, B
+            JMP NOWHR
+            END
+    ";
+
+    let error = assembler::assemble(source, Cpu::Intel8080).unwrap_err();
+    assert_eq!(error.position.unwrap().line, 3);
+    assert_eq!(error.message, "Unknown label");
+}
+
+#[test]
+fn test_encode_decode() {
+    let instruction = Instruction::Mvi(Register::B, 0x42);
+    let mut bytes = Vec::new();
+    coding::encode(&mut bytes, instruction).unwrap();
+    assert_eq!(bytes, [0x06, 0x42]);
+
+    let mut reader = Reader::new(&bytes);
+    let (decoded, opcode) = coding::decode(&mut reader, Cpu::Intel8080).unwrap();
+    assert_eq!(decoded, instruction);
+    assert_eq!(opcode.alias_of, None);
+    assert!(reader.at_end());
+
+    // An undocumented opcode reports the documented opcode it's an alias of.
+    let (decoded, opcode) = coding::decode(&mut Reader::new(&[0xCB, 0x34, 0x12]), Cpu::Intel8080).unwrap();
+    assert_eq!(decoded, Instruction::Jmp(0x1234));
+    assert_eq!(opcode.alias_of, Some(0xC3));
+
+    // The 8085 uses the opcode for an instruction of its own.
+    let (decoded, opcode) = coding::decode(&mut Reader::new(&[0xCB]), Cpu::Intel8085).unwrap();
+    assert_eq!(decoded, Instruction::Rstv);
+    assert_eq!(opcode.alias_of, None);
+}